@@ -0,0 +1,170 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2018-2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Exception handling.
+//!
+//! The vector table lives in `exception.s`. Every entry saves the interrupted context on the stack
+//! and calls one of the handlers below. IRQs taken from the kernel itself are passed on to
+//! [crate::interrupt::handle_irq]; everything else is unexpected and ends in a panic.
+
+use crate::error;
+use crate::fmt::Hex;
+use core::cell::UnsafeCell;
+use cortex_a::{asm::barrier, registers::*};
+use tock_registers::interfaces::{Readable, Writeable};
+
+// Assembly counterpart to this file.
+global_asm!(include_str!("exception.s"));
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The context that's saved on the stack when an exception is taken.
+#[repr(C)]
+#[allow(dead_code)] // the layout has to match exception.s, even if not every field is read
+struct ExceptionContext {
+    /// General Purpose Registers x0-x29
+    gpr: [u64; 30],
+    /// The link register, aka x30
+    lr: u64,
+    /// Exception link register. The program counter at the time the exception happened.
+    elr_el1: u64,
+    /// Saved program status
+    spsr_el1: u64,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Log what we know about an exception we can't handle and give up.
+fn unhandled_exception(kind: &str, e: &ExceptionContext) -> ! {
+    error!(
+        "Unhandled exception: {}, ESR_EL1 {}, FAR_EL1 {}, ELR_EL1 {}, SPSR_EL1 {}",
+        kind,
+        Hex(ESR_EL1.get()),
+        Hex(FAR_EL1.get()),
+        Hex(e.elr_el1),
+        Hex(e.spsr_el1)
+    );
+    panic!("Unhandled CPU exception")
+}
+
+//------------------------------------------------------------------------------
+// Current, EL0
+//------------------------------------------------------------------------------
+
+#[no_mangle]
+unsafe extern "C" fn current_el0_synchronous(e: &mut ExceptionContext) {
+    unhandled_exception("synchronous, current EL with SP_EL0", e)
+}
+
+#[no_mangle]
+unsafe extern "C" fn current_el0_irq(e: &mut ExceptionContext) {
+    unhandled_exception("IRQ, current EL with SP_EL0", e)
+}
+
+#[no_mangle]
+unsafe extern "C" fn current_el0_fiq(e: &mut ExceptionContext) {
+    unhandled_exception("FIQ, current EL with SP_EL0", e)
+}
+
+#[no_mangle]
+unsafe extern "C" fn current_el0_serror(e: &mut ExceptionContext) {
+    unhandled_exception("SError, current EL with SP_EL0", e)
+}
+
+//------------------------------------------------------------------------------
+// Current, ELx
+//------------------------------------------------------------------------------
+
+#[no_mangle]
+unsafe extern "C" fn current_elx_synchronous(e: &mut ExceptionContext) {
+    unhandled_exception("synchronous, current EL with SP_ELx", e)
+}
+
+#[no_mangle]
+unsafe extern "C" fn current_elx_irq(_e: &mut ExceptionContext) {
+    crate::interrupt::handle_irq()
+}
+
+#[no_mangle]
+unsafe extern "C" fn current_elx_fiq(e: &mut ExceptionContext) {
+    unhandled_exception("FIQ, current EL with SP_ELx", e)
+}
+
+#[no_mangle]
+unsafe extern "C" fn current_elx_serror(e: &mut ExceptionContext) {
+    unhandled_exception("SError, current EL with SP_ELx", e)
+}
+
+//------------------------------------------------------------------------------
+// Lower, AArch64
+//------------------------------------------------------------------------------
+
+#[no_mangle]
+unsafe extern "C" fn lower_aarch64_synchronous(e: &mut ExceptionContext) {
+    unhandled_exception("synchronous, lower EL in AArch64", e)
+}
+
+#[no_mangle]
+unsafe extern "C" fn lower_aarch64_irq(e: &mut ExceptionContext) {
+    unhandled_exception("IRQ, lower EL in AArch64", e)
+}
+
+#[no_mangle]
+unsafe extern "C" fn lower_aarch64_fiq(e: &mut ExceptionContext) {
+    unhandled_exception("FIQ, lower EL in AArch64", e)
+}
+
+#[no_mangle]
+unsafe extern "C" fn lower_aarch64_serror(e: &mut ExceptionContext) {
+    unhandled_exception("SError, lower EL in AArch64", e)
+}
+
+//------------------------------------------------------------------------------
+// Lower, AArch32
+//------------------------------------------------------------------------------
+
+#[no_mangle]
+unsafe extern "C" fn lower_aarch32_synchronous(e: &mut ExceptionContext) {
+    unhandled_exception("synchronous, lower EL in AArch32", e)
+}
+
+#[no_mangle]
+unsafe extern "C" fn lower_aarch32_irq(e: &mut ExceptionContext) {
+    unhandled_exception("IRQ, lower EL in AArch32", e)
+}
+
+#[no_mangle]
+unsafe extern "C" fn lower_aarch32_fiq(e: &mut ExceptionContext) {
+    unhandled_exception("FIQ, lower EL in AArch32", e)
+}
+
+#[no_mangle]
+unsafe extern "C" fn lower_aarch32_serror(e: &mut ExceptionContext) {
+    unhandled_exception("SError, lower EL in AArch32", e)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Install the exception vector table.
+///
+/// # Safety
+///
+/// Must be called while running at EL1, before any exception can be taken.
+pub unsafe fn init() {
+    extern "Rust" {
+        #[allow(non_upper_case_globals)]
+        static __exception_vector_start: UnsafeCell<()>;
+    }
+
+    VBAR_EL1.set(__exception_vector_start.get() as u64);
+
+    // Force VBAR update to complete before next instruction.
+    barrier::isb(barrier::SY);
+}
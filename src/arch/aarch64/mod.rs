@@ -2,6 +2,7 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
+pub mod exception;
 pub mod time;
 
 pub mod asm {
@@ -27,6 +28,36 @@ pub mod cpu {
 
     #[inline(always)]
     pub fn exception_level() -> u64 {
-        todo!()
+        use cortex_a::registers::*;
+        use tock_registers::interfaces::Readable;
+        CurrentEL.read(CurrentEL::EL)
+    }
+}
+
+pub mod irq {
+    use cortex_a::registers::*;
+    use tock_registers::interfaces::Readable;
+
+    /// Check whether IRQs are unmasked on the current core.
+    #[inline(always)]
+    pub fn are_enabled() -> bool {
+        DAIF.matches_all(DAIF::I::Unmasked)
+    }
+
+    /// Unmask IRQs on the current core.
+    ///
+    /// # Safety
+    ///
+    /// Interrupt handlers may run as soon as this returns, so the exception vectors and anything
+    /// the handlers rely on must already be set up.
+    #[inline(always)]
+    pub unsafe fn enable() {
+        asm!("msr DAIFClr, #2", options(nostack, preserves_flags))
+    }
+
+    /// Mask IRQs on the current core.
+    #[inline(always)]
+    pub fn disable() {
+        unsafe { asm!("msr DAIFSet, #2", options(nostack, preserves_flags)) }
     }
 }
@@ -11,16 +11,17 @@
 //! Together these can be used to measure relative time in real units.
 //!
 //! Additionally, there exists:
-//! - CNTP_TVAL_EL0: set a hardware countdown using CNTPCT_EL0,
+//! - CNTP_CVAL_EL0: set a hardware countdown using CNTPCT_EL0,
 //! - CNTP_CTL_EL0: control the behavior of the countdown timer (interrupts, enable/disable, etc)
 //!
-//! Which are used for creating countdown timers.
+//! Which are used for creating countdown timers. Those raise an interrupt, so they're left to the
+//! [clock event driver](crate::driver::arm_timer), and this module only reads the counter.
 
 use crate::{time::{self, NS_PER_SEC}, warn};
 use core::convert::TryInto;
 use core::time::Duration;
 use cortex_a::{asm::barrier, registers::*};
-use tock_registers::interfaces::{Readable, ReadWriteable, Writeable};
+use tock_registers::interfaces::Readable;
 
 //--------------------------------------------------------------------------------------------------
 // Private Definitions
@@ -35,6 +36,9 @@ struct GenericTimer;
 
 static SIMPLE_TIMER: GenericTimer = GenericTimer;
 
+/// The generic timer runs off the core's clock, and is read without going over a bus.
+const RATING: u32 = 400;
+
 //--------------------------------------------------------------------------------------------------
 // Private Code
 //--------------------------------------------------------------------------------------------------
@@ -57,6 +61,11 @@ pub fn simple_timer() -> &'static impl time::SimpleTimer {
     &SIMPLE_TIMER
 }
 
+/// Return the timer as a clock source, which is what the kernel uses before it picks one.
+pub fn clock_source() -> &'static dyn time::ClockSource {
+    &SIMPLE_TIMER
+}
+
 //------------------------------------------------------------------------------
 // OS Interface Code
 //------------------------------------------------------------------------------
@@ -67,49 +76,47 @@ impl time::SimpleTimer for GenericTimer {
     }
 
     fn uptime(&self) -> Duration {
-        let current_count: u64 = self.read_cntpct() * NS_PER_SEC;
+        let current_count = self.read_cntpct();
         let frq = CNTFRQ_EL0.get();
 
-        Duration::from_nanos(current_count / frq)
+        // split into seconds and the rest, so the count doesn't overflow once it's scaled
+        let secs = current_count / frq;
+        let nanos = (current_count % frq) * NS_PER_SEC / frq;
+        Duration::new(secs, nanos as u32)
     }
 
+    /// Busy-wait on the counter. This leaves the timer's compare registers alone, since they may
+    /// be programmed for a clock event.
     fn spin_for(&self, duration: Duration) {
         // Instantly return on zero.
         if duration.as_nanos() == 0 {
             return
         }
 
-        // Calculate the register compare value.
+        // Calculate how many ticks to wait for.
         let frq = CNTFRQ_EL0.get();
-        let nanos = duration.as_nanos().try_into().ok();
-        let x = match nanos.and_then(|nanos| frq.checked_mul(nanos)) {
-            Some(val) => val,
-            None => {
+        let ticks = duration.as_nanos() * u128::from(frq) / u128::from(NS_PER_SEC);
+        let ticks: u64 = match ticks.try_into() {
+            Ok(ticks) => ticks,
+            Err(_) => {
                 warn!("Spin duration of {}ns too long, skipping", duration.as_nanos());
                 return
             }
         };
-        let tval = x / NS_PER_SEC;
 
-        // Check if it is within supported bounds.
-        if tval == 0 {
-            warn!("Spin duration smaller than architecturally supported, skipping");
-            return
-        } else if tval > u32::max_value().into() {
-            warn!("Spin duration bigger than architecturally supported, skipping");
-            return
+        let deadline = self.read_cntpct().saturating_add(ticks);
+        while self.read_cntpct() < deadline {
+            core::hint::spin_loop();
         }
+    }
+}
 
-        // Set the compare value register.
-        CNTP_TVAL_EL0.set(tval);
-
-        // Kick off the counting.                       // Disable timer interrupt.
-        CNTP_CTL_EL0.modify(CNTP_CTL_EL0::ENABLE::SET + CNTP_CTL_EL0::IMASK::SET);
-
-        // ISTATUS will be '1' when cval ticks have passed. Busy-check it.
-        while !CNTP_CTL_EL0.matches_all(CNTP_CTL_EL0::ISTATUS::SET) {}
+impl time::ClockSource for GenericTimer {
+    fn name(&self) -> &'static str {
+        "ARM generic timer"
+    }
 
-        // Disable counting again.
-        CNTP_CTL_EL0.modify(CNTP_CTL_EL0::ENABLE::CLEAR);
+    fn rating(&self) -> u32 {
+        RATING
     }
 }
@@ -0,0 +1,210 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Exception handling.
+//!
+//! The interrupt descriptor table sends the CPU exceptions (vectors 0 to 31) to handlers that log
+//! what happened and panic, since the kernel doesn't expect any of them. Every vector from
+//! [IRQ_VECTOR_BASE] up is where the interrupt controller delivers IRQs, which are passed on to
+//! [crate::interrupt::handle_irq].
+
+use crate::error;
+use crate::fmt::Hex;
+use core::mem::size_of;
+use x86::dtables::{lidt, DescriptorTablePointer};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The vector that IRQ 0 is delivered on. Interrupt controllers are programmed to match.
+pub const IRQ_VECTOR_BASE: u8 = 32;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const VECTOR_COUNT: usize = 256;
+
+/// What the CPU pushes on the stack when it takes an interrupt.
+#[repr(C)]
+#[allow(dead_code)] // the layout is set by the CPU, even if not every field is read
+struct InterruptStackFrame {
+    instruction_pointer: u64,
+    code_segment: u64,
+    cpu_flags: u64,
+    stack_pointer: u64,
+    stack_segment: u64,
+}
+
+/// An entry in the interrupt descriptor table.
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct Gate {
+    offset_low: u16,
+    selector: u16,
+    options: u16,
+    offset_middle: u16,
+    offset_high: u32,
+    reserved: u32,
+}
+
+/// A 64-bit interrupt gate, which masks interrupts while the handler runs
+const GATE_TYPE_INTERRUPT: u16 = 0xe << 8;
+const GATE_PRESENT: u16 = 1 << 15;
+
+type Handler = extern "x86-interrupt" fn(InterruptStackFrame);
+type HandlerWithErrorCode = extern "x86-interrupt" fn(InterruptStackFrame, u64);
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static mut IDT: [Gate; VECTOR_COUNT] = [Gate::MISSING; VECTOR_COUNT];
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Gate {
+    /// A gate that isn't there, so using it raises a general protection fault.
+    const MISSING: Self = Self {
+        offset_low: 0,
+        selector: 0,
+        options: 0,
+        offset_middle: 0,
+        offset_high: 0,
+        reserved: 0,
+    };
+
+    fn new(handler: usize) -> Self {
+        Self {
+            offset_low: handler as u16,
+            selector: x86::segmentation::cs().bits(),
+            options: GATE_PRESENT | GATE_TYPE_INTERRUPT,
+            offset_middle: (handler >> 16) as u16,
+            offset_high: (handler >> 32) as u32,
+            reserved: 0,
+        }
+    }
+}
+
+/// Log what we know about an exception we can't handle and give up.
+fn unhandled_exception(kind: &str, frame: &InterruptStackFrame, error_code: Option<u64>) -> ! {
+    match error_code {
+        Some(error_code) => error!("Unhandled exception: {}, error code {}", kind, Hex(error_code)),
+        None => error!("Unhandled exception: {}", kind),
+    }
+    error!(
+        "RIP {}, CS {}, RFLAGS {}, RSP {}, CR2 {}",
+        Hex(frame.instruction_pointer),
+        Hex(frame.code_segment),
+        Hex(frame.cpu_flags),
+        Hex(frame.stack_pointer),
+        // SAFETY: reading CR2 has no side effects
+        Hex(unsafe { x86::controlregs::cr2() })
+    );
+    panic!("Unhandled CPU exception")
+}
+
+macro_rules! exception_handler {
+    ($name:ident, $kind:literal) => {
+        extern "x86-interrupt" fn $name(frame: InterruptStackFrame) {
+            unhandled_exception($kind, &frame, None)
+        }
+    };
+    ($name:ident, $kind:literal, error_code) => {
+        extern "x86-interrupt" fn $name(frame: InterruptStackFrame, error_code: u64) {
+            unhandled_exception($kind, &frame, Some(error_code))
+        }
+    };
+}
+
+exception_handler!(divide_error, "divide error");
+exception_handler!(debug, "debug");
+exception_handler!(non_maskable_interrupt, "non-maskable interrupt");
+exception_handler!(breakpoint, "breakpoint");
+exception_handler!(overflow, "overflow");
+exception_handler!(bound_range_exceeded, "bound range exceeded");
+exception_handler!(invalid_opcode, "invalid opcode");
+exception_handler!(device_not_available, "device not available");
+exception_handler!(double_fault, "double fault", error_code);
+exception_handler!(invalid_tss, "invalid TSS", error_code);
+exception_handler!(segment_not_present, "segment not present", error_code);
+exception_handler!(stack_segment_fault, "stack-segment fault", error_code);
+exception_handler!(general_protection_fault, "general protection fault", error_code);
+exception_handler!(page_fault, "page fault", error_code);
+exception_handler!(x87_floating_point, "x87 floating-point exception");
+exception_handler!(alignment_check, "alignment check", error_code);
+exception_handler!(machine_check, "machine check");
+exception_handler!(simd_floating_point, "SIMD floating-point exception");
+exception_handler!(virtualization, "virtualization exception");
+
+extern "x86-interrupt" fn irq(_frame: InterruptStackFrame) {
+    crate::interrupt::handle_irq()
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Fill in the interrupt descriptor table and load it.
+///
+/// # Safety
+///
+/// Must be called once, before interrupts are enabled.
+pub unsafe fn init() {
+    let exceptions: [(usize, Handler); 12] = [
+        (0, divide_error),
+        (1, debug),
+        (2, non_maskable_interrupt),
+        (3, breakpoint),
+        (4, overflow),
+        (5, bound_range_exceeded),
+        (6, invalid_opcode),
+        (7, device_not_available),
+        (16, x87_floating_point),
+        (18, machine_check),
+        (19, simd_floating_point),
+        (20, virtualization),
+    ];
+    let exceptions_with_error_code: [(usize, HandlerWithErrorCode); 7] = [
+        (8, double_fault),
+        (10, invalid_tss),
+        (11, segment_not_present),
+        (12, stack_segment_fault),
+        (13, general_protection_fault),
+        (14, page_fault),
+        (17, alignment_check),
+    ];
+
+    for &(vector, handler) in exceptions.iter() {
+        IDT[vector] = Gate::new(handler as usize);
+    }
+    for &(vector, handler) in exceptions_with_error_code.iter() {
+        IDT[vector] = Gate::new(handler as usize);
+    }
+    for gate in IDT[IRQ_VECTOR_BASE as usize..].iter_mut() {
+        *gate = Gate::new(irq as Handler as usize);
+    }
+
+    lidt(&DescriptorTablePointer {
+        limit: (size_of::<[Gate; VECTOR_COUNT]>() - 1) as u16,
+        base: IDT.as_ptr(),
+    });
+}
+
+/// Reset the CPU by raising an exception with no interrupt descriptor table to handle it. The
+/// double fault that follows can't be handled either, which makes the CPU give up and reset.
+///
+/// # Safety
+///
+/// Everything stops immediately, without any of the state being saved.
+pub unsafe fn triple_fault() -> ! {
+    lidt(&DescriptorTablePointer::<Gate> {
+        limit: 0,
+        base: core::ptr::null(),
+    });
+    asm!("int3", options(noreturn))
+}
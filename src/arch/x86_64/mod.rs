@@ -2,6 +2,7 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
+pub mod exception;
 pub mod time;
 
 pub mod asm {
@@ -25,6 +26,36 @@ pub mod asm {
 pub mod cpu {
     #[inline(always)]
     pub fn core_id() -> u64 {
-        todo!()
+        // the initial APIC id is reported in the top byte of EBX
+        // SAFETY: cpuid is available on every x86_64 processor
+        let info = unsafe { core::arch::x86_64::__cpuid(1) };
+        (info.ebx >> 24) as u64
+    }
+}
+
+pub mod irq {
+    use x86::bits64::rflags::{self, RFlags};
+
+    /// Check whether interrupts are enabled on the current core.
+    #[inline(always)]
+    pub fn are_enabled() -> bool {
+        rflags::read().contains(RFlags::FLAGS_IF)
+    }
+
+    /// Enable interrupts on the current core.
+    ///
+    /// # Safety
+    ///
+    /// Interrupt handlers may run as soon as this returns, so the interrupt descriptor table and
+    /// anything the handlers rely on must already be set up.
+    #[inline(always)]
+    pub unsafe fn enable() {
+        x86::irq::enable()
+    }
+
+    /// Disable interrupts on the current core.
+    #[inline(always)]
+    pub fn disable() {
+        unsafe { x86::irq::disable() }
     }
 }
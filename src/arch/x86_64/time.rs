@@ -2,11 +2,44 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
+//! The time stamp counter, which counts at a rate that nothing tells us directly.
+//!
+//! Until [calibrate] measures that rate against another timer, a tick is taken to be a
+//! nanosecond. Uptime carries on from wherever that left it, so it never goes backwards.
+
+use core::convert::TryFrom;
 use core::time::Duration;
-use crate::time::SimpleTimer;
+use crate::{driver, info};
+use crate::sync::OnceCell;
+use crate::time::{ClockSource, SimpleTimer, NS_PER_SEC};
 
 pub struct GenericTimer;
 
+/// What [calibrate] measured, and where the uncalibrated uptime left off.
+#[derive(Clone, Copy)]
+struct Calibration {
+    /// Ticks per second
+    frequency: u64,
+    /// The counter when the calibration was done
+    base_count: u64,
+    /// The uptime at `base_count`
+    base_uptime: Duration,
+}
+
+static SIMPLE_TIMER: GenericTimer = GenericTimer;
+
+static CALIBRATION: OnceCell<Calibration> = OnceCell::new();
+
+/// The TSC is read without going over a bus, which is better than any other timer once we know
+/// how fast it counts.
+const RATING: u32 = 300;
+
+/// Until then, it's only usable, and any calibrated clock source is preferred.
+const UNCALIBRATED_RATING: u32 = 150;
+
+/// How long [calibrate] measures for. Longer is more precise, but delays the boot.
+const CALIBRATION_TIME: Duration = Duration::from_millis(50);
+
 fn read_instruction_count() -> u64 {
     // SAFETY: it's alright that this acts as an instruction barrier
     unsafe { ::x86::time::rdtscp() }
@@ -16,18 +49,86 @@ pub fn simple_timer() -> impl SimpleTimer {
     GenericTimer
 }
 
+/// Return the timer as a clock source, which is what the kernel uses before it picks one.
+pub fn clock_source() -> &'static dyn ClockSource {
+    &SIMPLE_TIMER
+}
+
+/// Measure how fast the counter runs, using `wait` to busy-wait for a given time with a timer
+/// whose rate is known. Only the first successful call has any effect.
+///
+/// Fails with whatever `wait` fails with, in which case the counter stays uncalibrated.
+pub fn calibrate(
+    wait: impl FnOnce(Duration) -> Result<(), driver::Error>,
+) -> Result<(), driver::Error> {
+    if CALIBRATION.is_initialized() {
+        return Ok(())
+    }
+
+    let start = read_instruction_count();
+    wait(CALIBRATION_TIME)?;
+    let end = read_instruction_count();
+
+    let ticks = u128::from(end.wrapping_sub(start));
+    let frequency = ticks * u128::from(NS_PER_SEC) / CALIBRATION_TIME.as_nanos();
+    let frequency = match u64::try_from(frequency) {
+        Ok(frequency) if frequency > 0 => frequency,
+        _ => {
+            return Err(driver::Error::Io {
+                device: "TSC",
+                reason: "the counter's rate is out of range",
+            })
+        }
+    };
+    // the uptime at the end of the measurement, as it was counted until now
+    let base_uptime = Duration::from_nanos(end);
+    CALIBRATION.get_or_init(|| Calibration { frequency, base_count: end, base_uptime });
+    info!("The TSC runs at {} kHz", frequency / 1000);
+    Ok(())
+}
+
 impl SimpleTimer for GenericTimer {
     fn resolution(&self) -> Duration {
         Duration::from_nanos(10)
     }
 
     fn uptime(&self) -> Duration {
-        Duration::from_nanos(read_instruction_count())
+        let count = read_instruction_count();
+        match CALIBRATION.get() {
+            Some(calibration) => {
+                let ticks = u128::from(count.wrapping_sub(calibration.base_count));
+                let nanos = ticks * u128::from(NS_PER_SEC) / u128::from(calibration.frequency);
+                let nanos = u64::try_from(nanos).unwrap_or(u64::MAX);
+                calibration.base_uptime + Duration::from_nanos(nanos)
+            }
+            None => Duration::from_nanos(count),
+        }
     }
 
     fn spin_for(&self, duration: Duration) {
-        for _ in 0..duration.as_nanos() {
-            crate::arch::asm::nop()
+        if CALIBRATION.is_initialized() {
+            let deadline = self.uptime() + duration;
+            while self.uptime() < deadline {
+                core::hint::spin_loop();
+            }
+        } else {
+            for _ in 0..duration.as_nanos() {
+                crate::arch::asm::nop()
+            }
+        }
+    }
+}
+
+impl ClockSource for GenericTimer {
+    fn name(&self) -> &'static str {
+        "TSC"
+    }
+
+    fn rating(&self) -> u32 {
+        if CALIBRATION.is_initialized() {
+            RATING
+        } else {
+            UNCALIBRATED_RATING
         }
     }
 }
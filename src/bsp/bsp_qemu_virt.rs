@@ -0,0 +1,297 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! QEMU's generic aarch64 `virt` machine.
+//!
+//! Unlike the Raspberry Pi machines, this board is fully specified by QEMU and doesn't emulate any
+//! firmware, which makes it a good target for automated tests. QEMU always passes a device tree,
+//! which is where the devices are looked up; the memory map is only a fallback.
+//!
+//! The machine can have either a GICv2 or a GICv3, picked with `-M virt,gic-version=2` or `3`. The
+//! device tree says which one it is. Without a device tree, the GICv2 is assumed, since that's
+//! QEMU's default.
+//!
+//! The other cores, from `-smp`, are held off by QEMU until they're started through PSCI. That's
+//! done for every core in the device tree, but there's nothing for them to do yet, so they park
+//! themselves once they're up.
+
+use crate::driver::arm_timer::ArmTimer;
+use crate::driver::gicv2::Gicv2;
+use crate::driver::gicv3::Gicv3;
+use crate::driver::input::CharInput;
+use crate::driver::pl011::PL011Uart;
+use crate::driver::psci::{Conduit, Psci};
+use crate::driver::registry::Registry;
+use crate::driver::uart::UartConfig;
+use crate::driver::WriteError;
+use crate::fdt::{self, Fdt};
+use crate::fmt::Hex;
+use crate::interrupt::{InterruptController, IrqNumber};
+use crate::rand::EntropySource;
+use crate::runtime_init;
+use crate::sync::{IrqSafeSpinMutex, IrqSafeSpinMutexMut, OnceCell, SpinMutex, SpinMutexMut};
+use crate::time::{ClockEvent, ClockSource, SimpleTimer};
+use crate::watchdog::Watchdog;
+use crate::{info, warn};
+use core::time::Duration;
+use cortex_a::registers::MPIDR_EL1;
+use tock_registers::interfaces::Readable;
+
+/// Where the devices are when there's no device tree to say otherwise.
+pub mod mmap {
+    pub const GICD_BASE: usize = 0x0800_0000;
+    pub const GICC_BASE: usize = 0x0801_0000;
+    pub const GICR_BASE: usize = 0x080a_0000;
+    pub const PL011_UART_BASE: usize = 0x0900_0000;
+}
+
+/// Interrupt line numbers, as seen by either GIC, for when there's no device tree.
+pub mod irq_map {
+    use crate::interrupt::IrqNumber;
+
+    /// The generic timer's non-secure physical timer, private peripheral interrupt 14
+    pub const ARM_TIMER: IrqNumber = 30;
+}
+
+pub mod config {
+    /// Baud rate for the console UART. QEMU doesn't care, but the divisors still have to be valid.
+    pub const UART_BAUD: u32 = 115_200;
+
+    /// Frequency of the PL011 reference clock in Hz, as given by the `apb-pclk` node in QEMU's
+    /// device tree.
+    pub const UART_CLOCK_HZ: u32 = 24_000_000;
+
+    /// How PSCI is called when the kernel is started at EL1, unless the device tree says otherwise.
+    pub const PSCI_CONDUIT: super::Conduit = super::Conduit::Hvc;
+
+    /// The most cores that are started, the boot core included. A GICv2 can't handle any more.
+    pub const MAX_CORES: usize = 8;
+
+    /// Size of the stack of each core besides the boot core.
+    pub const SECONDARY_STACK_SIZE: usize = 16 * 1024;
+
+    /// Nobody is watching an emulator that's running tests, so let it exit after a panic.
+    pub const PANIC_ACTION: crate::power::PanicAction = crate::power::PanicAction::PowerOff;
+}
+
+/// The affinity fields of `MPIDR_EL1`, which is how PSCI and the device tree name a core.
+const MPIDR_AFFINITY_MASK: u64 = 0xff_00ff_ffff;
+
+/// How long the other cores get to come up once they're started.
+const SECONDARY_START_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The early console writes to the same UART as [`stdout`].
+pub const EARLY_CONSOLE_SHARES_STDOUT: bool = true;
+
+/// The UART is usually connected to a terminal, which understands ANSI escape sequences.
+pub const CONSOLE_SUPPORTS_ANSI: bool = true;
+
+/// Polled console that works before the drivers are initialized.
+///
+/// QEMU leaves the PL011 disabled, so this can't write anything until its driver has enabled it.
+/// The log keeps whatever was written before then.
+pub fn early_console() -> crate::driver::pl011::PL011Polled {
+    // SAFETY: the address comes from the memory map for this board
+    unsafe { crate::driver::pl011::PL011Polled::new(mmap::PL011_UART_BASE) }
+}
+
+/// The PSCI driver, or one that uses the default conduit if it isn't available, e.g. because it
+/// was locked by the code that panicked.
+fn psci() -> Psci {
+    crate::DRIVERS
+        .try_get()
+        .and_then(|drivers| drivers.get::<SpinMutex<Psci>>())
+        .and_then(|psci| psci.try_with_lock(|psci| *psci))
+        .unwrap_or_else(|| Psci::new(config::PSCI_CONDUIT))
+}
+
+/// Reset the machine, which makes QEMU start over. Only returns on failure.
+pub fn reboot() {
+    psci().system_reset()
+}
+
+/// Turn off the machine, which makes QEMU exit. Only returns on failure.
+pub fn power_off() {
+    psci().system_off()
+}
+
+/// Find out from the device tree whether PSCI is called with `hvc` or `smc`.
+fn psci_conduit(fdt: Option<&Fdt<'_>>) -> Conduit {
+    let method = fdt
+        .and_then(|fdt| fdt.find_driver::<Psci>())
+        .and_then(|node| node.property("method"))
+        .and_then(|method| method.as_str());
+    match method {
+        Some("smc") => Conduit::Smc,
+        Some("hvc") => Conduit::Hvc,
+        _ => config::PSCI_CONDUIT,
+    }
+}
+
+/// The line of the generic timer's non-secure physical timer, which is the second of its
+/// interrupts in the device tree.
+fn arm_timer_irq(
+    fdt: Option<&Fdt<'_>>,
+    interrupt_controller: &dyn InterruptController,
+) -> IrqNumber {
+    fdt.and_then(|fdt| fdt.find_driver::<ArmTimer>())
+        .and_then(|node| node.irqs::<2>(interrupt_controller))
+        .map_or(irq_map::ARM_TIMER, |irqs| irqs[1])
+}
+
+/// A stack for a core besides the boot core, which uses the one from the linker script.
+#[derive(Clone, Copy)]
+#[repr(C, align(16))]
+struct Stack([u8; config::SECONDARY_STACK_SIZE]);
+
+/// Stacks for the other cores. Each one is handed to a core as it's started.
+static mut SECONDARY_STACKS: [Stack; config::MAX_CORES - 1] =
+    [Stack([0; config::SECONDARY_STACK_SIZE]); config::MAX_CORES - 1];
+
+/// Start every core besides the boot core that the device tree lists, giving each a stack.
+///
+/// # Safety
+///
+/// Must be called only once, since the stacks are handed out here.
+unsafe fn start_secondary_cores(fdt: Option<&Fdt<'_>>, psci: &Psci) {
+    let fdt = match fdt {
+        Some(fdt) => fdt,
+        None => return,
+    };
+
+    let boot_core = MPIDR_EL1.get() & MPIDR_AFFINITY_MASK;
+    let cores = fdt
+        .nodes()
+        .filter(|node| node.property("device_type").and_then(|t| t.as_str()) == Some("cpu"))
+        .filter(|node| node.is_enabled())
+        .filter_map(|node| node.property("reg").and_then(|reg| reg.as_u64()))
+        .filter(|&mpidr| mpidr != boot_core);
+
+    let mut started = 0;
+    for mpidr in cores {
+        let stack = match SECONDARY_STACKS.get_mut(started) {
+            Some(stack) => stack,
+            None => {
+                warn!("Only starting {} cores", config::MAX_CORES);
+                break
+            }
+        };
+        let stack_top = stack.0.as_mut_ptr_range().end as u64;
+        match psci.cpu_on(mpidr, runtime_init::secondary_entry_point(), stack_top) {
+            Ok(()) => started += 1,
+            Err(e) => warn!("Couldn't start the core at {}: {}", Hex(mpidr), e),
+        }
+    }
+    if started == 0 {
+        return
+    }
+
+    let timer = crate::time::arch_timer();
+    let deadline = timer.uptime() + SECONDARY_START_TIMEOUT;
+    while runtime_init::secondary_cores_online() < started && timer.uptime() < deadline {
+        core::hint::spin_loop();
+    }
+    info!("{} of {} other cores are up", runtime_init::secondary_cores_online(), started);
+}
+
+static GICV2: OnceCell<IrqSafeSpinMutex<Gicv2>> = OnceCell::new();
+static GICV3: OnceCell<IrqSafeSpinMutex<Gicv3>> = OnceCell::new();
+static ARM_TIMER: OnceCell<SpinMutex<ArmTimer>> = OnceCell::new();
+static PSCI: OnceCell<SpinMutex<Psci>> = OnceCell::new();
+static UART: OnceCell<SpinMutex<PL011Uart>> = OnceCell::new();
+
+/// Create the drivers for the devices on this board and add them to `registry`.
+///
+/// # Safety
+///
+/// Must be called only once to avoid double-initializing peripherals.
+pub unsafe fn register_drivers(registry: &mut Registry) {
+    let device_tree = fdt::boot();
+    let fdt = device_tree.as_ref();
+    if fdt.is_none() {
+        warn!("No device tree, using the built-in memory map");
+    }
+
+    let psci = Psci::new(psci_conduit(fdt));
+    let uart = PL011Uart::new(
+        fdt::discover::<PL011Uart>(fdt, 0, mmap::PL011_UART_BASE),
+        config::UART_CLOCK_HZ,
+        UartConfig::new(config::UART_BAUD),
+    );
+
+    let (major, minor) = psci.version();
+    info!("PSCI version {}.{}", major, minor);
+    start_secondary_cores(fdt, &psci);
+
+    let arm_timer_irq = if fdt.and_then(|fdt| fdt.find_driver::<Gicv3>()).is_some() {
+        let interrupt_controller = Gicv3::new(
+            fdt::discover::<Gicv3>(fdt, 0, mmap::GICD_BASE),
+            fdt::discover::<Gicv3>(fdt, 1, mmap::GICR_BASE),
+        );
+        let irq = arm_timer_irq(fdt, &interrupt_controller);
+        registry.register(&GICV3, IrqSafeSpinMutex::new(interrupt_controller));
+        irq
+    } else {
+        let interrupt_controller = Gicv2::new(
+            fdt::discover::<Gicv2>(fdt, 0, mmap::GICD_BASE),
+            fdt::discover::<Gicv2>(fdt, 1, mmap::GICC_BASE),
+        );
+        let irq = arm_timer_irq(fdt, &interrupt_controller);
+        registry.register(&GICV2, IrqSafeSpinMutex::new(interrupt_controller));
+        irq
+    };
+    registry.register(&ARM_TIMER, SpinMutex::new(ArmTimer::new(arm_timer_irq)));
+    registry.register(&PSCI, SpinMutex::new(psci));
+    registry.register(&UART, SpinMutex::new(uart));
+}
+
+/// The interrupt controller, once its driver is initialized.
+pub fn interrupt_controller(
+    drivers: &'static Registry,
+) -> Option<IrqSafeSpinMutexMut<'static, dyn InterruptController>> {
+    match drivers.get::<IrqSafeSpinMutex<Gicv3>>() {
+        Some(controller) => Some(controller.borrow()),
+        None => drivers.get::<IrqSafeSpinMutex<Gicv2>>().map(|controller| controller.borrow()),
+    }
+}
+
+/// The console, once its driver is initialized.
+pub fn stdout(
+    drivers: &'static Registry,
+) -> Option<SpinMutexMut<'static, dyn ufmt::uWrite<Error = WriteError>>> {
+    drivers.get::<SpinMutex<PL011Uart>>().map(|uart| uart.borrow())
+}
+
+/// The devices that input is read from, which is just the UART.
+pub fn stdin(
+    drivers: &'static Registry,
+) -> impl Iterator<Item = SpinMutexMut<'static, dyn CharInput>> {
+    drivers.get::<SpinMutex<PL011Uart>>().map(|uart| uart.borrow()).into_iter()
+}
+
+/// The watchdog, which this board doesn't have.
+pub fn watchdog(_drivers: &'static Registry) -> Option<SpinMutexMut<'static, dyn Watchdog>> {
+    None
+}
+
+/// The hardware random number generator, which this board doesn't have.
+pub fn entropy_source(
+    _drivers: &'static Registry,
+) -> Option<SpinMutexMut<'static, dyn EntropySource>> {
+    None
+}
+
+/// Clock sources besides the architecture's timer, which this board doesn't have.
+pub fn clock_sources(
+    _drivers: &'static Registry,
+) -> impl Iterator<Item = &'static dyn ClockSource> {
+    core::iter::empty()
+}
+
+/// The generic timer, once its driver is initialized.
+pub fn clock_events(
+    drivers: &'static Registry,
+) -> impl Iterator<Item = SpinMutexMut<'static, dyn ClockEvent>> {
+    drivers.get::<SpinMutex<ArmTimer>>().map(|timer| timer.borrow()).into_iter()
+}
@@ -2,51 +2,554 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
-use crate::driver::{gpio::Gpio, uart::PL011Uart, traits::Compatible, WriteError};
-use crate::sync::{SpinMutex, SpinMutexMut};
+use crate::driver::arm_timer::ArmTimer;
+#[cfg(feature = "bsp_rpi3")]
+use crate::driver::bcm_intc::BcmInterruptController;
+#[cfg(feature = "bsp_rpi3")]
+use crate::driver::bcm_rng::BcmRng;
+use crate::driver::bcm_timer::{BcmSystemCounter, BcmSystemTimer};
+use crate::driver::bcm_watchdog::BcmWatchdog;
+use crate::driver::fb_console::FramebufferConsole;
+#[cfg(feature = "bsp_rpi4")]
+use crate::driver::gicv2::Gicv2;
+use crate::driver::gpio::{self, Gpio};
+use crate::driver::input::CharInput;
+use crate::driver::mailbox::{Clock, Mailbox};
+use crate::driver::mini_uart::{self, MiniUart};
+use crate::driver::pl011::PL011Uart;
+use crate::driver::registry::Registry;
+#[cfg(feature = "bsp_rpi4")]
+use crate::driver::rng200::Rng200;
+use crate::driver::uart::UartConfig;
+use crate::driver::WriteError;
+use crate::fdt::{self, Fdt};
+use crate::fmt::Hex;
+use crate::interrupt::{InterruptController, IrqNumber};
+use crate::rand::EntropySource;
+use crate::shell::{self, Args, Output};
+use crate::sync::{IrqSafeSpinMutex, IrqSafeSpinMutexMut, OnceCell, SpinMutex, SpinMutexMut};
+use crate::time::{ClockEvent, ClockSource};
+use crate::watchdog::Watchdog;
+use crate::{info, shell_command, warn};
 
+/// Where the devices are when there's no device tree to say otherwise.
 pub mod mmap {
     #[cfg(feature = "bsp_rpi3")]
     pub const MMIO_BASE: usize = 0x3f00_0000;
     #[cfg(feature = "bsp_rpi4")]
     pub const MMIO_BASE: usize = 0xfe00_0000;
 
+    pub const SYSTEM_TIMER_BASE: usize = MMIO_BASE + 0x3000;
+    #[cfg(feature = "bsp_rpi3")]
+    pub const INTERRUPT_CONTROLLER_BASE: usize = MMIO_BASE + 0xb200;
+    pub const MAILBOX_BASE: usize = MMIO_BASE + 0xb880;
+    pub const PM_BASE: usize = MMIO_BASE + 0x10_0000;
+    pub const RNG_BASE: usize = MMIO_BASE + 0x10_4000;
     pub const GPIO_BASE: usize = MMIO_BASE + 0x20_0000;
     pub const PL011_UART_BASE: usize = MMIO_BASE + 0x20_1000;
+    pub const AUX_BASE: usize = MMIO_BASE + 0x21_5000;
     // pub const SPI1_BASE: usize = MMIO_BASE + 0x21_5080;
     // pub const SPI2_BASE: usize = MMIO_BASE + 0x21_50c0;
+
+    /// The ARM local peripherals, which hold the interrupt controller in front of the BCM2835 one.
+    #[cfg(feature = "bsp_rpi3")]
+    pub const LOCAL_BASE: usize = 0x4000_0000;
+
+    /// The GIC-400 sits outside of the main peripheral window, in the ARM local peripherals.
+    #[cfg(feature = "bsp_rpi4")]
+    pub const GIC_BASE: usize = 0xff84_0000;
+    #[cfg(feature = "bsp_rpi4")]
+    pub const GICD_BASE: usize = GIC_BASE + 0x1000;
+    #[cfg(feature = "bsp_rpi4")]
+    pub const GICC_BASE: usize = GIC_BASE + 0x2000;
 }
 
-pub struct DriverManager {
-    gpio: SpinMutex<Gpio>,
-    uart: SpinMutex<PL011Uart>,
+/// Interrupt line numbers, as seen by the interrupt controller, for when there's no device tree.
+pub mod irq_map {
+    use crate::interrupt::IrqNumber;
+
+    /// System timer compare channels 0-3
+    #[cfg(feature = "bsp_rpi3")]
+    pub const SYSTEM_TIMER: [IrqNumber; 4] = [0, 1, 2, 3];
+    /// GPIO banks 0-2
+    #[cfg(feature = "bsp_rpi3")]
+    pub const GPIO: [IrqNumber; 3] = [49, 50, 51];
+    /// The generic timer's non-secure physical timer, which the driver numbers after its own lines
+    #[cfg(feature = "bsp_rpi3")]
+    pub const ARM_TIMER: IrqNumber = crate::driver::bcm_intc::FIRST_LOCAL_TIMER_IRQ + 1;
+
+    /// System timer compare channels 0-3. The GIC sees VideoCore interrupt `n` as shared
+    /// peripheral interrupt `64 + n`, which is interrupt ID `96 + n`.
+    #[cfg(feature = "bsp_rpi4")]
+    pub const SYSTEM_TIMER: [IrqNumber; 4] = [96, 97, 98, 99];
+    /// GPIO banks 0-2, numbered like the system timer's.
+    #[cfg(feature = "bsp_rpi4")]
+    pub const GPIO: [IrqNumber; 3] = [145, 146, 147];
+    /// The generic timer's non-secure physical timer, private peripheral interrupt 14
+    #[cfg(feature = "bsp_rpi4")]
+    pub const ARM_TIMER: IrqNumber = 30;
 }
 
-impl DriverManager {
-    /// Initialize all available drivers
-    ///
-    /// # Safety
+pub mod config {
+    /// Baud rate for the console UART.
+    pub const UART_BAUD: u32 = 921_600;
+
+    /// Frequency of the PL011 reference clock in Hz, if it's known ahead of time.
     ///
-    /// Must be called only once to avoid double-initializing peripherals.
-    pub unsafe fn new() -> Self {
-        let mut gpio = Gpio::new(mmap::GPIO_BASE);
-        let uart = PL011Uart::new(mmap::PL011_UART_BASE);
-        uart.init(&mut gpio, 921_600).unwrap();
+    /// If this is `None`, the firmware is asked for the frequency instead, and
+    /// [`UART_DEFAULT_CLOCK_HZ`] is used if that fails.
+    pub const UART_CLOCK_HZ: Option<u32> = None;
+
+    /// The PL011 reference clock the firmware sets up when `config.txt` doesn't override it.
+    pub const UART_DEFAULT_CLOCK_HZ: u32 = 48_000_000;
+
+    /// Frequency of the VideoCore core clock in Hz, which drives the mini UART. Works like
+    /// [`UART_CLOCK_HZ`].
+    pub const CORE_CLOCK_HZ: Option<u32> = None;
+
+    /// The core clock the firmware uses when `config.txt` doesn't override it.
+    #[cfg(feature = "bsp_rpi3")]
+    pub const CORE_DEFAULT_CLOCK_HZ: u32 = 250_000_000;
+    #[cfg(feature = "bsp_rpi4")]
+    pub const CORE_DEFAULT_CLOCK_HZ: u32 = 500_000_000;
+
+    /// The pin of the green activity LED, which the `led` command drives. On the 3B it's behind
+    /// the firmware's GPIO expander instead, where the command can't reach it.
+    #[cfg(feature = "bsp_rpi3")]
+    pub const ACT_LED_PIN: u8 = 29;
+    #[cfg(feature = "bsp_rpi4")]
+    pub const ACT_LED_PIN: u8 = 42;
 
-        let gpio = SpinMutex::new(gpio);
-        let uart = SpinMutex::new(uart);
+    /// Resolution of the framebuffer console in pixels, or `None` to use the display's.
+    pub const FRAMEBUFFER_SIZE: Option<(u32, u32)> = None;
+
+    /// What to do after a panic. The boards usually run a project unattended, so they're better
+    /// off rebooting. The delay stays below [WATCHDOG_TIMEOUT], which isn't petted after a panic.
+    pub const PANIC_ACTION: crate::power::PanicAction =
+        crate::power::PanicAction::Reboot(core::time::Duration::from_secs(5));
+
+    /// How long the kernel can go without a heartbeat before the watchdog resets the board, or
+    /// `None` to leave the watchdog off. It can't be longer than about 16 seconds.
+    pub const WATCHDOG_TIMEOUT: Option<core::time::Duration> =
+        Some(core::time::Duration::from_secs(10));
+}
 
-        Self {
-            gpio,
-            uart,
+#[cfg(feature = "bsp_rpi3")]
+const GPIO_VARIANT: gpio::Variant = gpio::Variant::Bcm2837;
+#[cfg(feature = "bsp_rpi4")]
+const GPIO_VARIANT: gpio::Variant = gpio::Variant::Bcm2711;
+
+#[cfg(feature = "bsp_rpi3")]
+type BoardInterruptController = BcmInterruptController;
+#[cfg(feature = "bsp_rpi4")]
+type BoardInterruptController = Gicv2;
+
+#[cfg(feature = "bsp_rpi3")]
+type BoardRng = BcmRng;
+#[cfg(feature = "bsp_rpi4")]
+type BoardRng = Rng200;
+
+/// # Safety
+///
+/// Must be called only once.
+#[cfg(feature = "bsp_rpi3")]
+unsafe fn new_interrupt_controller(fdt: Option<&Fdt<'_>>) -> BoardInterruptController {
+    BcmInterruptController::new(
+        fdt::discover::<BcmInterruptController>(fdt, 0, mmap::INTERRUPT_CONTROLLER_BASE),
+        mmap::LOCAL_BASE,
+    )
+}
+
+/// The line of the generic timer's non-secure physical timer. The device tree puts it on the
+/// ARM local interrupt controller, which only the driver for the one behind it knows about, so
+/// it's always taken from the map.
+#[cfg(feature = "bsp_rpi3")]
+fn arm_timer_irq(
+    _fdt: Option<&Fdt<'_>>,
+    _interrupt_controller: &BoardInterruptController,
+) -> IrqNumber {
+    irq_map::ARM_TIMER
+}
+
+/// # Safety
+///
+/// Must be called only once.
+#[cfg(feature = "bsp_rpi4")]
+unsafe fn new_interrupt_controller(fdt: Option<&Fdt<'_>>) -> BoardInterruptController {
+    let gicd_base_address = fdt::discover::<Gicv2>(fdt, 0, mmap::GICD_BASE);
+    let gicc_base_address = fdt::discover::<Gicv2>(fdt, 1, mmap::GICC_BASE);
+    Gicv2::new(gicd_base_address, gicc_base_address)
+}
+
+/// The line of the generic timer's non-secure physical timer, which is the second of its
+/// interrupts in the device tree.
+#[cfg(feature = "bsp_rpi4")]
+fn arm_timer_irq(
+    fdt: Option<&Fdt<'_>>,
+    interrupt_controller: &BoardInterruptController,
+) -> IrqNumber {
+    fdt.and_then(|fdt| fdt.find_driver::<ArmTimer>())
+        .and_then(|node| node.irqs::<2>(interrupt_controller))
+        .map_or(irq_map::ARM_TIMER, |irqs| irqs[1])
+}
+
+/// The early console writes to the same UART as [`stdout`], unless that's the framebuffer.
+pub const EARLY_CONSOLE_SHARES_STDOUT: bool = !cfg!(feature = "console_framebuffer");
+
+/// The UARTs are usually connected to terminals, which understand ANSI escape sequences, but the
+/// framebuffer console doesn't.
+pub const CONSOLE_SUPPORTS_ANSI: bool = !cfg!(feature = "console_framebuffer");
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "console_mini_uart")] {
+        /// Polled console that works before the drivers are initialized.
+        ///
+        /// This relies on the firmware having already set up the mini UART.
+        pub fn early_console() -> crate::driver::mini_uart::MiniUartPolled {
+            // SAFETY: the address comes from the memory map for this board
+            unsafe { crate::driver::mini_uart::MiniUartPolled::new(mmap::AUX_BASE) }
+        }
+    } else {
+        /// Polled console that works before the drivers are initialized.
+        ///
+        /// This relies on the firmware (or QEMU) having already set up the PL011.
+        pub fn early_console() -> crate::driver::pl011::PL011Polled {
+            // SAFETY: the address comes from the memory map for this board
+            unsafe { crate::driver::pl011::PL011Polled::new(mmap::PL011_UART_BASE) }
         }
     }
+}
+
+static INTERRUPT_CONTROLLER: OnceCell<IrqSafeSpinMutex<BoardInterruptController>> = OnceCell::new();
+static ARM_TIMER: OnceCell<SpinMutex<ArmTimer>> = OnceCell::new();
+static SYSTEM_TIMER: OnceCell<SpinMutex<BcmSystemTimer>> = OnceCell::new();
+/// The system timer's counter, which is read without taking the timer's lock.
+static SYSTEM_COUNTER: OnceCell<BcmSystemCounter> = OnceCell::new();
+static MAILBOX: OnceCell<SpinMutex<Mailbox>> = OnceCell::new();
+static GPIO: OnceCell<SpinMutex<Gpio>> = OnceCell::new();
+static UART: OnceCell<SpinMutex<PL011Uart>> = OnceCell::new();
+static MINI_UART: OnceCell<SpinMutex<MiniUart>> = OnceCell::new();
+static FB_CONSOLE: OnceCell<SpinMutex<FramebufferConsole>> = OnceCell::new();
+static WATCHDOG: OnceCell<SpinMutex<BcmWatchdog>> = OnceCell::new();
+static RNG: OnceCell<SpinMutex<BoardRng>> = OnceCell::new();
+/// The activity LED, which is claimed the first time the `led` command runs
+static ACT_LED: SpinMutex<Option<gpio::Pin<gpio::Output, { config::ACT_LED_PIN }>>> =
+    SpinMutex::new_spin(None);
+
+shell_command! {
+    /// Turn the activity LED on or off, or toggle it
+    static LED: "led" "[on|off]" => led;
+}
 
-    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &dyn Compatible> {
-        core::array::IntoIter::new([&self.gpio as &dyn Compatible, &self.uart])
+/// Log what the firmware knows about the board we're running on.
+fn log_board_info(mailbox: &mut Mailbox) {
+    match mailbox.board_revision() {
+        Ok(revision) => {
+            info!(
+                "Board: Raspberry Pi {} ({}), revision {}",
+                revision.model_name(),
+                revision.processor_name(),
+                Hex(revision.0)
+            );
+            if let Some(memory) = revision.memory_mib() {
+                info!("Installed memory: {} MiB", memory);
+            }
+        }
+        Err(e) => warn!("Failed to read the board revision: {}", e),
     }
 
-    pub fn stdout(&self) -> SpinMutexMut<dyn ufmt::uWrite<Error = WriteError>> {
-        self.uart.borrow()
+    if let Ok(model) = mailbox.board_model() {
+        info!("Board model: {}", Hex(model));
+    }
+    if let Ok(serial) = mailbox.board_serial() {
+        info!("Serial number: {}", Hex(serial));
+    }
+    if let Ok(firmware) = mailbox.firmware_revision() {
+        info!("Firmware revision: {}", Hex(firmware));
     }
+    if let (Ok(arm), Ok(vc)) = (mailbox.arm_memory(), mailbox.vc_memory()) {
+        info!(
+            "Memory split: ARM {} bytes at {}, VideoCore {} bytes at {}",
+            arm.size,
+            Hex(arm.base),
+            vc.size,
+            Hex(vc.base)
+        );
+    }
+    if let (Ok(rate), Ok(max_rate)) =
+        (mailbox.clock_rate(Clock::Arm), mailbox.max_clock_rate(Clock::Arm))
+    {
+        info!("ARM clock: {} MHz, up to {} MHz", rate / 1_000_000, max_rate / 1_000_000);
+    }
+    if let Ok(temperature) = mailbox.temperature() {
+        info!("SoC temperature: {} m°C", temperature);
+    }
+    if let Ok(temperature) = mailbox.max_temperature() {
+        info!("Throttling temperature: {} m°C", temperature);
+    }
+}
+
+fn led(args: &mut Args<'_>, _out: &mut Output) -> Result<(), shell::Error> {
+    let on = match args.next() {
+        Some("on") => Some(true),
+        Some("off") => Some(false),
+        Some(_) => return Err(shell::Error::Usage),
+        None => None,
+    };
+    args.finish()?;
+
+    ACT_LED.with_lock(|led| -> Result<(), shell::Error> {
+        if led.is_none() {
+            let gpio = crate::DRIVERS
+                .get()
+                .get::<SpinMutex<Gpio>>()
+                .ok_or(shell::Error::Failed("the GPIO driver isn't running"))?;
+            let pin = gpio.with_lock(|gpio| gpio.claim::<{ config::ACT_LED_PIN }>())?;
+            *led = Some(pin.into_output());
+        }
+        if let Some(led) = led {
+            match on {
+                Some(on) => led.set(on),
+                None => led.toggle(),
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Find out the frequency of one of the firmware's clocks.
+///
+/// `configured` takes precedence if it's set, and `default` is used if the firmware doesn't know.
+fn clock_hz(
+    mailbox: &mut Mailbox,
+    clock: Clock,
+    name: &str,
+    configured: Option<u32>,
+    default: u32,
+) -> u32 {
+    if let Some(clock_hz) = configured {
+        return clock_hz
+    }
+
+    match mailbox.clock_rate(clock) {
+        Ok(clock_hz) if clock_hz != 0 => clock_hz,
+        _ => {
+            warn!("Failed to read the {} clock, assuming {} Hz", name, default);
+            default
+        }
+    }
+}
+
+/// Find out the frequency of the PL011 reference clock.
+///
+/// Some firmware leaves the UART clock too slow for [`config::UART_BAUD`], e.g. at the 3 MHz that
+/// older releases (and QEMU) report. In that case the firmware is asked to switch to
+/// [`config::UART_DEFAULT_CLOCK_HZ`].
+fn uart_clock_hz(mailbox: &mut Mailbox) -> u32 {
+    let clock_hz = clock_hz(
+        mailbox,
+        Clock::Uart,
+        "UART",
+        config::UART_CLOCK_HZ,
+        config::UART_DEFAULT_CLOCK_HZ,
+    );
+    if config::UART_CLOCK_HZ.is_some() || PL011Uart::supports_baud(clock_hz, config::UART_BAUD) {
+        return clock_hz
+    }
+
+    match mailbox.set_clock_rate(Clock::Uart, config::UART_DEFAULT_CLOCK_HZ, true) {
+        Ok(new_clock_hz) if PL011Uart::supports_baud(new_clock_hz, config::UART_BAUD) => {
+            info!("Raised the UART clock from {} Hz to {} Hz", clock_hz, new_clock_hz);
+            new_clock_hz
+        }
+        _ => {
+            warn!("The UART clock of {} Hz can't generate {} baud", clock_hz, config::UART_BAUD);
+            clock_hz
+        }
+    }
+}
+
+/// Create the drivers for the devices on this board and add them to `registry`.
+///
+/// # Safety
+///
+/// Must be called only once to avoid double-initializing peripherals.
+pub unsafe fn register_drivers(registry: &mut Registry) {
+    let device_tree = fdt::boot();
+    let fdt = device_tree.as_ref();
+    if fdt.is_none() {
+        info!("No device tree, using the built-in memory map");
+    }
+
+    let interrupt_controller = new_interrupt_controller(fdt);
+    let mut mailbox = Mailbox::new(fdt::discover::<Mailbox>(fdt, 0, mmap::MAILBOX_BASE));
+    log_board_info(&mut mailbox);
+
+    let uart_clock_hz = uart_clock_hz(&mut mailbox);
+    let core_clock_hz = clock_hz(
+        &mut mailbox,
+        Clock::Core,
+        "core",
+        config::CORE_CLOCK_HZ,
+        config::CORE_DEFAULT_CLOCK_HZ,
+    );
+
+    let arm_timer = ArmTimer::new(arm_timer_irq(fdt, &interrupt_controller));
+
+    let system_timer_irqs = fdt
+        .and_then(|fdt| fdt.find_driver::<BcmSystemTimer>())
+        .and_then(|node| node.irqs(&interrupt_controller))
+        .unwrap_or(irq_map::SYSTEM_TIMER);
+    let system_timer = BcmSystemTimer::new(
+        fdt::discover::<BcmSystemTimer>(fdt, 0, mmap::SYSTEM_TIMER_BASE),
+        system_timer_irqs,
+    );
+    SYSTEM_COUNTER.get_or_init(|| system_timer.counter());
+
+    let gpio_irqs = fdt
+        .and_then(|fdt| fdt.find_driver::<Gpio>())
+        .and_then(|node| node.irqs(&interrupt_controller))
+        .unwrap_or(irq_map::GPIO);
+    let gpio = Gpio::new(
+        fdt::discover::<Gpio>(fdt, 0, mmap::GPIO_BASE),
+        GPIO_VARIANT,
+        gpio_irqs,
+    );
+
+    let uart_config = UartConfig::new(config::UART_BAUD);
+    let mut uart = PL011Uart::new(
+        fdt::discover::<PL011Uart>(fdt, 0, mmap::PL011_UART_BASE),
+        uart_clock_hz,
+        uart_config,
+    );
+
+    // the device tree points at the mini UART's registers, but the driver wants the start of the
+    // auxiliary block they're in
+    let mini_uart_base =
+        fdt::discover::<MiniUart>(fdt, 0, mmap::AUX_BASE + mini_uart::AUX_MU_OFFSET);
+    let aux_base = mini_uart_base - mini_uart::AUX_MU_OFFSET;
+    let mut mini_uart = MiniUart::new(aux_base, core_clock_hz, uart_config);
+
+    // Only the console gets GPIO 14 and 15. The other UART is left wherever the firmware routed
+    // it, e.g. to the Bluetooth module.
+    if cfg!(feature = "console_mini_uart") {
+        mini_uart.route_to_pins();
+    } else {
+        uart.route_to_pins();
+    }
+
+    let watchdog = BcmWatchdog::new(
+        fdt::discover::<BcmWatchdog>(fdt, 0, mmap::PM_BASE),
+        config::WATCHDOG_TIMEOUT,
+    );
+    let rng = BoardRng::new(fdt::discover::<BoardRng>(fdt, 0, mmap::RNG_BASE));
+
+    registry.register(&INTERRUPT_CONTROLLER, IrqSafeSpinMutex::new(interrupt_controller));
+    registry.register(&ARM_TIMER, SpinMutex::new(arm_timer));
+    registry.register(&SYSTEM_TIMER, SpinMutex::new(system_timer));
+    registry.register(&MAILBOX, SpinMutex::new(mailbox));
+    registry.register(&GPIO, SpinMutex::new(gpio));
+    registry.register(&UART, SpinMutex::new(uart));
+    registry.register(&MINI_UART, SpinMutex::new(mini_uart));
+    // setting up the console takes a framebuffer from the firmware, so it's only done if it's used
+    if cfg!(feature = "console_framebuffer") {
+        let fb_console = FramebufferConsole::new(config::FRAMEBUFFER_SIZE);
+        registry.register(&FB_CONSOLE, SpinMutex::new(fb_console));
+    }
+    registry.register(&WATCHDOG, SpinMutex::new(watchdog));
+    registry.register(&RNG, SpinMutex::new(rng));
+
+    // the shell can do without the command, so that's not a reason to fail
+    if let Err(e) = shell::register(&LED) {
+        warn!("Couldn't add the 'led' command: {}", e);
+    }
+}
+
+/// A copy of the watchdog driver, or one at the default address if it isn't available, e.g.
+/// because it was locked by the code that panicked.
+fn lockless_watchdog() -> BcmWatchdog {
+    crate::DRIVERS
+        .try_get()
+        .and_then(|drivers| drivers.get::<SpinMutex<BcmWatchdog>>())
+        .and_then(|watchdog| watchdog.try_with_lock(|watchdog| *watchdog))
+        // SAFETY: the address comes from the memory map for this board
+        .unwrap_or_else(|| unsafe { BcmWatchdog::new(mmap::PM_BASE, None) })
+}
+
+/// Reset the board with the watchdog. Only returns on failure.
+pub fn reboot() {
+    lockless_watchdog().restart()
+}
+
+/// Ask the firmware to halt the board, which is as close to off as a Pi gets. Only returns on
+/// failure.
+pub fn power_off() {
+    lockless_watchdog().power_off()
+}
+
+/// The interrupt controller, once its driver is initialized.
+pub fn interrupt_controller(
+    drivers: &'static Registry,
+) -> Option<IrqSafeSpinMutexMut<'static, dyn InterruptController>> {
+    drivers
+        .get::<IrqSafeSpinMutex<BoardInterruptController>>()
+        .map(|controller| controller.borrow())
+}
+
+/// The console, once its driver is initialized.
+pub fn stdout(
+    drivers: &'static Registry,
+) -> Option<SpinMutexMut<'static, dyn ufmt::uWrite<Error = WriteError>>> {
+    if cfg!(feature = "console_framebuffer") {
+        drivers.get::<SpinMutex<FramebufferConsole>>().map(|console| console.borrow())
+    } else if cfg!(feature = "console_mini_uart") {
+        drivers.get::<SpinMutex<MiniUart>>().map(|uart| uart.borrow())
+    } else {
+        drivers.get::<SpinMutex<PL011Uart>>().map(|uart| uart.borrow())
+    }
+}
+
+/// The devices that input is read from: the UART that's used as the console, or the PL011 if
+/// that's the framebuffer.
+pub fn stdin(
+    drivers: &'static Registry,
+) -> impl Iterator<Item = SpinMutexMut<'static, dyn CharInput>> {
+    let uart = if cfg!(feature = "console_mini_uart") {
+        drivers.get::<SpinMutex<MiniUart>>().map(|uart| uart.borrow())
+    } else {
+        drivers.get::<SpinMutex<PL011Uart>>().map(|uart| uart.borrow())
+    };
+    uart.into_iter()
+}
+
+/// The watchdog, once its driver is initialized.
+pub fn watchdog(drivers: &'static Registry) -> Option<SpinMutexMut<'static, dyn Watchdog>> {
+    drivers.get::<SpinMutex<BcmWatchdog>>().map(|watchdog| watchdog.borrow())
+}
+
+/// The hardware random number generator, once its driver is initialized.
+pub fn entropy_source(
+    drivers: &'static Registry,
+) -> Option<SpinMutexMut<'static, dyn EntropySource>> {
+    drivers.get::<SpinMutex<BoardRng>>().map(|rng| rng.borrow())
+}
+
+/// The system timer's counter, once the timer's driver is initialized.
+pub fn clock_sources(
+    drivers: &'static Registry,
+) -> impl Iterator<Item = &'static dyn ClockSource> {
+    drivers
+        .get::<SpinMutex<BcmSystemTimer>>()
+        .and(SYSTEM_COUNTER.get())
+        .map(|counter| counter as &'static dyn ClockSource)
+        .into_iter()
+}
+
+/// The generic timer and the system timer, once their drivers are initialized.
+pub fn clock_events(
+    drivers: &'static Registry,
+) -> impl Iterator<Item = SpinMutexMut<'static, dyn ClockEvent>> {
+    let arm_timer = drivers.get::<SpinMutex<ArmTimer>>().map(|timer| timer.borrow());
+    let system_timer = drivers.get::<SpinMutex<BcmSystemTimer>>().map(|timer| timer.borrow());
+    arm_timer.into_iter().chain(system_timer)
 }
@@ -2,34 +2,252 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
-use crate::driver::{text_vga::TextVga, traits::Compatible, WriteError};
-use crate::sync::{SpinMutex, SpinMutexMut};
+use crate::driver::hpet::{Hpet, HpetCounter};
+use crate::driver::i8042::I8042;
+use crate::driver::input::{CharInput, Key, KeyEvent};
+use crate::driver::pic8259::Pic8259;
+use crate::driver::pit8254::Pit8254;
+use crate::driver::ps2_keyboard::{Ps2Keyboard, ScancodeSet};
+use crate::driver::rdrand::Rdrand;
+use crate::driver::text_vga::{self, TextVga};
+use crate::driver::{registry::Registry, uart_16550::Uart16550, WriteError};
+use crate::interrupt::InterruptController;
+use crate::rand::EntropySource;
+use crate::runtime_init;
+use crate::sync::{IrqSafeSpinMutex, IrqSafeSpinMutexMut, OnceCell, SpinMutex, SpinMutexMut};
+use crate::time::{ClockEvent, ClockSource, SimpleTimer};
+use crate::watchdog::Watchdog;
+use core::time::Duration;
+use x86::io::{outb, outw};
 
+/// Physical addresses. Anything besides the VGA buffer is reached through the bootloader's
+/// mapping of physical memory.
 pub mod mmap {
     pub const TEXT_VGA: usize = 0xb8000;
+    /// Where the HPET is on nearly every PC. The ACPI tables say for sure, but they aren't read.
+    pub const HPET: usize = 0xfed0_0000;
 }
 
-pub struct DriverManager {
-    text_vga: SpinMutex<TextVga>,
+pub mod io_ports {
+    pub const PIC_PRIMARY: u16 = 0x20;
+    pub const PIT: u16 = 0x40;
+    pub const PS2_DATA: u16 = 0x60;
+    pub const PS2_COMMAND: u16 = 0x64;
+    pub const PIC_SECONDARY: u16 = 0xa0;
+    pub const COM1: u16 = 0x3f8;
+    /// The chipset's reset control register, which is where the ACPI reset register points on
+    /// nearly every PC
+    pub const RESET_CONTROL: u16 = 0xcf9;
 }
 
-impl DriverManager {
-    /// Initialize all available drivers
-    ///
-    /// # Safety
-    ///
-    /// Must be called only once to avoid double-initializing peripherals.
-    pub unsafe fn new() -> Self {
-        Self {
-            text_vga: SpinMutex::new(TextVga::new(mmap::TEXT_VGA)),
-        }
+pub mod irq_map {
+    use crate::interrupt::IrqNumber;
+
+    /// The PIT's channel 0, or the HPET's timer 0 once it takes the line over
+    pub const TIMER: IrqNumber = 0;
+    pub const KEYBOARD: IrqNumber = 1;
+}
+
+pub mod config {
+    use crate::driver::ps2_keyboard::ScancodeSet;
+
+    /// What the VGA console shows in place of characters that code page 437 doesn't have.
+    pub const VGA_REPLACEMENT_CHAR: char = '■';
+
+    /// How many lines that scrolled off the VGA console are kept to page back through.
+    pub const VGA_SCROLLBACK_LINES: usize = 200;
+
+    /// The scancodes the keyboard driver decodes. With set 1, the PS/2 controller translates
+    /// what the keyboard sends, which is how most firmware leaves it.
+    pub const PS2_SCANCODE_SET: ScancodeSet = ScancodeSet::Set1;
+
+    /// What to do after a panic. There's usually somebody in front of a PC's screen, and the VGA
+    /// console can't scroll back, so the report is left up for them to read.
+    pub const PANIC_ACTION: crate::power::PanicAction = crate::power::PanicAction::Halt;
+}
+
+/// Bits of the reset control register: reset the CPU, and the rest of the system with it
+const RESET_CONTROL_SYSTEM_RESET: u8 = 0x02;
+const RESET_CONTROL_RESET_CPU: u8 = 0x04;
+
+/// How long each way of resetting gets to work before the next one is tried
+const RESET_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// The ACPI power management ports that emulators turn off on, with the value that does it. There's
+/// no way to turn a real machine off without reading the ACPI tables, and those aren't mapped.
+const EMULATOR_POWER_OFF: [(u16, u16); 3] = [
+    // QEMU
+    (0x604, 0x2000),
+    // Bochs and older versions of QEMU
+    (0xb004, 0x2000),
+    // VirtualBox
+    (0x4004, 0x3400),
+];
+
+/// The early console writes to the serial port, while [`stdout`] is the screen.
+pub const EARLY_CONSOLE_SHARES_STDOUT: bool = false;
+
+/// Both the serial port's terminal and the VGA console understand ANSI escape sequences.
+pub const CONSOLE_SUPPORTS_ANSI: bool = true;
+
+/// Polled console that works before the drivers are initialized.
+///
+/// This relies on the firmware having already set up COM1.
+pub fn early_console() -> Uart16550 {
+    // SAFETY: COM1 is at the same port on every PC
+    unsafe { Uart16550::new(io_ports::COM1) }
+}
+
+static PIC: OnceCell<IrqSafeSpinMutex<Pic8259>> = OnceCell::new();
+static PIT: OnceCell<SpinMutex<Pit8254>> = OnceCell::new();
+static HPET: OnceCell<SpinMutex<Hpet>> = OnceCell::new();
+/// The HPET's counter, which is read without taking the timer's lock.
+static HPET_COUNTER: OnceCell<HpetCounter> = OnceCell::new();
+static TEXT_VGA: OnceCell<SpinMutex<TextVga>> = OnceCell::new();
+static KEYBOARD: OnceCell<SpinMutex<Ps2Keyboard>> = OnceCell::new();
+static SERIAL: OnceCell<SpinMutex<Uart16550>> = OnceCell::new();
+static RNG: OnceCell<SpinMutex<Rdrand>> = OnceCell::new();
+
+static mut VGA_SCROLLBACK: [text_vga::Line; config::VGA_SCROLLBACK_LINES] =
+    [text_vga::BLANK_LINE; config::VGA_SCROLLBACK_LINES];
+
+/// Create the drivers for the devices on this machine and add them to `registry`.
+///
+/// # Safety
+///
+/// Must be called only once to avoid double-initializing peripherals.
+pub unsafe fn register_drivers(registry: &mut Registry) {
+    // this is the only reference to the scrollback, since we're only called once
+    let text_vga = TextVga::new(
+        mmap::TEXT_VGA,
+        config::VGA_REPLACEMENT_CHAR,
+        &mut VGA_SCROLLBACK,
+    );
+    let pic = Pic8259::new(io_ports::PIC_PRIMARY, io_ports::PIC_SECONDARY);
+    let pit = Pit8254::new(io_ports::PIT, irq_map::TIMER);
+    let hpet = Hpet::new(runtime_init::physical_memory_offset() + mmap::HPET, irq_map::TIMER);
+    let mut keyboard = Ps2Keyboard::new(
+        io_ports::PS2_DATA,
+        io_ports::PS2_COMMAND,
+        irq_map::KEYBOARD,
+        config::PS2_SCANCODE_SET,
+    );
+    keyboard.set_hook(console_hotkeys);
+    // the firmware already set COM1 up for the early console, and the driver leaves it that way
+    let serial = Uart16550::new(io_ports::COM1);
+
+    registry.register(&PIC, IrqSafeSpinMutex::new(pic));
+    registry.register(&PIT, SpinMutex::new(pit));
+    registry.register(&HPET, SpinMutex::new(hpet));
+    registry.register(&TEXT_VGA, SpinMutex::new(text_vga));
+    registry.register(&KEYBOARD, SpinMutex::new(keyboard));
+    registry.register(&SERIAL, SpinMutex::new(serial));
+    registry.register(&RNG, SpinMutex::new(Rdrand::new()));
+}
+
+/// Shift+Page Up and Shift+Page Down page through the VGA console's scrollback.
+fn console_hotkeys(event: KeyEvent) -> bool {
+    if !event.modifiers.shift {
+        return false
+    }
+    let text_vga = match TEXT_VGA.get() {
+        Some(text_vga) => text_vga,
+        None => return false,
+    };
+    match event.key {
+        // the console may be in the middle of a write, in which case the key is dropped
+        Key::PageUp => text_vga.try_with_lock(|text_vga| text_vga.page_up()).is_some(),
+        Key::PageDown => text_vga.try_with_lock(|text_vga| text_vga.page_down()).is_some(),
+        _ => false,
     }
+}
 
-    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &dyn Compatible> {
-        core::array::IntoIter::new([&self.text_vga as &dyn Compatible])
+/// Reset the machine, trying the reset control register, then the keyboard controller, and then a
+/// triple fault, which always works.
+///
+/// The ACPI tables say where the reset register is, but they aren't mapped. Every chipset since
+/// the PIIX has the reset control register, though, and it's what the tables nearly always name.
+pub fn reboot() {
+    // SAFETY: the register is at the same port on every PC
+    unsafe {
+        outb(io_ports::RESET_CONTROL, RESET_CONTROL_SYSTEM_RESET);
+        outb(io_ports::RESET_CONTROL, RESET_CONTROL_SYSTEM_RESET | RESET_CONTROL_RESET_CPU);
     }
+    crate::time::arch_timer().spin_for(RESET_TIMEOUT);
+
+    // SAFETY: the controller is at the same ports on every PC
+    unsafe { I8042::new(io_ports::PS2_DATA, io_ports::PS2_COMMAND) }.pulse_reset();
+
+    // SAFETY: the machine is being reset anyways
+    unsafe { crate::arch::exception::triple_fault() }
+}
 
-    pub fn stdout(&self) -> SpinMutexMut<dyn ufmt::uWrite<Error = WriteError>> {
-        self.text_vga.borrow()
+/// Turn off the machine, if it's an emulator that makes that easy. Returns if it isn't.
+pub fn power_off() {
+    for &(port, value) in EMULATOR_POWER_OFF.iter() {
+        // SAFETY: nothing else in the kernel uses these ports
+        unsafe { outw(port, value) };
+        crate::time::arch_timer().spin_for(RESET_TIMEOUT);
     }
 }
+
+/// The interrupt controller, once its driver is initialized.
+pub fn interrupt_controller(
+    drivers: &'static Registry,
+) -> Option<IrqSafeSpinMutexMut<'static, dyn InterruptController>> {
+    drivers.get::<IrqSafeSpinMutex<Pic8259>>().map(|controller| controller.borrow())
+}
+
+/// The console, once its driver is initialized.
+pub fn stdout(
+    drivers: &'static Registry,
+) -> Option<SpinMutexMut<'static, dyn ufmt::uWrite<Error = WriteError>>> {
+    drivers.get::<SpinMutex<TextVga>>().map(|text_vga| text_vga.borrow())
+}
+
+/// The devices that input is read from: the keyboard, and the serial port, since the log is
+/// written there too.
+pub fn stdin(
+    drivers: &'static Registry,
+) -> impl Iterator<Item = SpinMutexMut<'static, dyn CharInput>> {
+    let keyboard = drivers.get::<SpinMutex<Ps2Keyboard>>().map(|keyboard| keyboard.borrow());
+    let serial = drivers.get::<SpinMutex<Uart16550>>().map(|serial| serial.borrow());
+    keyboard.into_iter().chain(serial)
+}
+
+/// The watchdog, which this board doesn't have.
+pub fn watchdog(_drivers: &'static Registry) -> Option<SpinMutexMut<'static, dyn Watchdog>> {
+    None
+}
+
+/// The processor's random number generator, once its driver found it.
+pub fn entropy_source(
+    drivers: &'static Registry,
+) -> Option<SpinMutexMut<'static, dyn EntropySource>> {
+    drivers.get::<SpinMutex<Rdrand>>().map(|rng| rng.borrow())
+}
+
+/// Clock sources besides the architecture's timer: the HPET, once its driver is initialized.
+pub fn clock_sources(
+    drivers: &'static Registry,
+) -> impl Iterator<Item = &'static dyn ClockSource> {
+    // the counter's period is only known to be right once the driver found the timer
+    drivers
+        .get::<SpinMutex<Hpet>>()
+        .map(|hpet| HPET_COUNTER.get_or_init(|| hpet.with_lock(|hpet| hpet.counter())))
+        .map(|counter| counter as &'static dyn ClockSource)
+        .into_iter()
+}
+
+/// The HPET and the PIT, once their drivers are initialized. Both raise line 0, so only the one
+/// that's picked may be programmed.
+///
+/// The local APIC's timer isn't driven. Its interrupt doesn't go through the 8259, which is the
+/// only thing IRQs are read back from, and it would need an end of interrupt of its own.
+pub fn clock_events(
+    drivers: &'static Registry,
+) -> impl Iterator<Item = SpinMutexMut<'static, dyn ClockEvent>> {
+    let hpet = drivers.get::<SpinMutex<Hpet>>().map(|hpet| hpet.borrow());
+    let pit = drivers.get::<SpinMutex<Pit8254>>().map(|pit| pit.borrow());
+    hpet.into_iter().chain(pit)
+}
@@ -8,6 +8,9 @@ cfg_if::cfg_if! {
     if #[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))] {
         pub mod bsp_rpi;
         pub use bsp_rpi::*;
+    } else if #[cfg(feature = "bsp_qemu_virt")] {
+        pub mod bsp_qemu_virt;
+        pub use bsp_qemu_virt::*;
     } else if #[cfg(target_arch = "x86_64")] {
         pub mod bsp_x86_64;
         pub use bsp_x86_64::*;
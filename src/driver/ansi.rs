@@ -0,0 +1,170 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Parser for the ANSI/VT100 escape sequences that text consoles understand.
+//!
+//! Only control sequences (`ESC [ ... final`) are recognized. Any other escape sequence is dropped
+//! after its first character, which is enough to keep the rest of it from being printed in most
+//! cases.
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// How many numeric parameters a control sequence can have. Extra parameters are dropped.
+pub const MAX_PARAMS: usize = 8;
+
+/// What the console should do with the character it just fed to the [Parser].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Nothing, because the character is part of an escape sequence.
+    None,
+    /// Print the character, or carry it out if it's a control character like `\n`.
+    Print(char),
+    /// Carry out a complete control sequence.
+    Control(ControlSequence),
+}
+
+/// A control sequence, such as `ESC [ 1 ; 31 m`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ControlSequence {
+    params: [u16; MAX_PARAMS],
+    len: usize,
+    /// Whether the parameters started with `?`, which marks DEC private modes
+    pub private: bool,
+    /// The character that ended the sequence and says what it does, e.g. `m` for SGR
+    pub command: char,
+}
+
+pub struct Parser {
+    state: State,
+    sequence: ControlSequence,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const ESCAPE: char = '\x1b';
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum State {
+    /// Outside of an escape sequence
+    Ground,
+    /// Just after an `ESC`
+    Escape,
+    /// Inside a control sequence, after the `ESC [`
+    Params,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl ControlSequence {
+    const fn new() -> Self {
+        Self {
+            params: [0; MAX_PARAMS],
+            len: 0,
+            private: false,
+            command: '\0',
+        }
+    }
+
+    fn push_digit(&mut self, digit: u16) {
+        if self.len == 0 {
+            self.len = 1;
+        }
+        if let Some(param) = self.params.get_mut(self.len - 1) {
+            *param = param.saturating_mul(10).saturating_add(digit);
+        }
+    }
+
+    fn next_param(&mut self) {
+        // an empty first parameter still counts, so `ESC [ ; 5 H` is row 1, column 5
+        if self.len == 0 {
+            self.len = 1;
+        }
+        // one past the end marks that parameters are being dropped
+        self.len = (self.len + 1).min(MAX_PARAMS + 1);
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl ControlSequence {
+    /// The parameters in the order they were given. Parameters that were left empty are 0.
+    pub fn params(&self) -> &[u16] {
+        &self.params[..self.len.min(MAX_PARAMS)]
+    }
+
+    /// The parameter at `index`, or `default` if it's missing or 0.
+    pub fn param(&self, index: usize, default: u16) -> u16 {
+        match self.params().get(index) {
+            Some(&param) if param != 0 => param,
+            _ => default,
+        }
+    }
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Self {
+            state: State::Ground,
+            sequence: ControlSequence::new(),
+        }
+    }
+
+    /// Feed the next character of the output to the parser.
+    pub fn advance(&mut self, c: char) -> Action {
+        match self.state {
+            State::Ground if c == ESCAPE => {
+                self.state = State::Escape;
+                Action::None
+            }
+            State::Ground => Action::Print(c),
+            State::Escape => {
+                self.state = if c == '[' { State::Params } else { State::Ground };
+                self.sequence = ControlSequence::new();
+                Action::None
+            }
+            State::Params => match c {
+                '0'..='9' => {
+                    self.sequence.push_digit(c as u16 - '0' as u16);
+                    Action::None
+                }
+                ';' => {
+                    self.sequence.next_param();
+                    Action::None
+                }
+                '?' if self.sequence.len == 0 && !self.sequence.private => {
+                    self.sequence.private = true;
+                    Action::None
+                }
+                '\x40'..='\x7e' => {
+                    self.state = State::Ground;
+                    self.sequence.command = c;
+                    Action::Control(self.sequence)
+                }
+                ESCAPE => {
+                    self.state = State::Escape;
+                    Action::None
+                }
+                // anything else means the sequence is malformed or one we don't know, so drop it
+                _ => {
+                    self.state = State::Ground;
+                    Action::None
+                }
+            },
+        }
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,143 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Driver for the non-secure physical timer of the ARM generic timer, as a clock event device.
+//!
+//! The timer is part of every core and compares the counter that [crate::time::arch_timer] reads
+//! against `CNTP_CVAL_EL0`. Its interrupt is private to the core, and it's wired differently on
+//! every board, which is why this is a driver rather than part of the architecture's code. Only the
+//! boot core's timer is used.
+
+use crate::driver::{self, registry::Registry, traits::Driver};
+use crate::interrupt::{self, IrqNumber};
+use crate::sync::IrqSafeSpinMutex;
+use crate::time::{ClockEvent, EventHandler, NS_PER_SEC};
+use core::convert::TryFrom;
+use core::time::Duration;
+use cortex_a::registers::{CNTFRQ_EL0, CNTPCT_EL0, CNTP_CTL_EL0, CNTP_CVAL_EL0};
+use tock_registers::interfaces::{Readable, Writeable};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+pub struct ArmTimer {
+    /// The interrupt line of the non-secure physical timer
+    irq: IrqNumber,
+    /// Whether our handler has been registered for `irq`
+    irq_hooked: bool,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// How the timer compares to others as a clock event device. It's read and programmed without
+/// going over a bus, and counts faster than the BCM2835 system timer.
+const RATING: u32 = 400;
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+/// The handler of the timer that's set, kept apart from [ArmTimer] so that the interrupt handler
+/// doesn't have to wait for whoever holds the driver.
+static HANDLER: IrqSafeSpinMutex<Option<EventHandler>> = IrqSafeSpinMutex::new_irq_safe(None);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// How many ticks of the counter make up `delay`, if that fits.
+fn ticks(delay: Duration) -> Option<u64> {
+    let ticks = delay.as_nanos() * u128::from(CNTFRQ_EL0.get()) / u128::from(NS_PER_SEC);
+    u64::try_from(ticks).ok()
+}
+
+/// Stop the timer, which also takes back its interrupt.
+fn stop() {
+    CNTP_CTL_EL0.write(CNTP_CTL_EL0::ENABLE::CLEAR + CNTP_CTL_EL0::IMASK::SET);
+}
+
+fn handle_irq(_irq: IrqNumber) {
+    // the interrupt is level-triggered, so it has to be turned off before it's acknowledged
+    stop();
+    // the handler runs without the lock held, so it's free to set the next timer
+    if let Some(handler) = HANDLER.with_lock(|handler| handler.take()) {
+        handler()
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl ArmTimer {
+    /// `irq` is the line of the non-secure physical timer, as numbered by the interrupt controller.
+    pub const fn new(irq: IrqNumber) -> Self {
+        Self { irq, irq_hooked: false }
+    }
+}
+
+impl Driver for ArmTimer {
+    const COMPATIBLE: &'static str = "arm,armv8-timer";
+    const ALSO_COMPATIBLE: &'static [&'static str] = &["arm,armv7-timer"];
+
+    fn init(&mut self, _registry: &Registry) -> Result<(), driver::Error> {
+        // the firmware may have left it running
+        stop();
+        Ok(())
+    }
+
+    fn shutdown(&mut self) {
+        self.cancel();
+        if self.irq_hooked {
+            interrupt::unregister(self.irq);
+            self.irq_hooked = false;
+        }
+    }
+}
+
+impl ClockEvent for ArmTimer {
+    fn name(&self) -> &'static str {
+        "ARM generic timer"
+    }
+
+    fn rating(&self) -> u32 {
+        RATING
+    }
+
+    /// Half the counter's range, so the compare value can't wrap around.
+    fn max_delay(&self) -> Duration {
+        let secs = (u64::MAX / 2) / CNTFRQ_EL0.get();
+        Duration::from_secs(secs)
+    }
+
+    fn program(&mut self, delay: Duration, handler: EventHandler) -> Result<(), driver::Error> {
+        if delay > self.max_delay() {
+            return Err(driver::Error::BadConfiguration)
+        }
+        let ticks = ticks(delay).ok_or(driver::Error::BadConfiguration)?;
+        if !self.irq_hooked {
+            interrupt::register(self.irq, handle_irq)?;
+            self.irq_hooked = true;
+        }
+
+        HANDLER.with_lock(|current| *current = Some(handler));
+        CNTP_CVAL_EL0.set(CNTPCT_EL0.get().wrapping_add(ticks));
+        CNTP_CTL_EL0.write(CNTP_CTL_EL0::ENABLE::SET + CNTP_CTL_EL0::IMASK::CLEAR);
+        interrupt::enable(self.irq)
+    }
+
+    fn cancel(&mut self) {
+        stop();
+        HANDLER.with_lock(|current| *current = None);
+    }
+}
+
+impl AsMut<dyn ClockEvent> for ArmTimer {
+    fn as_mut(&mut self) -> &mut (dyn ClockEvent + 'static) {
+        self
+    }
+}
@@ -0,0 +1,186 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Driver for the interrupt controller that the BCM2837 shares between the ARM cores and the
+//! VideoCore.
+//!
+//! Lines 0-63 are the peripheral ("GPU") interrupts, numbered as in the datasheet. Lines 64-71 are
+//! the ARM-specific basic interrupts, such as the ARM timer.
+//!
+//! Lines 72-75 are the boot core's generic timer interrupts. They don't go through this controller
+//! but through the BCM2836 ARM local interrupt controller in front of it, and are the secure
+//! physical, non-secure physical, hypervisor and virtual timer, in that order.
+
+use crate::driver::{self, traits::Driver};
+use crate::fdt::Cells;
+use crate::interrupt::{InterruptController, IrqNumber};
+use tock_registers::interfaces::{Readable, Writeable};
+use tock_registers::register_structs;
+use tock_registers::registers::{ReadOnly, ReadWrite, WriteOnly};
+
+/// Number of interrupt lines the controller has.
+pub const IRQ_COUNT: IrqNumber = 76;
+
+/// The first of the generic timer lines.
+pub const FIRST_LOCAL_TIMER_IRQ: IrqNumber = 72;
+
+// Description taken from
+// https://github.com/raspberrypi/documentation/files/1888662/BCM2837-ARM-Peripherals.-.Revised.-.V2-1.pdf
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => IRQ_BASIC_PENDING: ReadOnly<u32>),
+        (0x04 => IRQ_PENDING: [ReadOnly<u32>; 2]),
+        (0x0c => FIQ_CONTROL: ReadWrite<u32>),
+        (0x10 => ENABLE_IRQS: [WriteOnly<u32>; 2]),
+        (0x18 => ENABLE_BASIC_IRQS: WriteOnly<u32>),
+        (0x1c => DISABLE_IRQS: [WriteOnly<u32>; 2]),
+        (0x24 => DISABLE_BASIC_IRQS: WriteOnly<u32>),
+        (0x28 => @END),
+    }
+}
+
+// Description taken from the BCM2836 ARM-local peripherals document, QA7_rev3.4.
+register_structs! {
+    #[allow(non_snake_case)]
+    LocalRegisterBlock {
+        (0x00 => _reserved0),
+        /// Per core, bits 0-3 send the generic timer interrupts there as IRQs.
+        (0x40 => CORE_TIMER_IRQCNTL: [ReadWrite<u32>; 4]),
+        (0x50 => _reserved1),
+        /// Per core, bits 0-3 are the pending generic timer interrupts.
+        (0x60 => CORE_IRQ_SOURCE: [ReadOnly<u32>; 4]),
+        (0x70 => @END),
+    }
+}
+
+/// Only the low 8 bits of the basic registers are ARM interrupts. The rest mirror some of the
+/// peripheral interrupts.
+const BASIC_IRQ_MASK: u32 = 0xff;
+
+/// The generic timer interrupts, both in the local registers and shifted into the last word of
+/// [BcmInterruptController::enabled].
+const LOCAL_TIMER_MASK: u32 = 0xf;
+const LOCAL_TIMER_SHIFT: u32 = 8;
+
+/// Only the boot core receives interrupts for now.
+const BOOT_CORE: usize = 0;
+
+// FIXME: This provides shared mutability over the register block. Add some sort of Mutex to
+// regulate that interior mutability.
+pub struct BcmInterruptController {
+    regs: &'static mut RegisterBlock,
+    local: &'static mut LocalRegisterBlock,
+    /// Enabled lines, 32 per word. The last word holds the basic interrupts and, above them, the
+    /// generic timer interrupts.
+    enabled: [u32; 3],
+}
+
+impl BcmInterruptController {
+    /// # Safety
+    /// The user must verify that the addresses for the register block and the ARM local
+    /// peripherals are correct.
+    pub unsafe fn new(base_address: usize, local_base_address: usize) -> Self {
+        let controller = Self {
+            regs: &mut *(base_address as *mut _),
+            local: &mut *(local_base_address as *mut _),
+            enabled: [0; 3],
+        };
+
+        // start from a known state, where nothing reaches the CPU
+        controller.regs.FIQ_CONTROL.set(0);
+        controller.regs.DISABLE_IRQS[0].set(u32::MAX);
+        controller.regs.DISABLE_IRQS[1].set(u32::MAX);
+        controller.regs.DISABLE_BASIC_IRQS.set(BASIC_IRQ_MASK);
+        controller.local.CORE_TIMER_IRQCNTL[BOOT_CORE].set(0);
+        controller
+    }
+
+    fn pending(&self, bank: usize) -> u32 {
+        match bank {
+            0 | 1 => self.regs.IRQ_PENDING[bank].get(),
+            _ => {
+                let basic = self.regs.IRQ_BASIC_PENDING.get() & BASIC_IRQ_MASK;
+                let local = self.local.CORE_IRQ_SOURCE[BOOT_CORE].get() & LOCAL_TIMER_MASK;
+                basic | local << LOCAL_TIMER_SHIFT
+            }
+        }
+    }
+
+    /// Route the generic timer interrupts that are enabled in the last bank to the boot core.
+    fn update_local_timers(&self) {
+        let timers = self.enabled[2] >> LOCAL_TIMER_SHIFT & LOCAL_TIMER_MASK;
+        self.local.CORE_TIMER_IRQCNTL[BOOT_CORE].set(timers);
+    }
+}
+
+impl InterruptController for BcmInterruptController {
+    fn enable(&mut self, irq: IrqNumber) -> Result<(), driver::Error> {
+        if irq >= IRQ_COUNT {
+            return Err(driver::Error::BadConfiguration)
+        }
+
+        let (bank, bit) = (irq / 32, 1 << (irq % 32));
+        self.enabled[bank] |= bit;
+        match bank {
+            0 | 1 => self.regs.ENABLE_IRQS[bank].set(bit),
+            _ if irq >= FIRST_LOCAL_TIMER_IRQ => self.update_local_timers(),
+            _ => self.regs.ENABLE_BASIC_IRQS.set(bit),
+        }
+        Ok(())
+    }
+
+    fn disable(&mut self, irq: IrqNumber) {
+        if irq >= IRQ_COUNT {
+            return
+        }
+
+        let (bank, bit) = (irq / 32, 1 << (irq % 32));
+        self.enabled[bank] &= !bit;
+        match bank {
+            0 | 1 => self.regs.DISABLE_IRQS[bank].set(bit),
+            _ if irq >= FIRST_LOCAL_TIMER_IRQ => self.update_local_timers(),
+            _ => self.regs.DISABLE_BASIC_IRQS.set(bit),
+        }
+    }
+
+    fn acknowledge(&mut self) -> Option<IrqNumber> {
+        (0..self.enabled.len()).find_map(|bank| {
+            let pending = self.pending(bank) & self.enabled[bank];
+            if pending == 0 {
+                None
+            } else {
+                Some(bank * 32 + pending.trailing_zeros() as usize)
+            }
+        })
+    }
+
+    fn end_of_interrupt(&mut self, _irq: IrqNumber) {
+        // nothing to do, lines stay pending until the peripheral that raised them is serviced
+    }
+
+    /// Specifiers are a bank and a line within it. Bank 0 holds the basic interrupts, and banks 1
+    /// and 2 the peripheral interrupts 0-31 and 32-63. The generic timer interrupts belong to the
+    /// local interrupt controller's node, so they can't be translated here.
+    fn translate(&self, mut specifier: Cells<'_>) -> Option<IrqNumber> {
+        let (bank, line) = (specifier.next()? as IrqNumber, specifier.next()? as IrqNumber);
+        let irq = match bank {
+            0 => 64 + line,
+            1 | 2 => (bank - 1) * 32 + line,
+            _ => return None,
+        };
+        (line < 32 && irq < FIRST_LOCAL_TIMER_IRQ).then(|| irq)
+    }
+}
+
+impl Driver for BcmInterruptController {
+    const COMPATIBLE: &'static str = "brcm,bcm2836-armctrl-ic";
+    const ALSO_COMPATIBLE: &'static [&'static str] = &["brcm,bcm2835-armctrl-ic"];
+}
+
+impl AsMut<dyn InterruptController> for BcmInterruptController {
+    fn as_mut(&mut self) -> &mut (dyn InterruptController + 'static) {
+        self
+    }
+}
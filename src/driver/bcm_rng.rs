@@ -0,0 +1,121 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Driver for the hardware random number generator in the BCM2835 through BCM2837, which QEMU
+//! emulates as well.
+//!
+//! The generator fills a FIFO with 32-bit words. It throws away the first numbers it makes after
+//! it's enabled, since they aren't random enough yet.
+
+use crate::driver::{self, registry::Registry, traits::Driver};
+use crate::rand::EntropySource;
+use crate::time::SimpleTimer;
+use core::time::Duration;
+use tock_registers::interfaces::{Readable, Writeable};
+use tock_registers::register_structs;
+use tock_registers::registers::{ReadOnly, ReadWrite};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+pub struct BcmRng {
+    regs: &'static mut RegisterBlock,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Description taken from Linux's bcm2835-rng.c, since the datasheet leaves the generator out
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => CTRL: ReadWrite<u32>),
+        /// The top byte holds the number of words in the FIFO, the rest how many numbers to throw
+        /// away once the generator is enabled
+        (0x04 => STATUS: ReadWrite<u32>),
+        (0x08 => DATA: ReadOnly<u32>),
+        (0x0c => _reserved),
+        (0x10 => INT_MASK: ReadWrite<u32>),
+        (0x14 => @END),
+    }
+}
+
+/// Enables the generator
+const CTRL_RBGEN: u32 = 0x1;
+
+/// How many numbers are thrown away after the generator is enabled
+const STATUS_WARMUP_COUNT: u32 = 0x4_0000;
+const STATUS_AVAILABLE_SHIFT: u32 = 24;
+
+const INT_MASK_OFF: u32 = 0x1;
+
+/// How long to wait for a word, which has to be long enough for the generator to warm up.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl BcmRng {
+    /// Wait for the next word from the FIFO.
+    fn read_word(&self) -> Result<u32, driver::Error> {
+        let timer = crate::time::arch_timer();
+        let deadline = timer.uptime() + TIMEOUT;
+        while self.regs.STATUS.get() >> STATUS_AVAILABLE_SHIFT == 0 {
+            if timer.uptime() > deadline {
+                return Err(driver::Error::Timeout)
+            }
+            core::hint::spin_loop();
+        }
+        Ok(self.regs.DATA.get())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl BcmRng {
+    /// # Safety
+    /// The user must verify that the address is the base of the random number generator.
+    pub unsafe fn new(base_address: usize) -> Self {
+        Self { regs: &mut *(base_address as *mut _) }
+    }
+}
+
+impl Driver for BcmRng {
+    const COMPATIBLE: &'static str = "brcm,bcm2835-rng";
+
+    fn init(&mut self, _registry: &Registry) -> Result<(), driver::Error> {
+        // the firmware may already have started it, in which case it's warmed up
+        if self.regs.CTRL.get() & CTRL_RBGEN == 0 {
+            self.regs.INT_MASK.set(self.regs.INT_MASK.get() | INT_MASK_OFF);
+            self.regs.STATUS.set(STATUS_WARMUP_COUNT);
+            self.regs.CTRL.set(CTRL_RBGEN);
+        }
+        Ok(())
+    }
+
+    fn shutdown(&mut self) {
+        self.regs.CTRL.set(0);
+    }
+}
+
+impl EntropySource for BcmRng {
+    fn fill(&mut self, bytes: &mut [u8]) -> Result<(), driver::Error> {
+        for chunk in bytes.chunks_mut(4) {
+            let word = self.read_word()?.to_ne_bytes();
+            chunk.copy_from_slice(&word[..chunk.len()]);
+        }
+        Ok(())
+    }
+}
+
+impl AsMut<dyn EntropySource> for BcmRng {
+    fn as_mut(&mut self) -> &mut (dyn EntropySource + 'static) {
+        self
+    }
+}
@@ -0,0 +1,394 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Driver for the BCM283x system timer.
+//!
+//! The timer is a free-running 64-bit counter that ticks at 1 MHz, with four 32-bit compare
+//! channels that raise an interrupt when the low half of the counter matches them. It's clocked
+//! separately from the ARM cores, so it can be used to cross-check the generic timer.
+//!
+//! The VideoCore uses channels 0 and 2, which leaves [Channel::C1] and [Channel::C3] for alarms.
+//! [Channel::C3] is also what the timer uses as a [ClockEvent], so alarms set on it directly and
+//! timers set through [crate::time] replace each other.
+//!
+//! Reading the counter doesn't change anything, so it's also done through a [BcmSystemCounter],
+//! which works without the driver's lock and can be the kernel's [ClockSource].
+
+use crate::driver::{self, traits::Driver};
+use crate::interrupt::{self, IrqNumber};
+use crate::sync::IrqSafeSpinMutex;
+use crate::time::{ClockEvent, ClockSource, EventHandler, SimpleTimer};
+use core::convert::TryFrom;
+use core::time::Duration;
+use tock_registers::interfaces::{Readable, Writeable};
+use tock_registers::register_structs;
+use tock_registers::registers::{ReadOnly, ReadWrite};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A compare channel the ARM cores can use.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Channel {
+    C1,
+    C3,
+}
+
+/// Function that's called from the interrupt handler when an alarm goes off, given its channel.
+///
+/// It runs without the driver's lock, but mustn't take it either, since whoever was interrupted
+/// may be holding it.
+pub type Callback = fn(Channel);
+
+/// Reads the system timer's counter. Holds nothing but the address of the registers, so it can be
+/// copied and used without a lock.
+#[derive(Clone, Copy)]
+pub struct BcmSystemCounter {
+    base_address: usize,
+}
+
+// FIXME: This provides shared mutability over the register block. Add some sort of Mutex to
+// regulate that interior mutability.
+pub struct BcmSystemTimer {
+    regs: &'static mut RegisterBlock,
+    counter: BcmSystemCounter,
+    /// The interrupt lines of the four compare channels
+    irqs: [IrqNumber; CHANNEL_COUNT],
+    /// Whether our handler has been registered for the lines of [Channel::C1] and [Channel::C3]
+    irqs_hooked: bool,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Description taken from
+// https://github.com/raspberrypi/documentation/files/1888662/BCM2837-ARM-Peripherals.-.Revised.-.V2-1.pdf
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        /// One bit per channel, set when it matched. Writing a 1 clears it.
+        (0x00 => CS: ReadWrite<u32>),
+        (0x04 => CLO: ReadOnly<u32>),
+        (0x08 => CHI: ReadOnly<u32>),
+        (0x0c => C: [ReadWrite<u32>; CHANNEL_COUNT]),
+        (0x1c => @END),
+    }
+}
+
+const CHANNEL_COUNT: usize = 4;
+
+/// Alarms are set at least this many ticks ahead, so the counter doesn't pass the compare value
+/// before it's written.
+const MIN_DELAY_TICKS: u32 = 2;
+
+/// The channel that's used as a clock event device, which is the one Linux uses too.
+const CLOCK_EVENT_CHANNEL: Channel = Channel::C3;
+
+/// How the timer compares to others, both as a clock source and as a clock event device. The
+/// generic timer is more precise, and doesn't have to be read over the peripheral bus.
+const RATING: u32 = 300;
+
+#[derive(Clone, Copy)]
+struct Alarm {
+    callback: Callback,
+    /// Ticks until the alarm goes off again, or `None` if it only goes off once
+    period: Option<u32>,
+}
+
+/// Everything the interrupt handler needs. This is kept apart from [BcmSystemTimer] so that the
+/// handler doesn't have to wait for whoever holds the driver.
+struct IrqState {
+    base_address: usize,
+    alarms: [Option<Alarm>; CHANNEL_COUNT],
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static IRQ_STATE: IrqSafeSpinMutex<IrqState> = IrqSafeSpinMutex::new_irq_safe(IrqState {
+    base_address: 0,
+    alarms: [None; CHANNEL_COUNT],
+});
+
+/// The handler of the timer that's set through [ClockEvent::program].
+static CLOCK_EVENT_HANDLER: IrqSafeSpinMutex<Option<EventHandler>> =
+    IrqSafeSpinMutex::new_irq_safe(None);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Channel {
+    const ALL: [Channel; 2] = [Channel::C1, Channel::C3];
+
+    fn index(self) -> usize {
+        match self {
+            Channel::C1 => 1,
+            Channel::C3 => 3,
+        }
+    }
+}
+
+/// Convert a delay to ticks, if it fits in a compare register.
+fn ticks(delay: Duration) -> Option<u32> {
+    let ticks = delay.as_micros();
+    u32::try_from(ticks).ok().map(|ticks| ticks.max(MIN_DELAY_TICKS))
+}
+
+impl BcmSystemCounter {
+    fn regs(&self) -> &RegisterBlock {
+        // SAFETY: the address was checked by the creator of the `BcmSystemTimer`
+        unsafe { &*(self.base_address as *const RegisterBlock) }
+    }
+
+    /// Read the whole counter, whose halves can't be read at once.
+    fn ticks(&self) -> u64 {
+        let regs = self.regs();
+        let high = regs.CHI.get();
+        let low = regs.CLO.get();
+        // if the low half wrapped in between, it belongs with the new high half
+        let new_high = regs.CHI.get();
+        if new_high == high {
+            u64::from(high) << 32 | u64::from(low)
+        } else {
+            u64::from(new_high) << 32 | u64::from(regs.CLO.get())
+        }
+    }
+}
+
+impl BcmSystemTimer {
+    /// Register our handler for the lines of [Channel::C1] and [Channel::C3]. If that fails for
+    /// one of them, the other is let go again, so a later call can retry.
+    fn hook_irqs(&mut self) -> Result<(), driver::Error> {
+        for (hooked, channel) in Channel::ALL.iter().enumerate() {
+            if let Err(e) = interrupt::register(self.irqs[channel.index()], handle_irq) {
+                for channel in &Channel::ALL[..hooked] {
+                    interrupt::unregister(self.irqs[channel.index()]);
+                }
+                return Err(e)
+            }
+        }
+        self.irqs_hooked = true;
+        Ok(())
+    }
+
+    fn set_alarm_ticks(
+        &mut self,
+        channel: Channel,
+        ticks: u32,
+        alarm: Alarm,
+    ) -> Result<(), driver::Error> {
+        if !self.irqs_hooked {
+            self.hook_irqs()?;
+        }
+
+        let index = channel.index();
+        let base_address = self.regs as *const RegisterBlock as usize;
+        IRQ_STATE.with_lock(|state| {
+            state.base_address = base_address;
+            state.alarms[index] = Some(alarm);
+            self.regs.C[index].set(self.regs.CLO.get().wrapping_add(ticks));
+            // forget about an earlier match
+            self.regs.CS.set(1 << index);
+        });
+        interrupt::enable(self.irqs[index])
+    }
+}
+
+impl IrqState {
+    fn regs(&self) -> &RegisterBlock {
+        // SAFETY: the address is copied from a `BcmSystemTimer`, whose creator checked it
+        unsafe { &*(self.base_address as *const RegisterBlock) }
+    }
+
+    /// Clear the match of `channel` and find the alarm to run for it, setting the alarm up again
+    /// if it's periodic.
+    fn take_alarm(&mut self, channel: Channel) -> Option<Callback> {
+        if self.base_address == 0 {
+            return None
+        }
+
+        let index = channel.index();
+        let regs = self.regs();
+        if regs.CS.get() & 1 << index == 0 {
+            return None
+        }
+        regs.CS.set(1 << index);
+
+        let alarm = self.alarms[index]?;
+        match alarm.period {
+            // counting from the last compare value, so the period doesn't drift
+            Some(period) => regs.C[index].set(regs.C[index].get().wrapping_add(period)),
+            None => self.alarms[index] = None,
+        }
+        Some(alarm.callback)
+    }
+}
+
+fn handle_irq(_irq: IrqNumber) {
+    for &channel in Channel::ALL.iter() {
+        if let Some(callback) = IRQ_STATE.with_lock(|state| state.take_alarm(channel)) {
+            callback(channel)
+        }
+    }
+}
+
+/// The alarm callback of the clock event channel.
+fn run_clock_event(_channel: Channel) {
+    if let Some(handler) = CLOCK_EVENT_HANDLER.with_lock(|handler| handler.take()) {
+        handler()
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl BcmSystemTimer {
+    /// `irqs` are the interrupt lines of the four compare channels, including the ones the
+    /// VideoCore uses.
+    ///
+    /// # Safety
+    /// The user must verify that the address is the base of the system timer.
+    pub unsafe fn new(base_address: usize, irqs: [IrqNumber; CHANNEL_COUNT]) -> Self {
+        Self {
+            regs: &mut *(base_address as *mut _),
+            counter: BcmSystemCounter { base_address },
+            irqs,
+            irqs_hooked: false,
+        }
+    }
+
+    /// Something that reads the counter without the driver.
+    pub fn counter(&self) -> BcmSystemCounter {
+        self.counter
+    }
+
+    /// Call `callback` from the interrupt handler once `delay` has passed.
+    ///
+    /// The delay can be up to a little over 71 minutes. This replaces any alarm that was already
+    /// set on the channel.
+    pub fn set_alarm(
+        &mut self,
+        channel: Channel,
+        delay: Duration,
+        callback: Callback,
+    ) -> Result<(), driver::Error> {
+        let ticks = ticks(delay).ok_or(driver::Error::BadConfiguration)?;
+        self.set_alarm_ticks(channel, ticks, Alarm { callback, period: None })
+    }
+
+    /// Call `callback` from the interrupt handler every `period`, starting one period from now.
+    ///
+    /// The period has the same limit as the delay of [BcmSystemTimer::set_alarm].
+    pub fn set_periodic(
+        &mut self,
+        channel: Channel,
+        period: Duration,
+        callback: Callback,
+    ) -> Result<(), driver::Error> {
+        let ticks = ticks(period).ok_or(driver::Error::BadConfiguration)?;
+        self.set_alarm_ticks(channel, ticks, Alarm { callback, period: Some(ticks) })
+    }
+
+    /// Stop the alarm on `channel`, if there is one.
+    pub fn cancel(&mut self, channel: Channel) {
+        let index = channel.index();
+        interrupt::disable(self.irqs[index]);
+        IRQ_STATE.with_lock(|state| state.alarms[index] = None);
+        self.regs.CS.set(1 << index);
+    }
+}
+
+impl Driver for BcmSystemTimer {
+    const COMPATIBLE: &'static str = "brcm,bcm2835-system-timer";
+
+    fn shutdown(&mut self) {
+        for &channel in Channel::ALL.iter() {
+            self.cancel(channel);
+        }
+        if self.irqs_hooked {
+            for channel in Channel::ALL.iter() {
+                interrupt::unregister(self.irqs[channel.index()]);
+            }
+            self.irqs_hooked = false;
+        }
+    }
+}
+
+impl SimpleTimer for BcmSystemTimer {
+    fn resolution(&self) -> Duration {
+        self.counter.resolution()
+    }
+
+    fn uptime(&self) -> Duration {
+        self.counter.uptime()
+    }
+
+    fn spin_for(&self, duration: Duration) {
+        self.counter.spin_for(duration)
+    }
+}
+
+impl ClockEvent for BcmSystemTimer {
+    fn name(&self) -> &'static str {
+        "BCM2835 system timer"
+    }
+
+    fn rating(&self) -> u32 {
+        RATING
+    }
+
+    fn max_delay(&self) -> Duration {
+        Duration::from_micros(u32::MAX.into())
+    }
+
+    fn program(&mut self, delay: Duration, handler: EventHandler) -> Result<(), driver::Error> {
+        CLOCK_EVENT_HANDLER.with_lock(|current| *current = Some(handler));
+        self.set_alarm(CLOCK_EVENT_CHANNEL, delay, run_clock_event)
+    }
+
+    fn cancel(&mut self) {
+        BcmSystemTimer::cancel(self, CLOCK_EVENT_CHANNEL);
+        CLOCK_EVENT_HANDLER.with_lock(|current| *current = None);
+    }
+}
+
+impl AsMut<dyn ClockEvent> for BcmSystemTimer {
+    fn as_mut(&mut self) -> &mut (dyn ClockEvent + 'static) {
+        self
+    }
+}
+
+impl SimpleTimer for BcmSystemCounter {
+    fn resolution(&self) -> Duration {
+        Duration::from_micros(1)
+    }
+
+    /// The time since the VideoCore started the counter, which is before the ARM cores start.
+    fn uptime(&self) -> Duration {
+        Duration::from_micros(self.ticks())
+    }
+
+    fn spin_for(&self, duration: Duration) {
+        let ticks = u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+        let deadline = self.ticks().saturating_add(ticks);
+        while self.ticks() < deadline {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+impl ClockSource for BcmSystemCounter {
+    fn name(&self) -> &'static str {
+        "BCM2835 system timer"
+    }
+
+    fn rating(&self) -> u32 {
+        RATING
+    }
+}
@@ -0,0 +1,204 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Driver for the watchdog in the BCM283x power management (PM) block.
+//!
+//! The Pi has no other way to reset itself, so restarting is done by arming the watchdog with a
+//! very short timeout. Turning the Pi off works the same way, except that the reset status
+//! register is first set to ask the firmware to halt rather than boot again. Every write to the
+//! PM registers has to carry a password in the top byte, or it's ignored.
+//!
+//! When it's given a timeout, the driver also arms the watchdog as it's initialized, so that a
+//! kernel that stops calling [crate::watchdog::heartbeat] is reset rather than left hanging. The
+//! watchdog is stopped again when the driver is shut down, but not after a panic that halts, since
+//! that's a hang like any other.
+//!
+//! The PM block remembers that the watchdog reset the board, which is what
+//! [Watchdog::caused_last_reset] reports. Restarting on purpose goes through the watchdog too,
+//! so the kernel's own reboots count as well.
+
+use crate::driver::{self, registry::Registry, traits::Driver};
+use crate::time::{DurationExt, SimpleTimer, NS_PER_SEC};
+use crate::watchdog::Watchdog;
+use crate::{info, warn};
+use core::convert::TryFrom;
+use core::time::Duration;
+use tock_registers::interfaces::{Readable, Writeable};
+use tock_registers::register_structs;
+use tock_registers::registers::ReadWrite;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The driver is `Copy`, so a copy of it can be used without the lock, e.g. by the BSP after a
+/// panic left the lock held.
+#[derive(Clone, Copy)]
+pub struct BcmWatchdog {
+    base_address: usize,
+    /// What the countdown is set to when it's petted, or `None` if the watchdog isn't armed
+    timeout_ticks: Option<u32>,
+    /// How long to arm the watchdog for when the driver is initialized
+    timeout: Option<Duration>,
+    reset_by_watchdog: bool,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Description taken from Linux's bcm2835_wdt.c, since the datasheet leaves the PM block out
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => _reserved),
+        /// Reset control
+        (0x1c => RSTC: ReadWrite<u32>),
+        /// Reset status, which the firmware reads when it boots
+        (0x20 => RSTS: ReadWrite<u32>),
+        /// Watchdog timer, counting down in ticks
+        (0x24 => WDOG: ReadWrite<u32>),
+        (0x28 => @END),
+    }
+}
+
+const PASSWORD: u32 = 0x5a00_0000;
+const PASSWORD_MASK: u32 = 0xff00_0000;
+
+/// The bits of RSTC that configure what happens when the watchdog runs out
+const RSTC_WRCFG_MASK: u32 = 0x30;
+const RSTC_WRCFG_FULL_RESET: u32 = 0x20;
+/// Written to RSTC to stop the watchdog
+const RSTC_RESET: u32 = 0x102;
+
+/// Set when the last reset was done by the watchdog
+const RSTS_HADWRH: u32 = 0x40;
+
+/// Boot partition 63, which the firmware takes as a request to halt. The partition number is
+/// spread over every other bit of RSTS.
+const RSTS_PARTITION_HALT: u32 = 0x555;
+
+/// How many ticks the watchdog gets before it resets the board when we restart. It ticks 65536
+/// times a second, so this is immediate.
+const RESTART_TICKS: u32 = 10;
+
+const TICKS_PER_SEC: u32 = 1 << 16;
+
+/// The bits of WDOG that hold the countdown, which makes the longest timeout just under 16 seconds
+const WDOG_TIME_MASK: u32 = 0x000f_ffff;
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl BcmWatchdog {
+    fn regs(&self) -> &RegisterBlock {
+        // SAFETY: the address was checked by the caller of `new`
+        unsafe { &*(self.base_address as *const RegisterBlock) }
+    }
+
+    /// Convert a timeout to watchdog ticks, if it's one the watchdog can count down from.
+    fn ticks(timeout: Duration) -> Option<u32> {
+        let ticks = timeout.as_nanos() * u128::from(TICKS_PER_SEC) / u128::from(NS_PER_SEC);
+        u32::try_from(ticks).ok().filter(|&ticks| ticks != 0 && ticks <= WDOG_TIME_MASK)
+    }
+
+    /// Start the countdown, resetting the board once it runs out.
+    fn arm(&mut self, ticks: u32) {
+        let regs = self.regs();
+        regs.WDOG.set(PASSWORD | ticks);
+        let control = regs.RSTC.get() & !(PASSWORD_MASK | RSTC_WRCFG_MASK);
+        regs.RSTC.set(PASSWORD | control | RSTC_WRCFG_FULL_RESET);
+        self.timeout_ticks = Some(ticks);
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl BcmWatchdog {
+    /// Create a driver that arms the watchdog with `timeout` when it's initialized, or leaves it
+    /// alone if that's `None`. The timeout can't be longer than about 16 seconds.
+    ///
+    /// # Safety
+    /// The user must verify that the address is the base of the PM block.
+    pub const unsafe fn new(base_address: usize, timeout: Option<Duration>) -> Self {
+        Self { base_address, timeout_ticks: None, timeout, reset_by_watchdog: false }
+    }
+
+    /// Reset the board. This doesn't return, unless the watchdog is broken.
+    pub fn restart(&mut self) {
+        self.arm(RESTART_TICKS);
+
+        // the reset takes a few ticks to happen
+        crate::time::arch_timer().spin_for(Duration::from_millis(1));
+    }
+
+    /// Ask the firmware to halt the board. Like [BcmWatchdog::restart], this only returns on
+    /// failure.
+    ///
+    /// The Pi can't actually cut its own power, so it stays on with everything stopped until it's
+    /// unplugged.
+    pub fn power_off(&mut self) {
+        let regs = self.regs();
+        let status = regs.RSTS.get() & !PASSWORD_MASK;
+        regs.RSTS.set(PASSWORD | status | RSTS_PARTITION_HALT);
+        self.restart();
+    }
+
+    /// Stop the countdown, so the board isn't reset.
+    pub fn stop(&mut self) {
+        self.regs().RSTC.set(PASSWORD | RSTC_RESET);
+        self.timeout_ticks = None;
+    }
+}
+
+impl Driver for BcmWatchdog {
+    const COMPATIBLE: &'static str = "brcm,bcm2835-pm-wdt";
+    const ALSO_COMPATIBLE: &'static [&'static str] = &["brcm,bcm2835-pm", "brcm,bcm2711-pm"];
+
+    fn init(&mut self, _registry: &Registry) -> Result<(), driver::Error> {
+        self.reset_by_watchdog = self.regs().RSTS.get() & RSTS_HADWRH != 0;
+        if self.reset_by_watchdog {
+            warn!("The last reset was caused by the watchdog");
+        }
+
+        if let Some(timeout) = self.timeout {
+            let ticks = Self::ticks(timeout).ok_or(driver::Error::BadConfiguration)?;
+            self.arm(ticks);
+            info!("Armed the watchdog with a timeout of {}", timeout.display_human());
+        }
+        Ok(())
+    }
+
+    fn shutdown(&mut self) {
+        if self.timeout_ticks.is_some() {
+            self.stop();
+        }
+    }
+}
+
+impl Watchdog for BcmWatchdog {
+    fn pet(&mut self) {
+        if let Some(ticks) = self.timeout_ticks {
+            self.regs().WDOG.set(PASSWORD | ticks);
+        }
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        let ticks = self.timeout_ticks?;
+        Some(Duration::from_nanos(u64::from(ticks) * NS_PER_SEC / u64::from(TICKS_PER_SEC)))
+    }
+
+    fn caused_last_reset(&self) -> bool {
+        self.reset_by_watchdog
+    }
+}
+
+impl AsMut<dyn Watchdog> for BcmWatchdog {
+    fn as_mut(&mut self) -> &mut (dyn Watchdog + 'static) {
+        self
+    }
+}
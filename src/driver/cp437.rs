@@ -0,0 +1,76 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Conversion from Unicode to code page 437, the character set built into VGA cards.
+//!
+//! Code page 437 is ASCII plus box drawing characters, accented letters, some Greek letters and
+//! math symbols in the upper half, and a row of symbols in place of the control characters. A few
+//! characters that it lacks, like curly quotes and dashes, are approximated with ASCII.
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The characters shown for bytes 0x80 to 0xff.
+#[rustfmt::skip]
+const UPPER_HALF: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// The symbols shown for bytes 0x01 to 0x1f, which are usually control characters.
+#[rustfmt::skip]
+const CONTROL_GLYPHS: [char; 31] = [
+         '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼',
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+];
+
+/// The symbol shown for byte 0x7f, which is usually DEL.
+const DELETE_GLYPH: char = '⌂';
+
+/// Characters that aren't in the code page, but have a close enough match.
+const ALIASES: [(char, u8); 12] = [
+    ('β', 0xe1),
+    ('μ', 0xe6),
+    ('∑', 0xe4),
+    ('\u{2126}', 0xea), // OHM SIGN
+    ('∈', 0xee),
+    ('‘', b'\''),
+    ('’', b'\''),
+    ('“', b'"'),
+    ('”', b'"'),
+    ('‐', b'-'),
+    ('–', b'-'),
+    ('—', b'-'),
+];
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// The byte that displays `c`, or `None` if code page 437 has nothing like it.
+///
+/// ASCII characters, including the control characters, are returned as they are.
+pub fn encode(c: char) -> Option<u8> {
+    if c.is_ascii() {
+        return Some(c as u8)
+    }
+
+    if let Some(index) = UPPER_HALF.iter().position(|&glyph| glyph == c) {
+        return Some(0x80 + index as u8)
+    }
+    if let Some(index) = CONTROL_GLYPHS.iter().position(|&glyph| glyph == c) {
+        return Some(0x01 + index as u8)
+    }
+    if c == DELETE_GLYPH {
+        return Some(0x7f)
+    }
+    ALIASES.iter().find(|&&(alias, _)| alias == c).map(|&(_, byte)| byte)
+}
@@ -0,0 +1,226 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Text console drawn on a framebuffer that's allocated through the mailbox.
+//!
+//! Text is drawn with the 8x8 font in [font8x8], doubled in size on large displays. Characters
+//! without a glyph are drawn as a box, one per character no matter how many bytes it takes in
+//! UTF-8. Once the cursor runs off the bottom, everything scrolls up by a line.
+
+use crate::driver::font8x8::{self, GLYPH_HEIGHT, GLYPH_WIDTH};
+use crate::driver::framebuffer::{Color, Framebuffer};
+use crate::driver::mailbox::Mailbox;
+use crate::driver::{self, registry::Registry, traits::Driver, WriteError};
+use crate::fmt::Hex;
+use crate::info;
+use crate::sync::SpinMutex;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+pub struct FramebufferConsole {
+    /// The resolution to ask for, or `None` to use the display's
+    size: Option<(u32, u32)>,
+    /// The framebuffer, once the driver is initialized
+    framebuffer: Option<Framebuffer>,
+    /// How many times bigger than the font each glyph is drawn
+    scale: usize,
+    columns: usize,
+    rows: usize,
+    /// Index of the column currently being written to
+    column: usize,
+    /// Index of the row currently being written to
+    row: usize,
+    foreground: Color,
+    background: Color,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The resolution used when the display doesn't report one, e.g. because there isn't a display.
+const DEFAULT_SIZE: (u32, u32) = (640, 480);
+
+/// Displays at least this wide get glyphs that are twice as big.
+const DOUBLE_SCALE_WIDTH: usize = 1280;
+
+const TAB_WIDTH: usize = 8;
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl FramebufferConsole {
+    fn cell_width(&self) -> usize {
+        GLYPH_WIDTH * self.scale
+    }
+
+    fn cell_height(&self) -> usize {
+        GLYPH_HEIGHT * self.scale
+    }
+
+    fn draw_glyph(&mut self, c: char) {
+        let (x, y) = (self.column * self.cell_width(), self.row * self.cell_height());
+        let (scale, foreground, background) = (self.scale, self.foreground, self.background);
+        let framebuffer = match &mut self.framebuffer {
+            Some(framebuffer) => framebuffer,
+            None => return,
+        };
+
+        for (glyph_y, bits) in font8x8::glyph(c).iter().enumerate() {
+            for glyph_x in 0..GLYPH_WIDTH {
+                let color = if bits & (1 << glyph_x) != 0 { foreground } else { background };
+                let (pixel_x, pixel_y) = (x + glyph_x * scale, y + glyph_y * scale);
+                framebuffer.fill_rect(pixel_x, pixel_y, scale, scale, color);
+            }
+        }
+    }
+
+    fn new_line(&mut self) {
+        self.column = 0;
+        self.row += 1;
+        if self.row >= self.rows {
+            self.row = self.rows - 1;
+            let (line_height, background) = (self.cell_height(), self.background);
+            if let Some(framebuffer) = &mut self.framebuffer {
+                framebuffer.scroll_up(line_height, background);
+            }
+        }
+    }
+
+    fn put_char(&mut self, c: char) {
+        match c {
+            '\n' => self.new_line(),
+            '\r' => self.column = 0,
+            '\t' => {
+                let next_stop = (self.column / TAB_WIDTH + 1) * TAB_WIDTH;
+                while self.column < next_stop.min(self.columns) {
+                    self.draw_glyph(' ');
+                    self.column += 1;
+                }
+                if self.column >= self.columns {
+                    self.new_line();
+                }
+            }
+            '\x08' => self.column = self.column.saturating_sub(1),
+            c if c.is_control() => {}
+            c => {
+                self.draw_glyph(c);
+                self.column += 1;
+                if self.column >= self.columns {
+                    self.new_line();
+                }
+            }
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl FramebufferConsole {
+    /// `size` is the resolution to ask the firmware for, or `None` to use the display's.
+    pub const fn new(size: Option<(u32, u32)>) -> Self {
+        Self {
+            size,
+            framebuffer: None,
+            scale: 1,
+            columns: 0,
+            rows: 0,
+            column: 0,
+            row: 0,
+            foreground: Color::LIGHT_GREY,
+            background: Color::BLACK,
+        }
+    }
+
+    /// Change the colors of the text that's written from now on.
+    pub fn set_colors(&mut self, foreground: Color, background: Color) {
+        self.foreground = foreground;
+        self.background = background;
+    }
+
+    /// Fill the screen with the background color and move the cursor to the top left.
+    pub fn clear(&mut self) {
+        let background = self.background;
+        if let Some(framebuffer) = &mut self.framebuffer {
+            framebuffer.fill_rect(0, 0, framebuffer.width(), framebuffer.height(), background);
+        }
+        self.column = 0;
+        self.row = 0;
+    }
+}
+
+impl ufmt::uWrite for FramebufferConsole {
+    type Error = WriteError;
+
+    fn write_str(&mut self, s: &str) -> Result<(), Self::Error> {
+        if self.framebuffer.is_none() {
+            return Err(WriteError::NotReady)
+        }
+
+        s.chars().for_each(|c| self.put_char(c));
+        Ok(())
+    }
+
+    fn write_char(&mut self, c: char) -> Result<(), Self::Error> {
+        if self.framebuffer.is_none() {
+            return Err(WriteError::NotReady)
+        }
+
+        self.put_char(c);
+        Ok(())
+    }
+}
+
+impl Driver for FramebufferConsole {
+    const COMPATIBLE: &'static str = "brcm,bcm2708-fb";
+
+    fn dependencies(&self) -> &'static [&'static str] {
+        &[Mailbox::COMPATIBLE]
+    }
+
+    fn init(&mut self, registry: &Registry) -> Result<(), driver::Error> {
+        let mailbox = registry.get::<SpinMutex<Mailbox>>().ok_or(driver::Error::Unavailable)?;
+        let info = mailbox.with_lock(|mailbox| {
+            let (width, height) = match self.size {
+                Some(size) => size,
+                None => match mailbox.physical_size() {
+                    Ok((width, height)) if width != 0 && height != 0 => (width, height),
+                    _ => DEFAULT_SIZE,
+                },
+            };
+            mailbox.allocate_framebuffer(width, height)
+        })?;
+
+        // SAFETY: the firmware just allocated this framebuffer for us
+        let framebuffer = unsafe { Framebuffer::new(&info)? };
+        info!(
+            "Framebuffer: {}x{} pixels at {}",
+            framebuffer.width(),
+            framebuffer.height(),
+            Hex(framebuffer.base_address())
+        );
+
+        self.scale = if framebuffer.width() >= DOUBLE_SCALE_WIDTH { 2 } else { 1 };
+        self.columns = framebuffer.width() / (GLYPH_WIDTH * self.scale);
+        self.rows = framebuffer.height() / (GLYPH_HEIGHT * self.scale);
+        if self.columns == 0 || self.rows == 0 {
+            return Err(driver::Error::BadConfiguration)
+        }
+
+        self.framebuffer = Some(framebuffer);
+        self.clear();
+        Ok(())
+    }
+}
+
+impl AsMut<dyn ufmt::uWrite<Error=WriteError>> for FramebufferConsole {
+    fn as_mut(&mut self) -> &mut (dyn ufmt::uWrite<Error=WriteError> + 'static) {
+        self
+    }
+}
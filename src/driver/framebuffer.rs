@@ -0,0 +1,157 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! A linear framebuffer with 32 bits per pixel, allocated by the VideoCore firmware.
+//!
+//! Pixels are written one word at a time with volatile accesses. The MMU is still off, so the
+//! framebuffer is device memory, where the wider or unaligned accesses `memcpy` likes to make
+//! would fault.
+
+use crate::driver;
+use crate::driver::mailbox::{FramebufferInfo, PixelOrder};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A 24-bit color.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Color {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+pub struct Framebuffer {
+    base: *mut u32,
+    width: usize,
+    height: usize,
+    /// Distance between the starts of two rows, in pixels
+    stride: usize,
+    pixel_order: PixelOrder,
+}
+
+// SAFETY: the framebuffer belongs to whoever owns this, like a driver's register block
+unsafe impl Send for Framebuffer {}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The VideoCore sees RAM through an alias in the top two bits of its bus addresses, which the ARM
+/// cores don't use.
+const BUS_ADDRESS_MASK: u32 = 0x3fff_ffff;
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Framebuffer {
+    fn encode(&self, color: Color) -> u32 {
+        let red = u32::from(color.red);
+        let green = u32::from(color.green);
+        let blue = u32::from(color.blue);
+        match self.pixel_order {
+            PixelOrder::Bgr => red << 16 | green << 8 | blue,
+            PixelOrder::Rgb => blue << 16 | green << 8 | red,
+        }
+    }
+
+    fn pixel_ptr(&self, x: usize, y: usize) -> *mut u32 {
+        // SAFETY: callers keep x and y inside the visible area, which is inside the allocation
+        unsafe { self.base.add(y * self.stride + x) }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Color {
+    pub const BLACK: Self = Self::new(0x00, 0x00, 0x00);
+    pub const RED: Self = Self::new(0xaa, 0x00, 0x00);
+    pub const GREEN: Self = Self::new(0x00, 0xaa, 0x00);
+    pub const YELLOW: Self = Self::new(0xaa, 0xaa, 0x00);
+    pub const BLUE: Self = Self::new(0x00, 0x00, 0xaa);
+    pub const MAGENTA: Self = Self::new(0xaa, 0x00, 0xaa);
+    pub const CYAN: Self = Self::new(0x00, 0xaa, 0xaa);
+    pub const LIGHT_GREY: Self = Self::new(0xaa, 0xaa, 0xaa);
+    pub const WHITE: Self = Self::new(0xff, 0xff, 0xff);
+
+    pub const fn new(red: u8, green: u8, blue: u8) -> Self {
+        Self { red, green, blue }
+    }
+}
+
+impl Framebuffer {
+    /// # Safety
+    /// `info` must describe a framebuffer that the firmware allocated, and that nothing else is
+    /// using.
+    pub unsafe fn new(info: &FramebufferInfo) -> Result<Self, driver::Error> {
+        if info.depth != 32 || info.pitch % 4 != 0 || info.pitch / 4 < info.width {
+            return Err(driver::Error::BadConfiguration)
+        }
+        if (info.pitch as usize) * (info.height as usize) > info.size as usize {
+            return Err(driver::Error::BadConfiguration)
+        }
+
+        Ok(Self {
+            base: (info.bus_address & BUS_ADDRESS_MASK) as usize as *mut u32,
+            width: info.width as usize,
+            height: info.height as usize,
+            stride: info.pitch as usize / 4,
+            pixel_order: info.pixel_order,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Address of the first pixel, e.g. for dumping the framebuffer from a debugger.
+    pub fn base_address(&self) -> usize {
+        self.base as usize
+    }
+
+    /// Set a single pixel. Pixels outside of the framebuffer are ignored.
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: Color) {
+        if x < self.width && y < self.height {
+            let pixel = self.encode(color);
+            // SAFETY: the pixel is inside the framebuffer
+            unsafe { self.pixel_ptr(x, y).write_volatile(pixel) }
+        }
+    }
+
+    /// Fill a rectangle, clipped to the framebuffer.
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: Color) {
+        let pixel = self.encode(color);
+        let x_end = (x + width).min(self.width);
+        let y_end = (y + height).min(self.height);
+        for row in y..y_end {
+            for column in x..x_end {
+                // SAFETY: the pixel is inside the framebuffer
+                unsafe { self.pixel_ptr(column, row).write_volatile(pixel) }
+            }
+        }
+    }
+
+    /// Move everything up by `rows` pixels, filling the rows that are uncovered at the bottom.
+    pub fn scroll_up(&mut self, rows: usize, fill: Color) {
+        let rows = rows.min(self.height);
+        for y in 0..self.height - rows {
+            for x in 0..self.width {
+                // SAFETY: both pixels are inside the framebuffer
+                unsafe {
+                    let pixel = self.pixel_ptr(x, y + rows).read_volatile();
+                    self.pixel_ptr(x, y).write_volatile(pixel);
+                }
+            }
+        }
+        self.fill_rect(0, self.height - rows, self.width, rows, fill);
+    }
+}
@@ -0,0 +1,271 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Driver for the ARM Generic Interrupt Controller v2, e.g. the GIC-400 on the BCM2711 or the one
+//! emulated by QEMU's `virt` machine.
+//!
+//! The GIC is split into a distributor, which is shared between all cores and decides where each
+//! interrupt goes, and a CPU interface for every core, which is where interrupts are acknowledged.
+//! Lines are numbered by their interrupt ID: 0-15 are software generated, 16-31 are private to each
+//! core and 32 onwards are the shared peripheral interrupts (SPIs).
+//!
+//! Only the boot core receives interrupts for now.
+
+use crate::driver::{self, registry::Registry, traits::Driver};
+use crate::fdt::Cells;
+use crate::interrupt::{InterruptController, IrqNumber};
+use tock_registers::interfaces::{Readable, Writeable};
+use tock_registers::registers::{ReadOnly, ReadWrite, WriteOnly};
+use tock_registers::{register_bitfields, register_structs};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Description taken from the ARM Generic Interrupt Controller Architecture Specification, version
+// 2.0, and the CoreLink GIC-400 Technical Reference Manual.
+register_bitfields! {
+    u32,
+
+    /// Distributor Control Register
+    GICD_CTLR [
+        /// Forward group 1 interrupts to the CPU interfaces. When accessed from the non-secure
+        /// state, this is the only enable bit and it lives in bit 0 instead.
+        EnableGrp1 OFFSET(1) NUMBITS(1) [],
+        /// Forward group 0 interrupts to the CPU interfaces.
+        EnableGrp0 OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// Interrupt Controller Type Register
+    GICD_TYPER [
+        /// The distributor supports 32 * (ITLinesNumber + 1) interrupt IDs.
+        ITLinesNumber OFFSET(0) NUMBITS(5) []
+    ],
+
+    /// CPU Interface Control Register
+    GICC_CTLR [
+        /// Signal group 1 interrupts to the core. Like `GICD_CTLR`, this moves to bit 0 when
+        /// accessed from the non-secure state.
+        EnableGrp1 OFFSET(1) NUMBITS(1) [],
+        /// Signal group 0 interrupts to the core.
+        EnableGrp0 OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// Interrupt Priority Mask Register
+    GICC_PMR [
+        /// Only interrupts with a higher priority (lower value) than this are signalled.
+        Priority OFFSET(0) NUMBITS(8) []
+    ],
+
+    /// Interrupt Acknowledge Register
+    GICC_IAR [
+        /// For software generated interrupts, the core that requested the interrupt.
+        CPUID OFFSET(10) NUMBITS(3) [],
+        /// The ID of the interrupt that is now active.
+        InterruptID OFFSET(0) NUMBITS(10) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    DistributorRegisterBlock {
+        (0x000 => CTLR: ReadWrite<u32, GICD_CTLR::Register>),
+        (0x004 => TYPER: ReadOnly<u32, GICD_TYPER::Register>),
+        (0x008 => _reserved0),
+        (0x100 => ISENABLER: [ReadWrite<u32>; 32]),
+        (0x180 => ICENABLER: [ReadWrite<u32>; 32]),
+        (0x200 => _reserved1),
+        (0x280 => ICPENDR: [ReadWrite<u32>; 32]),
+        (0x300 => _reserved2),
+        (0x380 => ICACTIVER: [ReadWrite<u32>; 32]),
+        (0x400 => IPRIORITYR: [ReadWrite<u32>; 255]),
+        (0x7fc => _reserved3),
+        (0x800 => ITARGETSR: [ReadWrite<u32>; 255]),
+        (0xbfc => _reserved4),
+        (0xc00 => ICFGR: [ReadWrite<u32>; 64]),
+        (0xd00 => @END),
+    }
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    CpuInterfaceRegisterBlock {
+        (0x00 => CTLR: ReadWrite<u32, GICC_CTLR::Register>),
+        (0x04 => PMR: ReadWrite<u32, GICC_PMR::Register>),
+        (0x08 => BPR: ReadWrite<u32>),
+        (0x0c => IAR: ReadOnly<u32, GICC_IAR::Register>),
+        (0x10 => EOIR: WriteOnly<u32>),
+        (0x14 => @END),
+    }
+}
+
+/// The first shared peripheral interrupt. Everything below this is banked per core.
+const FIRST_SPI: IrqNumber = 32;
+
+/// IDs from here up are special values, e.g. 1023 when there's nothing pending.
+const FIRST_SPECIAL_ID: IrqNumber = 1020;
+
+/// Priority given to every interrupt. They're all equal, so none preempt another.
+const DEFAULT_PRIORITY: u32 = 0xa0;
+
+/// Interrupt types used in device tree specifiers.
+const SPECIFIER_SPI: u32 = 0;
+const SPECIFIER_PPI: u32 = 1;
+
+/// Value for `ITARGETSR` that sends an interrupt to core 0.
+const TARGET_CORE_0: u32 = 0x01;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+// FIXME: This provides shared mutability over the register blocks. Add some sort of Mutex to
+// regulate that interior mutability.
+pub struct Gicv2 {
+    gicd: &'static mut DistributorRegisterBlock,
+    gicc: &'static mut CpuInterfaceRegisterBlock,
+    /// Number of interrupt IDs the distributor implements
+    irq_count: IrqNumber,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Set the byte that belongs to `irq` in a register array that holds four interrupts per word.
+fn set_byte_field(regs: &[ReadWrite<u32>], irq: IrqNumber, value: u32) {
+    let reg = &regs[irq / 4];
+    let shift = 8 * (irq % 4);
+    reg.set(reg.get() & !(0xff << shift) | value << shift);
+}
+
+impl Gicv2 {
+    fn init_distributor(&mut self) {
+        self.gicd.CTLR.set(0);
+
+        // Route all of the shared interrupts to core 0 as level-sensitive, which is how the
+        // peripherals we drive signal them. The banked registers for the lower IDs are set up per
+        // core, in `init_cpu_interface`.
+        let first_word = FIRST_SPI / 32;
+        let words = self.irq_count / 32;
+        for word in first_word..words {
+            self.gicd.ICENABLER[word].set(u32::MAX);
+            self.gicd.ICPENDR[word].set(u32::MAX);
+            self.gicd.ICACTIVER[word].set(u32::MAX);
+        }
+        for irq in FIRST_SPI..self.irq_count {
+            set_byte_field(&self.gicd.IPRIORITYR, irq, DEFAULT_PRIORITY);
+            set_byte_field(&self.gicd.ITARGETSR, irq, TARGET_CORE_0);
+        }
+        for word in (FIRST_SPI / 16)..(self.irq_count / 16) {
+            self.gicd.ICFGR[word].set(0);
+        }
+
+        // The firmware's stub normally moves every interrupt to group 1 and hands us the
+        // non-secure view, where group 1 is enabled through bit 0. Setting both bits covers either
+        // case.
+        self.gicd.CTLR.write(GICD_CTLR::EnableGrp0::SET + GICD_CTLR::EnableGrp1::SET);
+    }
+
+    fn init_cpu_interface(&mut self) {
+        for irq in 0..FIRST_SPI {
+            set_byte_field(&self.gicd.IPRIORITYR, irq, DEFAULT_PRIORITY);
+        }
+
+        // let through every priority and don't split them into preemption groups
+        self.gicc.PMR.write(GICC_PMR::Priority.val(0xff));
+        self.gicc.BPR.set(0);
+        self.gicc.CTLR.write(GICC_CTLR::EnableGrp0::SET + GICC_CTLR::EnableGrp1::SET);
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Gicv2 {
+    /// # Safety
+    /// The user must verify that the addresses for the distributor and the CPU interface register
+    /// blocks are correct.
+    pub unsafe fn new(gicd_base_address: usize, gicc_base_address: usize) -> Self {
+        let mut controller = Self {
+            gicd: &mut *(gicd_base_address as *mut _),
+            gicc: &mut *(gicc_base_address as *mut _),
+            irq_count: 0,
+        };
+
+        let lines = controller.gicd.TYPER.read(GICD_TYPER::ITLinesNumber) as IrqNumber;
+        controller.irq_count = (32 * (lines + 1)).min(FIRST_SPECIAL_ID);
+        controller
+    }
+}
+
+impl InterruptController for Gicv2 {
+    fn enable(&mut self, irq: IrqNumber) -> Result<(), driver::Error> {
+        if irq >= self.irq_count {
+            return Err(driver::Error::BadConfiguration)
+        }
+
+        self.gicd.ISENABLER[irq / 32].set(1 << (irq % 32));
+        Ok(())
+    }
+
+    fn disable(&mut self, irq: IrqNumber) {
+        if irq >= self.irq_count {
+            return
+        }
+
+        self.gicd.ICENABLER[irq / 32].set(1 << (irq % 32));
+    }
+
+    fn acknowledge(&mut self) -> Option<IrqNumber> {
+        let irq = self.gicc.IAR.read(GICC_IAR::InterruptID) as IrqNumber;
+        if irq >= FIRST_SPECIAL_ID {
+            // special IDs never need an end of interrupt
+            None
+        } else {
+            Some(irq)
+        }
+    }
+
+    fn end_of_interrupt(&mut self, irq: IrqNumber) {
+        // Software generated interrupts would also need the CPUID they were acknowledged with, but
+        // nothing sends those yet.
+        self.gicc.EOIR.set(irq as u32);
+    }
+
+    /// Specifiers are the interrupt type, the number within that type and some flags.
+    fn translate(&self, mut specifier: Cells<'_>) -> Option<IrqNumber> {
+        let (kind, number) = (specifier.next()?, specifier.next()? as IrqNumber);
+        let irq = match kind {
+            SPECIFIER_SPI => FIRST_SPI + number,
+            SPECIFIER_PPI if number < 16 => 16 + number,
+            _ => return None,
+        };
+        (irq < self.irq_count).then(|| irq)
+    }
+}
+
+impl Driver for Gicv2 {
+    const COMPATIBLE: &'static str = "arm,gic-400";
+    const ALSO_COMPATIBLE: &'static [&'static str] = &["arm,cortex-a15-gic"];
+
+    fn init(&mut self, _registry: &Registry) -> Result<(), driver::Error> {
+        // start from a known state, where nothing reaches the CPU
+        self.init_distributor();
+        self.init_cpu_interface();
+        Ok(())
+    }
+
+    fn shutdown(&mut self) {
+        self.gicc.CTLR.set(0);
+        self.gicd.CTLR.set(0);
+    }
+}
+
+impl AsMut<dyn InterruptController> for Gicv2 {
+    fn as_mut(&mut self) -> &mut (dyn InterruptController + 'static) {
+        self
+    }
+}
@@ -0,0 +1,413 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Driver for the ARM Generic Interrupt Controller v3, e.g. the one emulated by QEMU's `virt`
+//! machine when it's started with `gic-version=3`.
+//!
+//! Like the GICv2, there's a distributor that's shared between all cores and routes the shared
+//! peripheral interrupts (SPIs, IDs 32 onwards). The lines that are private to a core (IDs 0-31)
+//! are configured in that core's redistributor instead, and the CPU interface is reached through
+//! system registers rather than memory. The redistributors sit next to each other in one region,
+//! and the one for a core is found by its affinity.
+//!
+//! Only the boot core receives interrupts for now, and everything is in the non-secure group 1.
+
+use crate::driver::{self, registry::Registry, traits::Driver};
+use crate::fdt::Cells;
+use crate::interrupt::{InterruptController, IrqNumber};
+use cortex_a::registers::MPIDR_EL1;
+use tock_registers::interfaces::{Readable, Writeable};
+use tock_registers::registers::{ReadOnly, ReadWrite};
+use tock_registers::{register_bitfields, register_structs};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Description taken from the ARM Generic Interrupt Controller Architecture Specification, GIC
+// architecture version 3 and version 4.
+register_bitfields! {
+    u32,
+
+    /// Distributor Control Register. The bits are named as in the non-secure view; with a single
+    /// security state, bit 0 enables group 0 and bit 1 group 1 instead.
+    GICD_CTLR [
+        /// A write to the register is still being applied.
+        RWP OFFSET(31) NUMBITS(1) [],
+        /// Route interrupts by affinity, which is what makes the redistributors and `IROUTER` work.
+        ARE_NS OFFSET(4) NUMBITS(1) [],
+        /// Forward group 1 interrupts to the redistributors.
+        EnableGrp1A OFFSET(1) NUMBITS(1) [],
+        /// Forward group 1 interrupts, or group 0 with a single security state.
+        EnableGrp1 OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// Interrupt Controller Type Register
+    GICD_TYPER [
+        /// The distributor supports 32 * (ITLinesNumber + 1) interrupt IDs.
+        ITLinesNumber OFFSET(0) NUMBITS(5) []
+    ],
+
+    /// Redistributor Wake Register
+    GICR_WAKER [
+        /// The interface to the core is quiescent.
+        ChildrenAsleep OFFSET(2) NUMBITS(1) [],
+        /// The core is asleep, so no interrupts are forwarded to it.
+        ProcessorSleep OFFSET(1) NUMBITS(1) []
+    ]
+}
+
+register_bitfields! {
+    u64,
+
+    /// Redistributor Type Register
+    GICR_TYPER [
+        /// The affinity of the core this redistributor belongs to, as Aff3.Aff2.Aff1.Aff0.
+        AffinityValue OFFSET(32) NUMBITS(32) [],
+        /// This is the last redistributor in the region.
+        Last OFFSET(4) NUMBITS(1) [],
+        /// The redistributor has the extra frames for virtual LPIs.
+        VLPIS OFFSET(1) NUMBITS(1) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    DistributorRegisterBlock {
+        (0x0000 => CTLR: ReadWrite<u32, GICD_CTLR::Register>),
+        (0x0004 => TYPER: ReadOnly<u32, GICD_TYPER::Register>),
+        (0x0008 => _reserved0),
+        (0x0080 => IGROUPR: [ReadWrite<u32>; 32]),
+        (0x0100 => ISENABLER: [ReadWrite<u32>; 32]),
+        (0x0180 => ICENABLER: [ReadWrite<u32>; 32]),
+        (0x0200 => _reserved1),
+        (0x0280 => ICPENDR: [ReadWrite<u32>; 32]),
+        (0x0300 => _reserved2),
+        (0x0380 => ICACTIVER: [ReadWrite<u32>; 32]),
+        (0x0400 => IPRIORITYR: [ReadWrite<u32>; 255]),
+        (0x07fc => _reserved3),
+        (0x0c00 => ICFGR: [ReadWrite<u32>; 64]),
+        (0x0d00 => _reserved4),
+        /// One per interrupt ID, of which the first 32 are reserved
+        (0x6000 => IROUTER: [ReadWrite<u64>; 1020]),
+        (0x7fe0 => @END),
+    }
+}
+
+// The RD_base frame, followed by the SGI_base frame that holds the registers for IDs 0-31.
+register_structs! {
+    #[allow(non_snake_case)]
+    RedistributorRegisterBlock {
+        (0x00000 => _reserved0),
+        (0x00008 => TYPER: ReadOnly<u64, GICR_TYPER::Register>),
+        (0x00010 => _reserved1),
+        (0x00014 => WAKER: ReadWrite<u32, GICR_WAKER::Register>),
+        (0x00018 => _reserved2),
+        (0x10080 => IGROUPR0: ReadWrite<u32>),
+        (0x10084 => _reserved3),
+        (0x10100 => ISENABLER0: ReadWrite<u32>),
+        (0x10104 => _reserved4),
+        (0x10180 => ICENABLER0: ReadWrite<u32>),
+        (0x10184 => _reserved5),
+        (0x10280 => ICPENDR0: ReadWrite<u32>),
+        (0x10284 => _reserved6),
+        (0x10380 => ICACTIVER0: ReadWrite<u32>),
+        (0x10384 => _reserved7),
+        (0x10400 => IPRIORITYR: [ReadWrite<u32>; 8]),
+        (0x10420 => _reserved8),
+        (0x20000 => @END),
+    }
+}
+
+/// Size of a redistributor with its RD_base and SGI_base frames.
+const REDISTRIBUTOR_STRIDE: usize = 0x2_0000;
+/// Size of a redistributor that also has the two frames for virtual LPIs.
+const REDISTRIBUTOR_STRIDE_VLPI: usize = 0x4_0000;
+
+/// The first shared peripheral interrupt. Everything below this is handled by the redistributors.
+const FIRST_SPI: IrqNumber = 32;
+
+/// IDs from here up are special values, e.g. 1023 when there's nothing pending.
+const FIRST_SPECIAL_ID: IrqNumber = 1020;
+
+/// `ICC_IAR1_EL1` holds the ID in its low 24 bits.
+const INTID_MASK: u64 = 0xff_ffff;
+
+/// Priority given to every interrupt. They're all equal, so none preempt another.
+const DEFAULT_PRIORITY: u32 = 0xa0;
+
+/// Interrupt types used in device tree specifiers.
+const SPECIFIER_SPI: u32 = 0;
+const SPECIFIER_PPI: u32 = 1;
+
+/// `ICC_SRE_EL1.SRE`, which switches the CPU interface over to system registers.
+const ICC_SRE_SRE: u64 = 1 << 0;
+
+/// `ICC_IGRPEN1_EL1.Enable`
+const ICC_IGRPEN1_ENABLE: u64 = 1 << 0;
+
+/// How often to check whether the distributor or redistributor caught up before giving up.
+const POLL_LIMIT: usize = 1_000_000;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+// FIXME: This provides shared mutability over the register blocks. Add some sort of Mutex to
+// regulate that interior mutability.
+pub struct Gicv3 {
+    gicd: &'static mut DistributorRegisterBlock,
+    /// Start of the region that holds the redistributors of all cores
+    gicr_base_address: usize,
+    /// The boot core's redistributor, once probing found it
+    gicr: Option<&'static mut RedistributorRegisterBlock>,
+    /// Number of interrupt IDs the distributor implements
+    irq_count: IrqNumber,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Set the byte that belongs to `irq` in a register array that holds four interrupts per word.
+fn set_byte_field(regs: &[ReadWrite<u32>], irq: IrqNumber, value: u32) {
+    let reg = &regs[irq / 4];
+    let shift = 8 * (irq % 4);
+    reg.set(reg.get() & !(0xff << shift) | value << shift);
+}
+
+/// Wait until `done` returns true, or fail with [driver::Error::Timeout].
+fn poll(mut done: impl FnMut() -> bool) -> Result<(), driver::Error> {
+    for _ in 0..POLL_LIMIT {
+        if done() {
+            return Ok(())
+        }
+        core::hint::spin_loop();
+    }
+    Err(driver::Error::Timeout)
+}
+
+/// The affinity of the current core, laid out as in `GICR_TYPER` and `GICD_IROUTER`.
+fn affinity() -> u64 {
+    let mpidr = MPIDR_EL1.get();
+    // Aff3 sits above the other fields in MPIDR, but right next to them in the GIC's registers
+    (mpidr >> 8 & 0xff00_0000) | (mpidr & 0x00ff_ffff)
+}
+
+impl Gicv3 {
+    /// Find the redistributor that belongs to the current core.
+    fn find_redistributor(&self) -> Option<&'static mut RedistributorRegisterBlock> {
+        let affinity = affinity();
+        let mut address = self.gicr_base_address;
+        loop {
+            // SAFETY: the creator checked the region's address, and `Last` marks where it ends
+            let gicr = unsafe { &mut *(address as *mut RedistributorRegisterBlock) };
+            if gicr.TYPER.read(GICR_TYPER::AffinityValue) == affinity {
+                return Some(gicr)
+            }
+            if gicr.TYPER.is_set(GICR_TYPER::Last) {
+                return None
+            }
+            address += if gicr.TYPER.is_set(GICR_TYPER::VLPIS) {
+                REDISTRIBUTOR_STRIDE_VLPI
+            } else {
+                REDISTRIBUTOR_STRIDE
+            };
+        }
+    }
+
+    fn wait_for_distributor(&self) -> Result<(), driver::Error> {
+        poll(|| !self.gicd.CTLR.is_set(GICD_CTLR::RWP))
+    }
+
+    fn init_distributor(&mut self) -> Result<(), driver::Error> {
+        self.gicd.CTLR.set(0);
+        self.wait_for_distributor()?;
+
+        // Route all of the shared interrupts to the boot core as level-sensitive group 1
+        // interrupts, which is how the peripherals we drive signal them.
+        let first_word = FIRST_SPI / 32;
+        let words = self.irq_count / 32;
+        for word in first_word..words {
+            self.gicd.ICENABLER[word].set(u32::MAX);
+            self.gicd.ICPENDR[word].set(u32::MAX);
+            self.gicd.ICACTIVER[word].set(u32::MAX);
+            self.gicd.IGROUPR[word].set(u32::MAX);
+        }
+        for irq in FIRST_SPI..self.irq_count {
+            set_byte_field(&self.gicd.IPRIORITYR, irq, DEFAULT_PRIORITY);
+        }
+        for word in (FIRST_SPI / 16)..(self.irq_count / 16) {
+            self.gicd.ICFGR[word].set(0);
+        }
+        self.wait_for_distributor()?;
+
+        // affinity routing has to be on before IROUTER means anything
+        self.gicd.CTLR.write(GICD_CTLR::ARE_NS::SET);
+        self.wait_for_distributor()?;
+        let affinity = affinity();
+        for irq in FIRST_SPI..self.irq_count {
+            self.gicd.IROUTER[irq].set(affinity);
+        }
+
+        // Setting both group enables covers the non-secure view and a GIC with a single security
+        // state.
+        self.gicd.CTLR.write(
+            GICD_CTLR::ARE_NS::SET + GICD_CTLR::EnableGrp1A::SET + GICD_CTLR::EnableGrp1::SET,
+        );
+        self.wait_for_distributor()
+    }
+
+    fn init_redistributor(&mut self) -> Result<(), driver::Error> {
+        let gicr = self.gicr.as_mut().ok_or(driver::Error::NotPresent)?;
+
+        // wake the redistributor up, so it forwards interrupts to the core
+        gicr.WAKER.write(GICR_WAKER::ProcessorSleep::CLEAR);
+        poll(|| !gicr.WAKER.is_set(GICR_WAKER::ChildrenAsleep))?;
+
+        gicr.ICENABLER0.set(u32::MAX);
+        gicr.ICPENDR0.set(u32::MAX);
+        gicr.ICACTIVER0.set(u32::MAX);
+        gicr.IGROUPR0.set(u32::MAX);
+        for irq in 0..FIRST_SPI {
+            set_byte_field(&gicr.IPRIORITYR, irq, DEFAULT_PRIORITY);
+        }
+        Ok(())
+    }
+
+    fn init_cpu_interface(&mut self) -> Result<(), driver::Error> {
+        // SAFETY: switching to the system register interface only changes how the GIC is reached,
+        // and nothing uses the memory-mapped one
+        let sre = unsafe {
+            asm!("msr icc_sre_el1, {}", "isb", in(reg) ICC_SRE_SRE, options(nostack));
+            let sre: u64;
+            asm!("mrs {}, icc_sre_el1", out(reg) sre, options(nostack));
+            sre
+        };
+        // the bit sticks at 0 if a higher exception level didn't let us use the system registers
+        if sre & ICC_SRE_SRE == 0 {
+            return Err(driver::Error::NotPresent)
+        }
+
+        // let through every priority and don't split them into preemption groups
+        // SAFETY: the system register interface is enabled
+        unsafe {
+            asm!("msr icc_pmr_el1, {}", in(reg) 0xff_u64, options(nostack));
+            asm!("msr icc_bpr1_el1, {}", in(reg) 0_u64, options(nostack));
+            asm!("msr icc_igrpen1_el1, {}", "isb", in(reg) ICC_IGRPEN1_ENABLE, options(nostack));
+        }
+        Ok(())
+    }
+
+    /// Mask and unmask lines in the distributor or the redistributor, depending on the ID.
+    fn enable_bit(&mut self, irq: IrqNumber, enable: bool) {
+        let bit = 1 << (irq % 32);
+        if irq >= FIRST_SPI {
+            let regs = if enable { &self.gicd.ISENABLER } else { &self.gicd.ICENABLER };
+            regs[irq / 32].set(bit);
+        } else if let Some(gicr) = self.gicr.as_ref() {
+            let reg = if enable { &gicr.ISENABLER0 } else { &gicr.ICENABLER0 };
+            reg.set(bit);
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Gicv3 {
+    /// # Safety
+    /// The user must verify that the addresses for the distributor and the redistributor region
+    /// are correct.
+    pub unsafe fn new(gicd_base_address: usize, gicr_base_address: usize) -> Self {
+        let mut controller = Self {
+            gicd: &mut *(gicd_base_address as *mut _),
+            gicr_base_address,
+            gicr: None,
+            irq_count: 0,
+        };
+
+        let lines = controller.gicd.TYPER.read(GICD_TYPER::ITLinesNumber) as IrqNumber;
+        controller.irq_count = (32 * (lines + 1)).min(FIRST_SPECIAL_ID);
+        controller
+    }
+}
+
+impl InterruptController for Gicv3 {
+    fn enable(&mut self, irq: IrqNumber) -> Result<(), driver::Error> {
+        if irq >= self.irq_count {
+            return Err(driver::Error::BadConfiguration)
+        }
+
+        self.enable_bit(irq, true);
+        Ok(())
+    }
+
+    fn disable(&mut self, irq: IrqNumber) {
+        if irq >= self.irq_count {
+            return
+        }
+
+        self.enable_bit(irq, false);
+    }
+
+    fn acknowledge(&mut self) -> Option<IrqNumber> {
+        let iar: u64;
+        // SAFETY: the system register interface was enabled by `init`
+        unsafe { asm!("mrs {}, icc_iar1_el1", out(reg) iar, options(nostack)) };
+        let irq = (iar & INTID_MASK) as IrqNumber;
+        if irq >= FIRST_SPECIAL_ID {
+            // special IDs never need an end of interrupt
+            None
+        } else {
+            Some(irq)
+        }
+    }
+
+    fn end_of_interrupt(&mut self, irq: IrqNumber) {
+        // SAFETY: the system register interface was enabled by `init`
+        unsafe { asm!("msr icc_eoir1_el1, {}", in(reg) irq as u64, options(nostack)) };
+    }
+
+    /// Specifiers are the interrupt type, the number within that type and some flags.
+    fn translate(&self, mut specifier: Cells<'_>) -> Option<IrqNumber> {
+        let (kind, number) = (specifier.next()?, specifier.next()? as IrqNumber);
+        let irq = match kind {
+            SPECIFIER_SPI => FIRST_SPI + number,
+            SPECIFIER_PPI if number < 16 => 16 + number,
+            _ => return None,
+        };
+        (irq < self.irq_count).then(|| irq)
+    }
+}
+
+impl Driver for Gicv3 {
+    const COMPATIBLE: &'static str = "arm,gic-v3";
+
+    fn probe(&mut self) -> Result<(), driver::Error> {
+        let gicr = self.find_redistributor().ok_or(driver::Error::NotPresent)?;
+        self.gicr = Some(gicr);
+        Ok(())
+    }
+
+    fn init(&mut self, _registry: &Registry) -> Result<(), driver::Error> {
+        // start from a known state, where nothing reaches the CPU
+        self.init_distributor()?;
+        self.init_redistributor()?;
+        self.init_cpu_interface()
+    }
+
+    fn shutdown(&mut self) {
+        // SAFETY: turning group 1 off only stops interrupts from being signalled
+        unsafe { asm!("msr icc_igrpen1_el1, {}", "isb", in(reg) 0_u64, options(nostack)) };
+        self.gicd.CTLR.set(0);
+    }
+}
+
+impl AsMut<dyn InterruptController> for Gicv3 {
+    fn as_mut(&mut self) -> &mut (dyn InterruptController + 'static) {
+        self
+    }
+}
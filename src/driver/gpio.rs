@@ -1,30 +1,165 @@
-use crate::driver::traits::Driver;
-use tock_registers::interfaces::{ReadWriteable, Writeable};
-use tock_registers::registers::ReadWrite;
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Driver for the BCM2837/BCM2711 GPIO controller.
+//!
+//! Pins are handed out as typed [Pin] objects by [Gpio::claim]. The pin number and the function the
+//! pin is set to are part of its type, so e.g. only pins configured as outputs can be driven. Each
+//! pin can only be claimed once, which keeps two drivers from fighting over the same pin.
+//!
+//! Input pins can also raise interrupts on edges or levels, see [Gpio::listen].
+//!
+//! The driver adds a `pins` command to the shell, which lists the pins that are claimed.
+
+use crate::driver::{self, registry::Registry, traits::Driver};
+use crate::interrupt::{self, IrqNumber};
+use crate::shell::{self, Args, Output};
+use crate::sync::{IrqSafeSpinMutex, SpinMutex};
+use crate::time::SimpleTimer;
+use crate::{shell_command, warn};
+use core::marker::PhantomData;
+use core::time::Duration;
+use tock_registers::fields::Field;
+use tock_registers::interfaces::{ReadWriteable, Readable, Writeable};
+use tock_registers::registers::{ReadOnly, ReadWrite, WriteOnly};
 use tock_registers::{register_bitfields, register_structs};
+use ufmt::uwriteln;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Number of GPIO pins on the controller.
+pub const PIN_COUNT: u8 = 54;
+
+/// Which chip the GPIO controller is part of. They differ in how pull-up/down resistors are set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Variant {
+    /// Raspberry Pi 3
+    Bcm2837,
+    /// Raspberry Pi 4
+    Bcm2711,
+}
+
+/// Setting for a pin's internal pull-up/down resistor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pull {
+    None,
+    Up,
+    Down,
+}
+
+/// Value of a pin's function select field.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Function {
+    Input = 0b000,
+    Output = 0b001,
+    Alt0 = 0b100,
+    Alt1 = 0b101,
+    Alt2 = 0b110,
+    Alt3 = 0b111,
+    Alt4 = 0b011,
+    Alt5 = 0b010,
+}
+
+/// What makes a pin raise an interrupt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trigger {
+    /// A low to high transition, sampled with the system clock. This filters out short glitches.
+    RisingEdge,
+    /// A high to low transition, sampled with the system clock.
+    FallingEdge,
+    /// Either of the above.
+    BothEdges,
+    /// The pin is high. This keeps firing until whatever drives the pin lets go of it.
+    High,
+    /// The pin is low. This keeps firing until whatever drives the pin lets go of it.
+    Low,
+    /// A low to high transition, without sampling. Catches very short pulses.
+    AsyncRisingEdge,
+    /// A high to low transition, without sampling.
+    AsyncFallingEdge,
+}
+
+/// Function that's called from the interrupt handler, given the number of the pin that fired.
+pub type Callback = fn(u8);
+
+/// Mode of a pin that was just claimed. It's left however the firmware set it up.
+pub struct Unconfigured;
+
+/// Mode of a pin that's configured as an input.
+pub struct Input;
+
+/// Mode of a pin that's configured as an output.
+pub struct Output;
+
+/// Mode of a pin that's handed over to a peripheral, using alternate function `F`.
+pub struct Alt<const F: u8>;
+
+pub type Alt0 = Alt<0>;
+pub type Alt1 = Alt<1>;
+pub type Alt2 = Alt<2>;
+pub type Alt3 = Alt<3>;
+pub type Alt4 = Alt<4>;
+pub type Alt5 = Alt<5>;
+
+/// A pin mode that can be selected through the function select registers.
+pub trait PinMode: private::Sealed {
+    const FUNCTION: Function;
+}
+
+/// Marker for the [Alt] modes.
+pub trait AltFunction: PinMode {}
+
+/// A claimed GPIO pin.
+///
+/// Dropping a pin doesn't give up the claim on it; use [Gpio::release] for that.
+///
+/// The pin number comes after the mode because the compiler we're pinned to doesn't let const
+/// parameters come before type parameters without an unstable feature.
+pub struct Pin<Mode, const N: u8> {
+    base_address: usize,
+    variant: Variant,
+    _mode: PhantomData<Mode>,
+}
+
+// FIXME: This provides shared mutability over the register block. Add some sort of Mutex to
+// regulate that interior mutability.
+pub struct Gpio {
+    regs: &'static mut RegisterBlock,
+    variant: Variant,
+    /// One bit for every pin that has been claimed
+    claimed: u64,
+    /// The interrupt lines for each bank of pins
+    irqs: [IrqNumber; 3],
+    /// Whether our handler has been registered for `irqs`
+    irqs_hooked: bool,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
 
 // Description taken from
 // https://github.com/raspberrypi/documentation/files/1888662/BCM2837-ARM-Peripherals.-.Revised.-.V2-1.pdf
+// and the BCM2711 ARM Peripherals document.
 register_bitfields! {
     u32,
 
-    /// GPIO Function Select 1. Used for switching pins between read/write/IO device mode.
-    GPFSEL1 [
-        /// Pin 15
-        FSEL15 OFFSET(15) NUMBITS(3) [
-            Input         = 0b000,
-            Output        = 0b001,
-            MINI_UART_RX  = 0b010, // mini-uart alternate function 5
-            PL011_UART_RX = 0b100 // PL011 uart controller RX
-        ],
-
-        /// Pin 14
-        FSEL14 OFFSET(12) NUMBITS(3) [
-            Input         = 0b000,
-            Output        = 0b001,
-            MINI_UART_TX  = 0b010, // mini-uart alternate function 5
-            PL011_UART_TX = 0b100 // PL011 uart controller TX
-        ]
+    /// GPIO Function Select. Used for switching pins between input, output and alternate
+    /// functions. Each register covers 10 pins, with the values described by [Function].
+    GPFSEL [
+        FSEL0 OFFSET(0) NUMBITS(3) [],
+        FSEL1 OFFSET(3) NUMBITS(3) [],
+        FSEL2 OFFSET(6) NUMBITS(3) [],
+        FSEL3 OFFSET(9) NUMBITS(3) [],
+        FSEL4 OFFSET(12) NUMBITS(3) [],
+        FSEL5 OFFSET(15) NUMBITS(3) [],
+        FSEL6 OFFSET(18) NUMBITS(3) [],
+        FSEL7 OFFSET(21) NUMBITS(3) [],
+        FSEL8 OFFSET(24) NUMBITS(3) [],
+        FSEL9 OFFSET(27) NUMBITS(3) []
     ],
 
     /// GPIO Pull-up/down register
@@ -37,45 +172,385 @@ register_bitfields! {
             PullDown = 0b01,
             PullUp = 0b10
         ]
-    ],
-
-    /// GPIO Pull-up/down Clock Register 0
-    GPPUDCLK0 [
-        /// Pin 15
-        PUDCLK15 OFFSET(15) NUMBITS(1) [
-            NoEffect    = 0,
-            AssertClock = 1
-        ],
-
-        /// Pin 14
-        PUDCLK14 OFFSET(14) NUMBITS(1) [
-            NoEffect    = 0,
-            AssertClock = 1
-        ]
     ]
 }
 
 register_structs! {
     #[allow(non_snake_case)]
     RegisterBlock {
-        (0x00 => GPFSEL0: ReadWrite<u32>),
-        (0x04 => GPFSEL1: ReadWrite<u32, GPFSEL1::Register>),
-        (0x08 => GPFSEL2: ReadWrite<u32>),
-        (0x0c => GPFSEL3: ReadWrite<u32>),
-        (0x10 => GPFSEL4: ReadWrite<u32>),
-        (0x14 => GPFSEL5: ReadWrite<u32>),
-        (0x18 => _reserved),
+        (0x00 => GPFSEL: [ReadWrite<u32, GPFSEL::Register>; 6]),
+        (0x18 => _reserved0),
+        (0x1c => GPSET: [WriteOnly<u32>; 2]),
+        (0x24 => _reserved1),
+        (0x28 => GPCLR: [WriteOnly<u32>; 2]),
+        (0x30 => _reserved2),
+        (0x34 => GPLEV: [ReadOnly<u32>; 2]),
+        (0x3c => _reserved3),
+        (0x40 => GPEDS: [ReadWrite<u32>; 2]),
+        (0x48 => _reserved4),
+        (0x4c => GPREN: [ReadWrite<u32>; 2]),
+        (0x54 => _reserved5),
+        (0x58 => GPFEN: [ReadWrite<u32>; 2]),
+        (0x60 => _reserved6),
+        (0x64 => GPHEN: [ReadWrite<u32>; 2]),
+        (0x6c => _reserved7),
+        (0x70 => GPLEN: [ReadWrite<u32>; 2]),
+        (0x78 => _reserved8),
+        (0x7c => GPAREN: [ReadWrite<u32>; 2]),
+        (0x84 => _reserved9),
+        (0x88 => GPAFEN: [ReadWrite<u32>; 2]),
+        (0x90 => _reserved10),
         (0x94 => GPPUD: ReadWrite<u32, GPPUD::Register>),
-        (0x98 => GPPUDCLK0: ReadWrite<u32, GPPUDCLK0::Register>),
-        (0x9c => GPPUDCLK1: ReadWrite<u32>),
-        (0xa0 => @END),
+        (0x98 => GPPUDCLK: [ReadWrite<u32>; 2]),
+        (0xa0 => _reserved11),
+        /// BCM2711 only. Two bits per pin: 0b00 for no resistor, 0b01 for pull-up and 0b10 for
+        /// pull-down.
+        (0xe4 => GPIO_PUP_PDN_CNTRL: [ReadWrite<u32>; 4]),
+        (0xf4 => @END),
     }
 }
 
-// FIXME: This provides shared mutability over the register block. Add some sort of Mutex to
-// regulate that interior mutability.
-pub struct Gpio {
-    regs: &'static mut RegisterBlock,
+const FSEL_FIELDS: [Field<u32, GPFSEL::Register>; 10] = [
+    GPFSEL::FSEL0,
+    GPFSEL::FSEL1,
+    GPFSEL::FSEL2,
+    GPFSEL::FSEL3,
+    GPFSEL::FSEL4,
+    GPFSEL::FSEL5,
+    GPFSEL::FSEL6,
+    GPFSEL::FSEL7,
+    GPFSEL::FSEL8,
+    GPFSEL::FSEL9,
+];
+
+/// How long to wait between the steps of the BCM2837 pull-up/down sequence. The datasheet asks for
+/// 150 cycles.
+const PULL_SETUP_DELAY: Duration = Duration::from_nanos(2000);
+
+mod private {
+    pub trait Sealed {}
+}
+
+#[derive(Clone, Copy)]
+struct Listener {
+    callback: Callback,
+    /// Events that come sooner than this after the last one are ignored
+    debounce: Duration,
+    /// Uptime when the callback last ran
+    last_event: Option<Duration>,
+}
+
+/// Everything the interrupt handler needs. This is kept apart from [Gpio] so that the handler
+/// doesn't have to wait for whoever holds the driver.
+struct IrqState {
+    base_address: usize,
+    listeners: [Option<Listener>; PIN_COUNT as usize],
+}
+
+shell_command! {
+    /// List the GPIO pins that are claimed, with their functions and levels
+    static PINS: "pins" "" => pins;
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static IRQ_STATE: IrqSafeSpinMutex<IrqState> = IrqSafeSpinMutex::new_irq_safe(IrqState {
+    base_address: 0,
+    listeners: [None; PIN_COUNT as usize],
+});
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl private::Sealed for Input {}
+impl PinMode for Input {
+    const FUNCTION: Function = Function::Input;
+}
+
+impl private::Sealed for Output {}
+impl PinMode for Output {
+    const FUNCTION: Function = Function::Output;
+}
+
+macro_rules! impl_alt_function {
+    ($($f:literal => $function:ident),*) => {$(
+        impl private::Sealed for Alt<$f> {}
+        impl PinMode for Alt<$f> {
+            const FUNCTION: Function = Function::$function;
+        }
+        impl AltFunction for Alt<$f> {}
+    )*}
+}
+
+impl_alt_function!(0 => Alt0, 1 => Alt1, 2 => Alt2, 3 => Alt3, 4 => Alt4, 5 => Alt5);
+
+/// Index of the 32-pin bank register that holds `pin`, and the pin's bit in it.
+const fn bank_bit(pin: u8) -> (usize, u32) {
+    ((pin / 32) as usize, 1 << (pin % 32))
+}
+
+fn set_function(regs: &RegisterBlock, pin: u8, function: Function) {
+    let field = FSEL_FIELDS[(pin % 10) as usize];
+    regs.GPFSEL[(pin / 10) as usize].modify(field.val(function as u32));
+}
+
+fn function(regs: &RegisterBlock, pin: u8) -> Function {
+    let field = FSEL_FIELDS[(pin % 10) as usize];
+    match regs.GPFSEL[(pin / 10) as usize].read(field) {
+        0b000 => Function::Input,
+        0b001 => Function::Output,
+        0b100 => Function::Alt0,
+        0b101 => Function::Alt1,
+        0b110 => Function::Alt2,
+        0b111 => Function::Alt3,
+        0b011 => Function::Alt4,
+        _ => Function::Alt5,
+    }
+}
+
+fn set_pull(regs: &RegisterBlock, variant: Variant, pin: u8, pull: Pull) {
+    match variant {
+        Variant::Bcm2837 => {
+            let timer = crate::time::arch_timer();
+            let (bank, bit) = bank_bit(pin);
+
+            regs.GPPUD.write(match pull {
+                Pull::None => GPPUD::PUD::Off,
+                Pull::Up => GPPUD::PUD::PullUp,
+                Pull::Down => GPPUD::PUD::PullDown,
+            });
+            timer.spin_for(PULL_SETUP_DELAY);
+
+            regs.GPPUDCLK[bank].set(bit);
+            timer.spin_for(PULL_SETUP_DELAY);
+
+            regs.GPPUD.write(GPPUD::PUD::Off);
+            regs.GPPUDCLK[bank].set(0);
+        }
+        Variant::Bcm2711 => {
+            let value = match pull {
+                Pull::None => 0b00,
+                Pull::Up => 0b01,
+                Pull::Down => 0b10,
+            };
+            let reg = &regs.GPIO_PUP_PDN_CNTRL[(pin / 16) as usize];
+            let shift = 2 * (pin % 16);
+            reg.set(reg.get() & !(0b11 << shift) | value << shift);
+        }
+    }
+}
+
+/// Turn on the event detection for `pin` that's needed for `trigger`, and turn off the rest.
+fn set_detect(regs: &RegisterBlock, pin: u8, trigger: Option<Trigger>) {
+    let (bank, bit) = bank_bit(pin);
+    let registers = [
+        (&regs.GPREN[bank], matches!(trigger, Some(Trigger::RisingEdge | Trigger::BothEdges))),
+        (&regs.GPFEN[bank], matches!(trigger, Some(Trigger::FallingEdge | Trigger::BothEdges))),
+        (&regs.GPHEN[bank], trigger == Some(Trigger::High)),
+        (&regs.GPLEN[bank], trigger == Some(Trigger::Low)),
+        (&regs.GPAREN[bank], trigger == Some(Trigger::AsyncRisingEdge)),
+        (&regs.GPAFEN[bank], trigger == Some(Trigger::AsyncFallingEdge)),
+    ];
+    for (reg, enable) in registers.iter() {
+        if *enable {
+            reg.set(reg.get() | bit);
+        } else {
+            reg.set(reg.get() & !bit);
+        }
+    }
+
+    // forget about anything that was detected before
+    regs.GPEDS[bank].set(bit);
+}
+
+impl IrqState {
+    fn regs(&self) -> &RegisterBlock {
+        // SAFETY: the address is copied from a `Gpio`, whose creator checked it
+        unsafe { &*(self.base_address as *const RegisterBlock) }
+    }
+
+    /// Collect and clear the pins that have detected an event.
+    fn take_events(&self) -> u64 {
+        if self.base_address == 0 {
+            return 0
+        }
+
+        let regs = self.regs();
+        let mut events = 0;
+        for bank in 0..regs.GPEDS.len() {
+            let detected = regs.GPEDS[bank].get();
+            regs.GPEDS[bank].set(detected);
+            events |= u64::from(detected) << (32 * bank);
+        }
+        events
+    }
+
+    /// Find the callback to run for an event on `pin`, unless it's too soon after the last one.
+    fn debounced_callback(&mut self, pin: u8, now: Duration) -> Option<Callback> {
+        let listener = self.listeners[pin as usize].as_mut()?;
+        if let Some(last_event) = listener.last_event {
+            if now.saturating_sub(last_event) < listener.debounce {
+                return None
+            }
+        }
+        listener.last_event = Some(now);
+        Some(listener.callback)
+    }
+}
+
+fn handle_irq(_irq: IrqNumber) {
+    let now = crate::time::arch_timer().uptime();
+    let events = IRQ_STATE.with_lock(|state| state.take_events());
+
+    for pin in 0..PIN_COUNT {
+        if events & 1 << pin == 0 {
+            continue
+        }
+        // the callback runs without the lock held, so it's free to use the GPIO itself
+        if let Some(callback) = IRQ_STATE.with_lock(|state| state.debounced_callback(pin, now)) {
+            callback(pin)
+        }
+    }
+}
+
+fn pins(args: &mut Args<'_>, out: &mut Output) -> Result<(), shell::Error> {
+    args.finish()?;
+    let gpio = crate::DRIVERS
+        .get()
+        .get::<SpinMutex<Gpio>>()
+        .ok_or(shell::Error::Failed("the GPIO driver isn't running"))?;
+    gpio.with_lock(|gpio| {
+        for pin in (0..PIN_COUNT).filter(|&pin| gpio.is_claimed(pin)) {
+            let (bank, bit) = bank_bit(pin);
+            let level = if gpio.regs.GPLEV[bank].get() & bit != 0 { "high" } else { "low" };
+            let _ = uwriteln!(out, "{}: {}, {}", pin, function(gpio.regs, pin).name(), level);
+        }
+    });
+    Ok(())
+}
+
+impl Function {
+    fn name(self) -> &'static str {
+        match self {
+            Function::Input => "input",
+            Function::Output => "output",
+            Function::Alt0 => "alt0",
+            Function::Alt1 => "alt1",
+            Function::Alt2 => "alt2",
+            Function::Alt3 => "alt3",
+            Function::Alt4 => "alt4",
+            Function::Alt5 => "alt5",
+        }
+    }
+}
+
+impl Gpio {
+    /// Register our handler for the interrupt lines and let them through. If that fails partway,
+    /// the lines that were already hooked are let go again, so a later call can retry.
+    fn hook_irqs(&mut self) -> Result<(), driver::Error> {
+        for (hooked, &irq) in self.irqs.iter().enumerate() {
+            let result = interrupt::register(irq, handle_irq).and_then(|()| {
+                interrupt::enable(irq).map_err(|e| {
+                    interrupt::unregister(irq);
+                    e
+                })
+            });
+            if let Err(e) = result {
+                for &irq in &self.irqs[..hooked] {
+                    interrupt::unregister(irq);
+                }
+                return Err(e)
+            }
+        }
+        self.irqs_hooked = true;
+        Ok(())
+    }
+
+    fn unhook_irqs(&mut self) {
+        if self.irqs_hooked {
+            for &irq in self.irqs.iter() {
+                interrupt::unregister(irq);
+            }
+            self.irqs_hooked = false;
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl<Mode, const N: u8> Pin<Mode, N> {
+    /// The number of this pin.
+    pub const NUMBER: u8 = N;
+
+    fn regs(&self) -> &RegisterBlock {
+        // SAFETY: the address was checked by the caller of `Gpio::new`
+        unsafe { &*(self.base_address as *const RegisterBlock) }
+    }
+
+    fn into_mode<M: PinMode>(self) -> Pin<M, N> {
+        set_function(self.regs(), N, M::FUNCTION);
+        Pin {
+            base_address: self.base_address,
+            variant: self.variant,
+            _mode: PhantomData,
+        }
+    }
+
+    pub fn into_input(self) -> Pin<Input, N> {
+        self.into_mode()
+    }
+
+    pub fn into_output(self) -> Pin<Output, N> {
+        self.into_mode()
+    }
+
+    /// Hand the pin over to a peripheral, e.g. `pin.into_alt::<Alt0>()`.
+    pub fn into_alt<F: AltFunction>(self) -> Pin<F, N> {
+        self.into_mode()
+    }
+
+    pub fn set_pull(&mut self, pull: Pull) {
+        set_pull(self.regs(), self.variant, N, pull)
+    }
+
+    /// Read the level of the pin. This works no matter which function the pin is set to.
+    pub fn is_high(&self) -> bool {
+        let (bank, bit) = bank_bit(N);
+        self.regs().GPLEV[bank].get() & bit != 0
+    }
+
+    pub fn is_low(&self) -> bool {
+        !self.is_high()
+    }
+}
+
+impl<const N: u8> Pin<Output, N> {
+    pub fn set_high(&mut self) {
+        let (bank, bit) = bank_bit(N);
+        self.regs().GPSET[bank].set(bit)
+    }
+
+    pub fn set_low(&mut self) {
+        let (bank, bit) = bank_bit(N);
+        self.regs().GPCLR[bank].set(bit)
+    }
+
+    pub fn set(&mut self, high: bool) {
+        if high {
+            self.set_high()
+        } else {
+            self.set_low()
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.set(self.is_low())
+    }
 }
 
 impl Gpio {
@@ -83,35 +558,114 @@ impl Gpio {
     /// The user must verify that the address for the register block is correct and
     /// that no more than once instance of `Gpio` exists for a base address at any
     /// given time.
-    pub unsafe fn new(base_address: usize) -> Self {
+    ///
+    /// `irqs` are the interrupt lines for pins 0-27, 28-45 and 46-53, respectively.
+    pub unsafe fn new(base_address: usize, variant: Variant, irqs: [IrqNumber; 3]) -> Self {
         Self {
             regs: &mut *(base_address as *mut _),
+            variant,
+            claimed: 0,
+            irqs,
+            irqs_hooked: false,
+        }
+    }
+
+    /// Take ownership of pin `N`.
+    ///
+    /// Fails with [driver::Error::Busy] if the pin was already claimed, or with
+    /// [driver::Error::BadConfiguration] if there is no such pin.
+    pub fn claim<const N: u8>(&mut self) -> Result<Pin<Unconfigured, N>, driver::Error> {
+        if N >= PIN_COUNT {
+            return Err(driver::Error::BadConfiguration)
         }
+        if self.is_claimed(N) {
+            return Err(driver::Error::Busy)
+        }
+        self.claimed |= 1 << N;
+
+        Ok(Pin {
+            base_address: self.regs as *const RegisterBlock as usize,
+            variant: self.variant,
+            _mode: PhantomData,
+        })
     }
 
-    pub fn setup_uart(&mut self) {
-        use crate::time::SimpleTimer;
-        use core::time::Duration;
+    /// Give up ownership of a pin so that it can be claimed again.
+    ///
+    /// The pin is switched back to an input without any pull-up/down, so it doesn't drive anything.
+    pub fn release<Mode, const N: u8>(&mut self, pin: Pin<Mode, N>) {
+        self.unlisten_pin(N);
+        let mut pin = pin.into_input();
+        pin.set_pull(Pull::None);
+        self.claimed &= !(1 << N);
+    }
 
-        const DELAY: Duration = Duration::from_nanos(2000);
+    pub fn is_claimed(&self, pin: u8) -> bool {
+        pin < PIN_COUNT && self.claimed & 1 << pin != 0
+    }
+
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
 
-        // map UART1 to GPIO pins
-        self.regs.GPFSEL1.modify(GPFSEL1::FSEL14::PL011_UART_TX + GPFSEL1::FSEL15::PL011_UART_RX);
-        crate::time::arch_timer().spin_for(DELAY);
+    /// Call `callback` from the interrupt handler whenever `trigger` happens on `pin`.
+    ///
+    /// Events that come within `debounce` of the last one that ran the callback are ignored, which
+    /// helps with mechanical switches. Pass [Duration::ZERO] to see every event. This replaces any
+    /// callback that was already set for the pin.
+    pub fn listen<const N: u8>(
+        &mut self,
+        _pin: &Pin<Input, N>,
+        trigger: Trigger,
+        debounce: Duration,
+        callback: Callback,
+    ) -> Result<(), driver::Error> {
+        if !self.irqs_hooked {
+            self.hook_irqs()?;
+        }
 
-        self.regs.GPPUD.write(GPPUD::PUD::Off);
-        crate::time::arch_timer().spin_for(DELAY);
+        let base_address = self.regs as *const RegisterBlock as usize;
+        IRQ_STATE.with_lock(|state| {
+            state.base_address = base_address;
+            state.listeners[N as usize] = Some(Listener {
+                callback,
+                debounce,
+                last_event: None,
+            });
+            set_detect(self.regs, N, Some(trigger));
+        });
+        Ok(())
+    }
 
-        self.regs.GPPUDCLK0.write(
-            GPPUDCLK0::PUDCLK14::AssertClock + GPPUDCLK0::PUDCLK15::AssertClock,
-        );
-        crate::time::arch_timer().spin_for(DELAY);
+    /// Stop calling the callback that was set for `pin` by [Gpio::listen].
+    pub fn unlisten<const N: u8>(&mut self, _pin: &Pin<Input, N>) {
+        self.unlisten_pin(N)
+    }
 
-        self.regs.GPPUD.write(GPPUD::PUD::Off);
-        self.regs.GPPUDCLK0.set(0);
+    fn unlisten_pin(&mut self, pin: u8) {
+        IRQ_STATE.with_lock(|state| {
+            set_detect(self.regs, pin, None);
+            state.listeners[pin as usize] = None;
+        })
     }
 }
 
 impl Driver for Gpio {
-    const COMPATIBLE: &'static str = "BCM GPIO";
+    const COMPATIBLE: &'static str = "brcm,bcm2835-gpio";
+    const ALSO_COMPATIBLE: &'static [&'static str] = &["brcm,bcm2711-gpio"];
+
+    fn init(&mut self, _registry: &Registry) -> Result<(), driver::Error> {
+        // the shell can do without the command, so that's not a reason to fail
+        if let Err(e) = shell::register(&PINS) {
+            warn!("Couldn't add the 'pins' command: {}", e);
+        }
+        Ok(())
+    }
+
+    fn shutdown(&mut self) {
+        for pin in 0..PIN_COUNT {
+            self.unlisten_pin(pin);
+        }
+        self.unhook_irqs();
+    }
 }
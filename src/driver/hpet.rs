@@ -0,0 +1,306 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Driver for the high precision event timer of PCs.
+//!
+//! The timer has a main counter that runs at a fixed rate of at least 10 MHz, and a few
+//! comparators that raise an interrupt when the counter reaches them. Timer 0 is what the driver
+//! uses as a [ClockEvent]. Where its interrupt goes is normally up to the IOAPIC, which the kernel
+//! doesn't drive, so the timer is put in legacy replacement mode instead: timer 0 takes over line 0
+//! of the 8259 from the PIT, and timer 1 takes over line 8 from the RTC.
+//!
+//! Reading the counter doesn't change anything, so it's also done through an [HpetCounter], which
+//! works without the driver's lock and can be the kernel's [ClockSource]. Only timers with a 64-bit
+//! counter are driven, since a 32-bit one wraps around within minutes.
+
+use crate::driver::{self, registry::Registry, traits::Driver};
+use crate::interrupt::{self, IrqNumber};
+use crate::sync::IrqSafeSpinMutex;
+use crate::time::{ClockEvent, ClockSource, EventHandler, SimpleTimer};
+use core::convert::TryFrom;
+use core::time::Duration;
+use tock_registers::interfaces::{ReadWriteable, Readable, Writeable};
+use tock_registers::registers::{ReadOnly, ReadWrite};
+use tock_registers::{register_bitfields, register_structs};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Reads the timer's main counter. Holds nothing but the address of the registers and the
+/// counter's period, so it can be copied and used without a lock.
+#[derive(Clone, Copy)]
+pub struct HpetCounter {
+    base_address: usize,
+    period_fs: u64,
+}
+
+// FIXME: This provides shared mutability over the register block. Add some sort of Mutex to
+// regulate that interior mutability.
+pub struct Hpet {
+    regs: &'static mut RegisterBlock,
+    /// The interrupt line of timer 0 in legacy replacement mode
+    irq: IrqNumber,
+    /// Whether our handler has been registered for `irq`
+    irq_hooked: bool,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Description taken from the IA-PC HPET (High Precision Event Timers) Specification, revision 1.0a
+register_bitfields! {
+    u64,
+
+    /// General Capabilities and ID Register
+    GCAP_ID [
+        /// How long a tick of the main counter is, in femtoseconds
+        COUNTER_CLK_PERIOD OFFSET(32) NUMBITS(32) [],
+        /// Timer 0 and 1 can be routed to the legacy interrupt lines.
+        LEG_RT_CAP OFFSET(15) NUMBITS(1) [],
+        /// The main counter is 64 bits wide.
+        COUNT_SIZE_CAP OFFSET(13) NUMBITS(1) []
+    ],
+
+    /// General Configuration Register
+    GEN_CONF [
+        /// Route timer 0 to line 0 of the 8259, and timer 1 to line 8.
+        LEG_RT_CNF OFFSET(1) NUMBITS(1) [],
+        /// Run the main counter, and let the timers raise interrupts.
+        ENABLE_CNF OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// Timer N Configuration and Capability Register
+    TIMER_CONF [
+        /// Count with the low 32 bits of the counter only.
+        TIMER_32MODE_CNF OFFSET(8) NUMBITS(1) [],
+        /// Go off every time the period set through the comparator passes.
+        TYPE_CNF OFFSET(3) NUMBITS(1) [],
+        /// Raise an interrupt when the comparator matches.
+        INT_ENB_CNF OFFSET(2) NUMBITS(1) [],
+        /// Raise a level-triggered interrupt rather than an edge-triggered one.
+        INT_TYPE_CNF OFFSET(1) NUMBITS(1) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x000 => GCAP_ID: ReadOnly<u64, GCAP_ID::Register>),
+        (0x008 => _reserved0),
+        (0x010 => GEN_CONF: ReadWrite<u64, GEN_CONF::Register>),
+        (0x018 => _reserved1),
+        (0x0f0 => MAIN_COUNTER: ReadWrite<u64>),
+        (0x0f8 => _reserved2),
+        (0x100 => TIMER0_CONF: ReadWrite<u64, TIMER_CONF::Register>),
+        (0x108 => TIMER0_COMPARATOR: ReadWrite<u64>),
+        (0x110 => @END),
+    }
+}
+
+const FS_PER_NS: u64 = 1_000_000;
+
+/// The longest tick the specification allows, which is 100 ns
+const MAX_PERIOD_FS: u64 = 100 * FS_PER_NS;
+
+/// Timers are set at least this many ticks ahead, so the counter doesn't pass the comparator
+/// before it's written.
+const MIN_DELAY_TICKS: u64 = 16;
+
+/// How the timer compares to others, both as a clock source and as a clock event device. It's
+/// read over the bus, unlike the TSC, but it's much better than the PIT.
+const RATING: u32 = 250;
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+/// The handler of the timer that's set, kept apart from [Hpet] so that the interrupt handler
+/// doesn't have to wait for whoever holds the driver.
+static HANDLER: IrqSafeSpinMutex<Option<EventHandler>> = IrqSafeSpinMutex::new_irq_safe(None);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl HpetCounter {
+    fn regs(&self) -> &RegisterBlock {
+        // SAFETY: the address was checked by the creator of the `Hpet`
+        unsafe { &*(self.base_address as *const RegisterBlock) }
+    }
+
+    fn ticks(&self) -> u64 {
+        self.regs().MAIN_COUNTER.get()
+    }
+
+    /// Convert a duration to ticks, rounding down.
+    fn duration_to_ticks(&self, duration: Duration) -> u64 {
+        let ticks = duration.as_nanos() * u128::from(FS_PER_NS) / u128::from(self.period_fs);
+        u64::try_from(ticks).unwrap_or(u64::MAX)
+    }
+
+    fn ticks_to_duration(&self, ticks: u64) -> Duration {
+        let nanos = u128::from(ticks) * u128::from(self.period_fs) / u128::from(FS_PER_NS);
+        Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX))
+    }
+}
+
+fn handle_irq(_irq: IrqNumber) {
+    // the comparator won't match again until the counter wraps around, so it's left alone
+    if let Some(handler) = HANDLER.with_lock(|handler| handler.take()) {
+        handler()
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Hpet {
+    /// `irq` is the line that timer 0 is routed to in legacy replacement mode.
+    ///
+    /// # Safety
+    /// The user must verify that the address is where the timer's registers are mapped.
+    pub unsafe fn new(base_address: usize, irq: IrqNumber) -> Self {
+        Self {
+            regs: &mut *(base_address as *mut _),
+            irq,
+            irq_hooked: false,
+        }
+    }
+
+    /// Something that reads the counter without the driver. Only meaningful once the driver is
+    /// initialized.
+    pub fn counter(&self) -> HpetCounter {
+        HpetCounter {
+            base_address: self.regs as *const RegisterBlock as usize,
+            period_fs: self.regs.GCAP_ID.read(GCAP_ID::COUNTER_CLK_PERIOD),
+        }
+    }
+}
+
+impl Driver for Hpet {
+    const COMPATIBLE: &'static str = "intel,hpet";
+
+    fn probe(&mut self) -> Result<(), driver::Error> {
+        // nothing answers at the address if there's no timer, which reads as all zeros or ones
+        let capabilities = self.regs.GCAP_ID.extract();
+        let period_fs = capabilities.read(GCAP_ID::COUNTER_CLK_PERIOD);
+        if period_fs == 0 || period_fs > MAX_PERIOD_FS {
+            return Err(driver::Error::NotPresent)
+        }
+        if !capabilities.is_set(GCAP_ID::COUNT_SIZE_CAP)
+            || !capabilities.is_set(GCAP_ID::LEG_RT_CAP)
+        {
+            return Err(driver::Error::NotPresent)
+        }
+        Ok(())
+    }
+
+    fn init(&mut self, _registry: &Registry) -> Result<(), driver::Error> {
+        // timer 0 goes off once, on an edge, and counts with the whole counter
+        self.regs.TIMER0_CONF.modify(
+            TIMER_CONF::INT_ENB_CNF::CLEAR
+                + TIMER_CONF::TYPE_CNF::CLEAR
+                + TIMER_CONF::INT_TYPE_CNF::CLEAR
+                + TIMER_CONF::TIMER_32MODE_CNF::CLEAR,
+        );
+        // the PIT keeps its line until timer 0 is programmed, and the counter keeps whatever count
+        // the firmware left it with
+        self.regs.GEN_CONF.modify(GEN_CONF::LEG_RT_CNF::CLEAR + GEN_CONF::ENABLE_CNF::SET);
+        Ok(())
+    }
+
+    fn shutdown(&mut self) {
+        self.cancel();
+        if self.irq_hooked {
+            interrupt::unregister(self.irq);
+            self.irq_hooked = false;
+        }
+        // give the legacy lines back
+        self.regs.GEN_CONF.modify(GEN_CONF::LEG_RT_CNF::CLEAR);
+    }
+}
+
+impl ClockEvent for Hpet {
+    fn name(&self) -> &'static str {
+        "HPET"
+    }
+
+    fn rating(&self) -> u32 {
+        RATING
+    }
+
+    /// Half the counter's range, so the comparator can't wrap around.
+    fn max_delay(&self) -> Duration {
+        self.counter().ticks_to_duration(u64::MAX / 2)
+    }
+
+    fn program(&mut self, delay: Duration, handler: EventHandler) -> Result<(), driver::Error> {
+        if delay > self.max_delay() {
+            return Err(driver::Error::BadConfiguration)
+        }
+        let counter = self.counter();
+        let mut ticks = counter.duration_to_ticks(delay).max(MIN_DELAY_TICKS);
+        if !self.irq_hooked {
+            interrupt::register(self.irq, handle_irq)?;
+            self.irq_hooked = true;
+        }
+
+        HANDLER.with_lock(|current| *current = Some(handler));
+        self.regs.GEN_CONF.modify(GEN_CONF::LEG_RT_CNF::SET);
+        self.regs.TIMER0_CONF.modify(TIMER_CONF::INT_ENB_CNF::SET);
+        loop {
+            let deadline = counter.ticks().wrapping_add(ticks);
+            self.regs.TIMER0_COMPARATOR.set(deadline);
+            // the comparator only goes off when the counter reaches it, so if the counter got
+            // there first, try again further ahead
+            if (deadline.wrapping_sub(counter.ticks()) as i64) > 0 {
+                break
+            }
+            ticks = ticks.saturating_mul(2);
+        }
+        interrupt::enable(self.irq)
+    }
+
+    fn cancel(&mut self) {
+        self.regs.TIMER0_CONF.modify(TIMER_CONF::INT_ENB_CNF::CLEAR);
+        HANDLER.with_lock(|current| *current = None);
+    }
+}
+
+impl AsMut<dyn ClockEvent> for Hpet {
+    fn as_mut(&mut self) -> &mut (dyn ClockEvent + 'static) {
+        self
+    }
+}
+
+impl SimpleTimer for HpetCounter {
+    fn resolution(&self) -> Duration {
+        self.ticks_to_duration(1).max(Duration::from_nanos(1))
+    }
+
+    /// The time since the counter was started, by the firmware or by the driver.
+    fn uptime(&self) -> Duration {
+        self.ticks_to_duration(self.ticks())
+    }
+
+    fn spin_for(&self, duration: Duration) {
+        let deadline = self.ticks().saturating_add(self.duration_to_ticks(duration));
+        while self.ticks() < deadline {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+impl ClockSource for HpetCounter {
+    fn name(&self) -> &'static str {
+        "HPET"
+    }
+
+    fn rating(&self) -> u32 {
+        RATING
+    }
+}
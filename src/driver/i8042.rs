@@ -0,0 +1,216 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! The i8042 PS/2 controller, which the keyboard is connected to.
+//!
+//! Only the first port is used. The second one, which is usually the mouse, is left disabled.
+
+use crate::driver;
+use crate::driver::ps2_keyboard::Ps2Keyboard;
+use crate::time::SimpleTimer;
+use core::time::Duration;
+use x86::io::{inb, outb};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+pub struct I8042 {
+    data_port: u16,
+    command_port: u16,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// How long the controller or keyboard gets to answer. Resetting the keyboard is the slowest.
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+
+// Controller commands
+const COMMAND_READ_CONFIG: u8 = 0x20;
+const COMMAND_WRITE_CONFIG: u8 = 0x60;
+const COMMAND_DISABLE_SECOND_PORT: u8 = 0xa7;
+const COMMAND_SELF_TEST: u8 = 0xaa;
+const COMMAND_TEST_FIRST_PORT: u8 = 0xab;
+const COMMAND_DISABLE_FIRST_PORT: u8 = 0xad;
+const COMMAND_ENABLE_FIRST_PORT: u8 = 0xae;
+/// Pulse the output lines given by the low nibble low. Line 0 is the CPU's reset line.
+const COMMAND_PULSE_RESET: u8 = 0xfe;
+
+const SELF_TEST_PASSED: u8 = 0x55;
+const PORT_TEST_PASSED: u8 = 0x00;
+
+const CONFIG_FIRST_PORT_IRQ: u8 = 1 << 0;
+const CONFIG_SECOND_PORT_IRQ: u8 = 1 << 1;
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+// Responses from the keyboard
+const ACK: u8 = 0xfa;
+const RESEND: u8 = 0xfe;
+
+/// How many times a byte is sent to the keyboard before giving up on it
+const SEND_ATTEMPTS: usize = 3;
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl I8042 {
+    fn status(&self) -> u8 {
+        // SAFETY: the ports were checked by the caller of `new`
+        unsafe { inb(self.command_port) }
+    }
+
+    /// Wait until `ready` is true of the status register.
+    fn wait(&self, ready: impl Fn(u8) -> bool) -> Result<(), driver::Error> {
+        let timer = crate::time::arch_timer();
+        let deadline = timer.uptime() + TIMEOUT;
+        while !ready(self.status()) {
+            if timer.uptime() > deadline {
+                return Err(driver::Error::Timeout)
+            }
+        }
+        Ok(())
+    }
+
+    fn command(&mut self, command: u8) -> Result<(), driver::Error> {
+        self.wait(|status| status & STATUS_INPUT_FULL == 0)?;
+        // SAFETY: the ports were checked by the caller of `new`
+        unsafe { outb(self.command_port, command) }
+        Ok(())
+    }
+
+    fn write_data(&mut self, byte: u8) -> Result<(), driver::Error> {
+        self.wait(|status| status & STATUS_INPUT_FULL == 0)?;
+        // SAFETY: the ports were checked by the caller of `new`
+        unsafe { outb(self.data_port, byte) }
+        Ok(())
+    }
+
+    fn read_data(&mut self) -> Result<u8, driver::Error> {
+        self.wait(|status| status & STATUS_OUTPUT_FULL != 0)?;
+        // SAFETY: the ports were checked by the caller of `new`
+        Ok(unsafe { inb(self.data_port) })
+    }
+
+    /// Run a controller command that answers with a byte.
+    fn query(&mut self, command: u8) -> Result<u8, driver::Error> {
+        self.command(command)?;
+        self.read_data()
+    }
+
+    fn write_config(&mut self, config: u8) -> Result<(), driver::Error> {
+        self.command(COMMAND_WRITE_CONFIG)?;
+        self.write_data(config)
+    }
+
+    /// Throw away anything the devices sent that nobody read.
+    fn flush(&mut self) {
+        while self.status() & STATUS_OUTPUT_FULL != 0 {
+            // SAFETY: the ports were checked by the caller of `new`
+            unsafe { inb(self.data_port) };
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl I8042 {
+    /// # Safety
+    /// The user must verify that the ports are the data and command ports of an i8042.
+    pub const unsafe fn new(data_port: u16, command_port: u16) -> Self {
+        Self { data_port, command_port }
+    }
+
+    /// Check that there's a controller, since reads from ports nothing answers on come back as
+    /// all ones.
+    pub fn is_present(&self) -> bool {
+        self.status() != 0xff
+    }
+
+    /// Reset the controller and enable the first port, with its interrupt off.
+    ///
+    /// With `translate`, the controller turns whatever the keyboard sends into scancode set 1.
+    pub fn init(&mut self, translate: bool) -> Result<(), driver::Error> {
+        self.command(COMMAND_DISABLE_FIRST_PORT)?;
+        self.command(COMMAND_DISABLE_SECOND_PORT)?;
+        self.flush();
+
+        let mut config = self.query(COMMAND_READ_CONFIG)?;
+        config &= !(CONFIG_FIRST_PORT_IRQ | CONFIG_SECOND_PORT_IRQ | CONFIG_TRANSLATION);
+        if translate {
+            config |= CONFIG_TRANSLATION;
+        }
+        self.write_config(config)?;
+
+        if self.query(COMMAND_SELF_TEST)? != SELF_TEST_PASSED {
+            return Err(driver::Error::io::<Ps2Keyboard>("the controller failed its self test"))
+        }
+        // some controllers reset their configuration during the self test
+        self.write_config(config)?;
+
+        if self.query(COMMAND_TEST_FIRST_PORT)? != PORT_TEST_PASSED {
+            return Err(driver::Error::io::<Ps2Keyboard>("the keyboard port failed its test"))
+        }
+        self.command(COMMAND_ENABLE_FIRST_PORT)
+    }
+
+    /// Turn the first port's interrupt on or off.
+    pub fn set_irq_enabled(&mut self, enabled: bool) -> Result<(), driver::Error> {
+        let mut config = self.query(COMMAND_READ_CONFIG)?;
+        if enabled {
+            config |= CONFIG_FIRST_PORT_IRQ;
+        } else {
+            config &= !CONFIG_FIRST_PORT_IRQ;
+        }
+        self.write_config(config)
+    }
+
+    /// Send a byte to the keyboard and wait for it to be acknowledged, resending it if the
+    /// keyboard asks.
+    pub fn send(&mut self, byte: u8) -> Result<(), driver::Error> {
+        for _ in 0..SEND_ATTEMPTS {
+            self.write_data(byte)?;
+            match self.read_data()? {
+                ACK => return Ok(()),
+                RESEND => continue,
+                _ => return Err(driver::Error::io::<Ps2Keyboard>("unexpected response")),
+            }
+        }
+        Err(driver::Error::io::<Ps2Keyboard>("the keyboard kept asking for a resend"))
+    }
+
+    /// Wait for the next byte from the keyboard.
+    pub fn receive(&mut self) -> Result<u8, driver::Error> {
+        self.read_data()
+    }
+
+    /// Read a byte the keyboard sent without waiting, e.g. from the interrupt handler.
+    pub fn try_receive(&mut self) -> Option<u8> {
+        if self.status() & STATUS_OUTPUT_FULL == 0 {
+            return None
+        }
+        // SAFETY: the ports were checked by the caller of `new`
+        Some(unsafe { inb(self.data_port) })
+    }
+
+    /// Reset the machine through the line the controller has to the CPU, which is how PCs
+    /// restarted before there were better ways. This only returns if nothing happened.
+    pub fn pulse_reset(&mut self) {
+        let _ = self.command(COMMAND_PULSE_RESET);
+        crate::time::arch_timer().spin_for(TIMEOUT);
+    }
+
+    /// Stop the keyboard from sending anything.
+    pub fn disable(&mut self) {
+        let _ = self.set_irq_enabled(false);
+        let _ = self.command(COMMAND_DISABLE_FIRST_PORT);
+    }
+}
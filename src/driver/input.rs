@@ -0,0 +1,144 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Interfaces for the devices that read what the user types.
+//!
+//! Everything is read as characters through [CharInput]. Keys that don't type anything, like the
+//! arrows, are turned into the escape sequences a VT100 terminal would send for them, so input
+//! from a keyboard looks the same as input from a serial terminal. Devices that receive bytes,
+//! like UARTs, decode them as UTF-8 with a [Utf8Decoder].
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A device that characters can be read from, such as a keyboard or the receiving side of a UART.
+pub trait CharInput {
+    /// The next character of input, or `None` if nothing new was typed.
+    fn read_char(&mut self) -> Option<char>;
+}
+
+/// A key that was pressed, after the keymap has been applied.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Key {
+    /// A key that types a character. Enter is `\n`, Backspace is `\x08` and Escape is `\x1b`.
+    Char(char),
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Insert,
+    Delete,
+    /// A function key, numbered from 1.
+    Function(u8),
+}
+
+/// The modifier keys that are held down, and the locks that are on.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub control: bool,
+    pub alt: bool,
+    pub caps_lock: bool,
+    pub num_lock: bool,
+}
+
+/// A key press, along with the modifiers that were active at the time.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub key: Key,
+    pub modifiers: Modifiers,
+}
+
+/// Turns a stream of UTF-8 bytes back into characters, one byte at a time.
+///
+/// Malformed input comes out as [char::REPLACEMENT_CHARACTER] rather than being dropped, so the
+/// user can see that something was typed.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Utf8Decoder {
+    /// The bits of the character decoded so far
+    code_point: u32,
+    /// How many more continuation bytes the character needs
+    remaining: u8,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Key {
+    /// What a VT100 terminal sends for a key that doesn't type a character.
+    pub const fn escape_sequence(self) -> Option<&'static str> {
+        let sequence = match self {
+            Key::Char(_) => return None,
+            Key::Up => "\x1b[A",
+            Key::Down => "\x1b[B",
+            Key::Right => "\x1b[C",
+            Key::Left => "\x1b[D",
+            Key::Home => "\x1b[H",
+            Key::End => "\x1b[F",
+            Key::Insert => "\x1b[2~",
+            Key::Delete => "\x1b[3~",
+            Key::PageUp => "\x1b[5~",
+            Key::PageDown => "\x1b[6~",
+            Key::Function(1) => "\x1bOP",
+            Key::Function(2) => "\x1bOQ",
+            Key::Function(3) => "\x1bOR",
+            Key::Function(4) => "\x1bOS",
+            Key::Function(5) => "\x1b[15~",
+            Key::Function(6) => "\x1b[17~",
+            Key::Function(7) => "\x1b[18~",
+            Key::Function(8) => "\x1b[19~",
+            Key::Function(9) => "\x1b[20~",
+            Key::Function(10) => "\x1b[21~",
+            Key::Function(11) => "\x1b[23~",
+            Key::Function(12) => "\x1b[24~",
+            Key::Function(_) => return None,
+        };
+        Some(sequence)
+    }
+}
+
+impl Utf8Decoder {
+    pub const fn new() -> Self {
+        Self {
+            code_point: 0,
+            remaining: 0,
+        }
+    }
+
+    /// Feed the next byte to the decoder, which returns a character once it has seen all of one.
+    pub fn push(&mut self, byte: u8) -> Option<char> {
+        let (bits, remaining) = match byte {
+            0x00..=0x7f => {
+                self.remaining = 0;
+                return Some(byte as char)
+            }
+            0x80..=0xbf if self.remaining > 0 => {
+                self.code_point = (self.code_point << 6) | u32::from(byte & 0x3f);
+                self.remaining -= 1;
+                if self.remaining > 0 {
+                    return None
+                }
+                // surrogates and code points past U+10FFFF aren't characters
+                return Some(char::from_u32(self.code_point).unwrap_or(char::REPLACEMENT_CHARACTER))
+            }
+            0xc0..=0xdf => (byte & 0x1f, 1),
+            0xe0..=0xef => (byte & 0x0f, 2),
+            0xf0..=0xf7 => (byte & 0x07, 3),
+            _ => {
+                self.remaining = 0;
+                return Some(char::REPLACEMENT_CHARACTER)
+            }
+        };
+        // a new character starting cuts off whatever was being decoded
+        self.code_point = u32::from(bits);
+        self.remaining = remaining;
+        None
+    }
+}
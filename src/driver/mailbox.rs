@@ -0,0 +1,640 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Driver for the BCM2837 mailbox, used to talk to the VideoCore firmware.
+//!
+//! Only the property channel (channel 8) is supported. A property message is a buffer in memory
+//! holding a list of tags, each of which is a request that the firmware overwrites with its
+//! response. The buffer's address is sent through the mailbox, and the firmware writes the same
+//! value back once it's done.
+//!
+//! The driver adds a `power` command to the shell, which shows the power state of the devices the
+//! firmware manages, or turns one on or off.
+//!
+//! Descriptions taken from
+//! <https://github.com/raspberrypi/firmware/wiki/Mailbox-property-interface>
+
+use crate::arch;
+use crate::driver::{self, registry::Registry, traits::Driver};
+use crate::shell::{self, Args, Output};
+use crate::sync::SpinMutex;
+use crate::time::SimpleTimer;
+use crate::{shell_command, warn};
+use core::sync::atomic::{compiler_fence, Ordering};
+use core::time::Duration;
+use tock_registers::interfaces::{Readable, Writeable};
+use tock_registers::registers::{ReadOnly, WriteOnly};
+use tock_registers::{register_bitfields, register_structs};
+use ufmt::uwriteln;
+
+register_bitfields! {
+    u32,
+
+    /// Mailbox status register
+    STATUS [
+        /// No more messages can be written to the mailbox
+        FULL OFFSET(31) NUMBITS(1) [],
+
+        /// There are no messages to read from the mailbox
+        EMPTY OFFSET(30) NUMBITS(1) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        /// Mailbox 0 is used for messages from the VideoCore to the ARM
+        (0x00 => READ: ReadOnly<u32>),
+        (0x04 => _reserved1),
+        (0x18 => STATUS0: ReadOnly<u32, STATUS::Register>),
+        (0x1c => _reserved2),
+        /// Mailbox 1 is used for messages from the ARM to the VideoCore
+        (0x20 => WRITE: WriteOnly<u32>),
+        (0x24 => _reserved3),
+        (0x38 => STATUS1: ReadOnly<u32, STATUS::Register>),
+        (0x3c => @END),
+    }
+}
+
+/// The channel for property tags sent from the ARM to the VideoCore
+const PROPERTY_CHANNEL: u32 = 8;
+
+/// Written to the buffer's code field to mark the message as a request
+const CODE_REQUEST: u32 = 0;
+/// The firmware successfully processed the message
+const CODE_RESPONSE_SUCCESS: u32 = 0x8000_0000;
+/// Set in a tag's code field by the firmware once it has responded to the tag
+const TAG_RESPONSE: u32 = 0x8000_0000;
+/// Marks the end of the tag list
+const TAG_END: u32 = 0;
+
+/// How long to wait for the firmware to respond
+const TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Size of the property buffer in 32-bit words
+const BUFFER_WORDS: usize = 64;
+/// Words used by the message header and end tag
+const MESSAGE_OVERHEAD_WORDS: usize = 3;
+/// Words used by each tag's header
+const TAG_OVERHEAD_WORDS: usize = 3;
+
+/// Alignment, in bytes, requested for the framebuffer
+const FRAMEBUFFER_ALIGNMENT: u32 = 16;
+
+shell_command! {
+    /// Show the power state of the firmware's devices, or turn one on or off
+    static POWER: "power" "[<device> on|off]" => power;
+}
+
+mod tag {
+    pub const FIRMWARE_REVISION: u32 = 0x0000_0001;
+    pub const BOARD_MODEL: u32 = 0x0001_0001;
+    pub const BOARD_REVISION: u32 = 0x0001_0002;
+    pub const BOARD_SERIAL: u32 = 0x0001_0004;
+    pub const ARM_MEMORY: u32 = 0x0001_0005;
+    pub const VC_MEMORY: u32 = 0x0001_0006;
+    pub const POWER_STATE: u32 = 0x0002_0001;
+    pub const SET_POWER_STATE: u32 = 0x0002_8001;
+    pub const CLOCK_RATE: u32 = 0x0003_0002;
+    pub const MAX_CLOCK_RATE: u32 = 0x0003_0004;
+    pub const SET_CLOCK_RATE: u32 = 0x0003_8002;
+    pub const TEMPERATURE: u32 = 0x0003_0006;
+    pub const MAX_TEMPERATURE: u32 = 0x0003_000a;
+    pub const ALLOCATE_BUFFER: u32 = 0x0004_0001;
+    pub const PHYSICAL_SIZE: u32 = 0x0004_0003;
+    pub const PITCH: u32 = 0x0004_0008;
+    pub const SET_PHYSICAL_SIZE: u32 = 0x0004_8003;
+    pub const SET_VIRTUAL_SIZE: u32 = 0x0004_8004;
+    pub const SET_DEPTH: u32 = 0x0004_8005;
+    pub const SET_PIXEL_ORDER: u32 = 0x0004_8006;
+    pub const SET_VIRTUAL_OFFSET: u32 = 0x0004_8009;
+}
+
+/// Clocks managed by the firmware
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Clock {
+    Emmc = 1,
+    Uart = 2,
+    Arm = 3,
+    Core = 4,
+    V3d = 5,
+    H264 = 6,
+    Isp = 7,
+    Sdram = 8,
+    Pixel = 9,
+    Pwm = 10,
+    Emmc2 = 12,
+}
+
+/// Devices whose power is managed by the firmware
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Device {
+    SdCard = 0,
+    Uart0 = 1,
+    Uart1 = 2,
+    UsbHcd = 3,
+    I2c0 = 4,
+    I2c1 = 5,
+    I2c2 = 6,
+    Spi = 7,
+    Ccp2tx = 8,
+}
+
+impl Device {
+    pub const ALL: [Device; 9] = [
+        Device::SdCard,
+        Device::Uart0,
+        Device::Uart1,
+        Device::UsbHcd,
+        Device::I2c0,
+        Device::I2c1,
+        Device::I2c2,
+        Device::Spi,
+        Device::Ccp2tx,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Device::SdCard => "sdcard",
+            Device::Uart0 => "uart0",
+            Device::Uart1 => "uart1",
+            Device::UsbHcd => "usb",
+            Device::I2c0 => "i2c0",
+            Device::I2c1 => "i2c1",
+            Device::I2c2 => "i2c2",
+            Device::Spi => "spi",
+            Device::Ccp2tx => "ccp2tx",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PowerState {
+    pub on: bool,
+    /// False if the firmware doesn't know about the device
+    pub exists: bool,
+}
+
+/// A region of physical memory
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub base: u32,
+    pub size: u32,
+}
+
+/// The order of the color channels in a pixel.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PixelOrder {
+    /// Blue in the lowest byte, i.e. `0x00RRGGBB` as a little-endian word
+    Bgr = 0,
+    /// Red in the lowest byte, i.e. `0x00BBGGRR` as a little-endian word
+    Rgb = 1,
+}
+
+/// A framebuffer the firmware allocated.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FramebufferInfo {
+    /// Address of the first pixel, as seen from the VideoCore's bus
+    pub bus_address: u32,
+    /// Size of the buffer in bytes
+    pub size: u32,
+    /// Visible width in pixels
+    pub width: u32,
+    /// Visible height in pixels
+    pub height: u32,
+    /// Distance in bytes between the starts of two rows
+    pub pitch: u32,
+    /// Bits per pixel
+    pub depth: u32,
+    pub pixel_order: PixelOrder,
+}
+
+/// The board revision code, which encodes the model, processor and memory size.
+///
+/// Described in <https://www.raspberrypi.com/documentation/computers/raspberry-pi.html>
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BoardRevision(pub u32);
+
+#[repr(C, align(16))]
+struct PropertyBuffer([u32; BUFFER_WORDS]);
+
+/// One tag in a property message.
+struct Tag<'a> {
+    id: u32,
+    /// The request, which is overwritten with the response
+    value: &'a mut [u32],
+    /// Length of the response in bytes, once there is one
+    response_len: usize,
+}
+
+impl<'a> Tag<'a> {
+    fn new(id: u32, value: &'a mut [u32]) -> Self {
+        Self { id, value, response_len: 0 }
+    }
+}
+
+// FIXME: This provides shared mutability over the register block. Add some sort of Mutex to
+// regulate that interior mutability.
+pub struct Mailbox {
+    regs: &'static mut RegisterBlock,
+    buffer: PropertyBuffer,
+}
+
+impl BoardRevision {
+    /// Whether this uses the new-style encoding. Old-style codes are just an opaque number.
+    pub fn is_new_style(&self) -> bool {
+        self.0 & (1 << 23) != 0
+    }
+
+    pub fn model_name(&self) -> &'static str {
+        if !self.is_new_style() {
+            return "Unknown (old-style revision)"
+        }
+
+        match (self.0 >> 4) & 0xff {
+            0x00 => "A",
+            0x01 => "B",
+            0x02 => "A+",
+            0x03 => "B+",
+            0x04 => "2B",
+            0x06 => "CM1",
+            0x08 => "3B",
+            0x09 => "Zero",
+            0x0a => "CM3",
+            0x0c => "Zero W",
+            0x0d => "3B+",
+            0x0e => "3A+",
+            0x10 => "CM3+",
+            0x11 => "4B",
+            0x12 => "Zero 2 W",
+            0x13 => "400",
+            0x14 => "CM4",
+            _ => "Unknown",
+        }
+    }
+
+    pub fn processor_name(&self) -> &'static str {
+        if !self.is_new_style() {
+            return "BCM2835"
+        }
+
+        match (self.0 >> 12) & 0xf {
+            0 => "BCM2835",
+            1 => "BCM2836",
+            2 => "BCM2837",
+            3 => "BCM2711",
+            _ => "Unknown",
+        }
+    }
+
+    /// Installed memory in MiB, if known.
+    pub fn memory_mib(&self) -> Option<u32> {
+        if !self.is_new_style() {
+            return None
+        }
+
+        match (self.0 >> 20) & 0b111 {
+            size @ 0..=5 => Some(256 << size),
+            _ => None,
+        }
+    }
+}
+
+impl Mailbox {
+    /// # Safety
+    /// The user must verify that the address for the register block is correct and
+    /// that no more than once instance of `Mailbox` exists for a base address at any
+    /// given time.
+    pub unsafe fn new(base_address: usize) -> Self {
+        Self {
+            regs: &mut *(base_address as *mut _),
+            buffer: PropertyBuffer([0; BUFFER_WORDS]),
+        }
+    }
+
+    /// Send the property buffer to the firmware and wait for it to respond.
+    fn call(&mut self) -> Result<(), driver::Error> {
+        // The buffer is 16-byte aligned, leaving the bottom 4 bits free for the channel. RAM
+        // visible to the VideoCore sits below 4GiB, so the address fits in 32 bits.
+        // FIXME: once the data cache is enabled, the buffer needs to be cleaned before sending and
+        // invalidated after receiving.
+        let message = (&self.buffer as *const PropertyBuffer as usize as u32) | PROPERTY_CHANNEL;
+        let timer = crate::time::arch_timer();
+        let deadline = timer.uptime() + TIMEOUT;
+
+        // make sure the buffer is written out before the firmware gets a chance to read it
+        compiler_fence(Ordering::SeqCst);
+
+        while self.regs.STATUS1.is_set(STATUS::FULL) {
+            if timer.uptime() > deadline {
+                return Err(driver::Error::Timeout)
+            }
+            arch::asm::nop()
+        }
+        self.regs.WRITE.set(message);
+
+        loop {
+            while self.regs.STATUS0.is_set(STATUS::EMPTY) {
+                if timer.uptime() > deadline {
+                    return Err(driver::Error::Timeout)
+                }
+                arch::asm::nop()
+            }
+
+            // responses to other channels, or to someone else's message, aren't for us
+            if self.regs.READ.get() == message {
+                break
+            }
+        }
+
+        // the firmware wrote the response behind the compiler's back
+        compiler_fence(Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Send several property tags in one message, replacing their values with the responses.
+    fn properties(&mut self, tags: &mut [Tag<'_>]) -> Result<(), driver::Error> {
+        let total_words = MESSAGE_OVERHEAD_WORDS
+            + tags.iter().map(|tag| TAG_OVERHEAD_WORDS + tag.value.len()).sum::<usize>();
+        assert!(total_words <= BUFFER_WORDS, "property tags too large for the mailbox buffer");
+
+        let buffer = &mut self.buffer.0;
+        buffer[0] = (total_words * 4) as u32;
+        buffer[1] = CODE_REQUEST;
+        let mut offset = 2;
+        for tag in tags.iter() {
+            let value_words = tag.value.len();
+            buffer[offset] = tag.id;
+            buffer[offset + 1] = (value_words * 4) as u32;
+            buffer[offset + 2] = 0;
+            buffer[offset + 3..offset + 3 + value_words].copy_from_slice(tag.value);
+            offset += TAG_OVERHEAD_WORDS + value_words;
+        }
+        buffer[offset] = TAG_END;
+
+        self.call()?;
+
+        let buffer = &self.buffer.0;
+        if buffer[1] != CODE_RESPONSE_SUCCESS {
+            return Err(driver::Error::io::<Self>("the firmware rejected the request"))
+        }
+        let mut offset = 2;
+        for tag in tags.iter_mut() {
+            let value_words = tag.value.len();
+            let tag_code = buffer[offset + 2];
+            if tag_code & TAG_RESPONSE == 0 {
+                return Err(driver::Error::io::<Self>("the firmware doesn't know the tag"))
+            }
+            tag.response_len = (tag_code & !TAG_RESPONSE) as usize;
+            tag.value.copy_from_slice(&buffer[offset + 3..offset + 3 + value_words]);
+            offset += TAG_OVERHEAD_WORDS + value_words;
+        }
+        Ok(())
+    }
+
+    /// Send a single property tag and return the response's value words.
+    fn property<const REQ: usize, const RESP: usize>(
+        &mut self,
+        tag: u32,
+        request: [u32; REQ],
+    ) -> Result<[u32; RESP], driver::Error> {
+        let mut value = [0; BUFFER_WORDS];
+        let value = &mut value[..REQ.max(RESP)];
+        value[..REQ].copy_from_slice(&request);
+
+        let mut tags = [Tag::new(tag, value)];
+        self.properties(&mut tags)?;
+        let [tag] = tags;
+        if tag.response_len < RESP * 4 {
+            return Err(driver::Error::io::<Self>("the response is too short"))
+        }
+
+        let mut response = [0; RESP];
+        response.copy_from_slice(&tag.value[..RESP]);
+        Ok(response)
+    }
+
+    pub fn firmware_revision(&mut self) -> Result<u32, driver::Error> {
+        let [revision] = self.property(tag::FIRMWARE_REVISION, [])?;
+        Ok(revision)
+    }
+
+    pub fn board_model(&mut self) -> Result<u32, driver::Error> {
+        let [model] = self.property(tag::BOARD_MODEL, [])?;
+        Ok(model)
+    }
+
+    pub fn board_revision(&mut self) -> Result<BoardRevision, driver::Error> {
+        let [revision] = self.property(tag::BOARD_REVISION, [])?;
+        Ok(BoardRevision(revision))
+    }
+
+    pub fn board_serial(&mut self) -> Result<u64, driver::Error> {
+        let [low, high] = self.property(tag::BOARD_SERIAL, [])?;
+        Ok((high as u64) << 32 | low as u64)
+    }
+
+    /// The memory reserved for the ARM cores.
+    pub fn arm_memory(&mut self) -> Result<MemoryRegion, driver::Error> {
+        let [base, size] = self.property(tag::ARM_MEMORY, [])?;
+        Ok(MemoryRegion { base, size })
+    }
+
+    /// The memory reserved for the VideoCore.
+    pub fn vc_memory(&mut self) -> Result<MemoryRegion, driver::Error> {
+        let [base, size] = self.property(tag::VC_MEMORY, [])?;
+        Ok(MemoryRegion { base, size })
+    }
+
+    /// The current rate of the clock in Hz.
+    pub fn clock_rate(&mut self, clock: Clock) -> Result<u32, driver::Error> {
+        let [_, rate] = self.property(tag::CLOCK_RATE, [clock as u32])?;
+        Ok(rate)
+    }
+
+    /// The maximum supported rate of the clock in Hz.
+    pub fn max_clock_rate(&mut self, clock: Clock) -> Result<u32, driver::Error> {
+        let [_, rate] = self.property(tag::MAX_CLOCK_RATE, [clock as u32])?;
+        Ok(rate)
+    }
+
+    /// Request a new clock rate in Hz, returning the rate that was actually set.
+    ///
+    /// Unless `skip_turbo` is set, the firmware may also raise other clocks to match when the ARM
+    /// clock is set above its default.
+    pub fn set_clock_rate(
+        &mut self,
+        clock: Clock,
+        rate: u32,
+        skip_turbo: bool,
+    ) -> Result<u32, driver::Error> {
+        let request = [clock as u32, rate, skip_turbo as u32];
+        let [_, rate] = self.property(tag::SET_CLOCK_RATE, request)?;
+        Ok(rate)
+    }
+
+    pub fn power_state(&mut self, device: Device) -> Result<PowerState, driver::Error> {
+        let [_, state] = self.property(tag::POWER_STATE, [device as u32])?;
+        Ok(PowerState::from_raw(state))
+    }
+
+    /// Turn a device on or off, returning its new state.
+    ///
+    /// If `wait` is set, the firmware waits for the device to become stable before responding.
+    pub fn set_power_state(
+        &mut self,
+        device: Device,
+        on: bool,
+        wait: bool,
+    ) -> Result<PowerState, driver::Error> {
+        let request = [device as u32, on as u32 | (wait as u32) << 1];
+        let [_, state] = self.property(tag::SET_POWER_STATE, request)?;
+        Ok(PowerState::from_raw(state))
+    }
+
+    /// The SoC temperature in thousandths of a degree Celsius.
+    pub fn temperature(&mut self) -> Result<u32, driver::Error> {
+        let [_, temperature] = self.property(tag::TEMPERATURE, [0])?;
+        Ok(temperature)
+    }
+
+    /// The temperature at which the firmware starts throttling, in thousandths of a degree Celsius.
+    pub fn max_temperature(&mut self) -> Result<u32, driver::Error> {
+        let [_, temperature] = self.property(tag::MAX_TEMPERATURE, [0])?;
+        Ok(temperature)
+    }
+
+    /// The width and height of the attached display in pixels. Both are 0 if there's no display.
+    pub fn physical_size(&mut self) -> Result<(u32, u32), driver::Error> {
+        let [width, height] = self.property(tag::PHYSICAL_SIZE, [])?;
+        Ok((width, height))
+    }
+
+    /// Ask the firmware for a `width` by `height` framebuffer with 32 bits per pixel.
+    ///
+    /// The firmware may pick a different size or pixel order than what was asked for, so check the
+    /// returned [FramebufferInfo].
+    pub fn allocate_framebuffer(
+        &mut self,
+        width: u32,
+        height: u32,
+    ) -> Result<FramebufferInfo, driver::Error> {
+        let mut physical_size = [width, height];
+        let mut virtual_size = [width, height];
+        let mut virtual_offset = [0, 0];
+        let mut depth = [32];
+        let mut pixel_order = [PixelOrder::Rgb as u32];
+        let mut buffer = [FRAMEBUFFER_ALIGNMENT, 0];
+        let mut pitch = [0];
+
+        // the buffer is only allocated with the settings that are sent along with it
+        self.properties(&mut [
+            Tag::new(tag::SET_PHYSICAL_SIZE, &mut physical_size),
+            Tag::new(tag::SET_VIRTUAL_SIZE, &mut virtual_size),
+            Tag::new(tag::SET_VIRTUAL_OFFSET, &mut virtual_offset),
+            Tag::new(tag::SET_DEPTH, &mut depth),
+            Tag::new(tag::SET_PIXEL_ORDER, &mut pixel_order),
+            Tag::new(tag::ALLOCATE_BUFFER, &mut buffer),
+            Tag::new(tag::PITCH, &mut pitch),
+        ])?;
+
+        let [bus_address, size] = buffer;
+        if bus_address == 0 || size == 0 {
+            return Err(driver::Error::io::<Self>("the firmware didn't allocate a framebuffer"))
+        }
+        Ok(FramebufferInfo {
+            bus_address,
+            size,
+            width: physical_size[0],
+            height: physical_size[1],
+            pitch: pitch[0],
+            depth: depth[0],
+            pixel_order: if pixel_order[0] == PixelOrder::Bgr as u32 {
+                PixelOrder::Bgr
+            } else {
+                PixelOrder::Rgb
+            },
+        })
+    }
+}
+
+impl PowerState {
+    fn from_raw(state: u32) -> Self {
+        Self {
+            on: state & 0b01 != 0,
+            exists: state & 0b10 == 0,
+        }
+    }
+}
+
+fn power(args: &mut Args<'_>, out: &mut Output) -> Result<(), shell::Error> {
+    let device = args.next();
+    let on = match args.next() {
+        Some("on") => Some(true),
+        Some("off") => Some(false),
+        Some(_) => return Err(shell::Error::Usage),
+        None => None,
+    };
+    args.finish()?;
+
+    let mailbox = crate::DRIVERS
+        .get()
+        .get::<SpinMutex<Mailbox>>()
+        .ok_or(shell::Error::Failed("the mailbox driver isn't running"))?;
+    match (device, on) {
+        (None, _) => {
+            mailbox.with_lock(|mailbox| -> Result<(), driver::Error> {
+                for &device in Device::ALL.iter() {
+                    let state = mailbox.power_state(device)?;
+                    if state.exists {
+                        let state = if state.on { "on" } else { "off" };
+                        let _ = uwriteln!(out, "{}: {}", device.name(), state);
+                    }
+                }
+                Ok(())
+            })?;
+            Ok(())
+        }
+        (Some(name), Some(on)) => {
+            let device = Device::ALL
+                .iter()
+                .copied()
+                .find(|device| device.name() == name)
+                .ok_or(shell::Error::Failed("no such device"))?;
+            let state = mailbox.with_lock(|mailbox| mailbox.set_power_state(device, on, true))?;
+            if !state.exists {
+                return Err(shell::Error::Failed("the firmware doesn't know the device"))
+            }
+            if state.on != on {
+                return Err(shell::Error::Failed("the device didn't change state"))
+            }
+            Ok(())
+        }
+        (Some(_), None) => Err(shell::Error::Usage),
+    }
+}
+
+impl Driver for Mailbox {
+    const COMPATIBLE: &'static str = "brcm,bcm2835-mbox";
+
+    /// Ask for the firmware revision, which any firmware that speaks the property interface knows.
+    fn probe(&mut self) -> Result<(), driver::Error> {
+        match self.firmware_revision() {
+            Ok(_) => Ok(()),
+            Err(driver::Error::Timeout) => Err(driver::Error::NotPresent),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn init(&mut self, _registry: &Registry) -> Result<(), driver::Error> {
+        // the shell can do without the command, so that's not a reason to fail
+        if let Err(e) = shell::register(&POWER) {
+            warn!("Couldn't add the 'power' command: {}", e);
+        }
+        Ok(())
+    }
+}
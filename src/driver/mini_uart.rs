@@ -0,0 +1,379 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Driver for the mini UART (UART1) in the BCM auxiliary peripherals block.
+//!
+//! The mini UART is clocked from the VideoCore core clock, so its baud rate drifts if the firmware
+//! scales that clock. Set `core_freq` (or `enable_uart=1`) in `config.txt` to keep it fixed.
+
+use crate::arch;
+use crate::driver::{self, registry::Registry, traits::Driver, WriteError};
+use crate::driver::gpio::{Alt5, Gpio, Pin, Pull};
+use crate::driver::input::{CharInput, Utf8Decoder};
+use crate::driver::uart::{baud_within_tolerance, Parity, StopBits, Uart, UartConfig, WordLength};
+use crate::sync::SpinMutex;
+use tock_registers::interfaces::{ReadWriteable, Readable, Writeable};
+use tock_registers::registers::{ReadOnly, ReadWrite, WriteOnly};
+use tock_registers::{register_bitfields, register_structs};
+
+// Description taken from
+// https://github.com/raspberrypi/documentation/files/1888662/BCM2837-ARM-Peripherals.-.Revised.-.V2-1.pdf
+register_bitfields! {
+    u32,
+
+    /// Auxiliary enables. Turns the mini UART and the two SPI masters on and off.
+    AUX_ENABLES [
+        /// If set, the mini UART is enabled and its registers are accessible.
+        MINI_UART OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// Mini UART Interrupt Identify. Writing to it clears the FIFOs.
+    AUX_MU_IIR [
+        /// Clear the transmit FIFO
+        CLEAR_TX_FIFO OFFSET(2) NUMBITS(1) [],
+
+        /// Clear the receive FIFO
+        CLEAR_RX_FIFO OFFSET(1) NUMBITS(1) []
+    ],
+
+    /// Mini UART Line Control
+    AUX_MU_LCR [
+        /// Gives access to the baud rate register through the IO and IER registers.
+        DLAB OFFSET(7) NUMBITS(1) [],
+
+        /// Data size. The datasheet documents this as a single bit, but both bits have to be set
+        /// for 8 bit mode.
+        DATA_SIZE OFFSET(0) NUMBITS(2) [
+            SevenBit = 0b00,
+            EightBit = 0b11
+        ]
+    ],
+
+    /// Mini UART Line Status
+    AUX_MU_LSR [
+        /// The transmit FIFO is empty and the transmitter is idle.
+        TX_IDLE OFFSET(6) NUMBITS(1) [],
+
+        /// The transmit FIFO can accept at least one byte.
+        TX_EMPTY OFFSET(5) NUMBITS(1) [],
+
+        /// The receive FIFO holds at least one byte.
+        DATA_READY OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// Mini UART Extra Control
+    AUX_MU_CNTL [
+        /// Transmitter enable
+        TX_ENABLE OFFSET(1) NUMBITS(1) [],
+
+        /// Receiver enable
+        RX_ENABLE OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// Mini UART Baudrate. The baud rate is `core clock / (8 * (BAUD + 1))`.
+    AUX_MU_BAUD [
+        BAUD OFFSET(0) NUMBITS(16) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => AUX_IRQ: ReadOnly<u32>),
+        (0x04 => AUX_ENABLES: ReadWrite<u32, AUX_ENABLES::Register>),
+        (0x08 => _reserved1),
+        (0x40 => AUX_MU_IO: ReadWrite<u32>),
+        (0x44 => AUX_MU_IER: ReadWrite<u32>),
+        (0x48 => AUX_MU_IIR: WriteOnly<u32, AUX_MU_IIR::Register>),
+        (0x4c => AUX_MU_LCR: ReadWrite<u32, AUX_MU_LCR::Register>),
+        (0x50 => AUX_MU_MCR: ReadWrite<u32>),
+        (0x54 => AUX_MU_LSR: ReadOnly<u32, AUX_MU_LSR::Register>),
+        (0x58 => AUX_MU_MSR: ReadOnly<u32>),
+        (0x5c => AUX_MU_SCRATCH: ReadWrite<u32>),
+        (0x60 => AUX_MU_CNTL: ReadWrite<u32, AUX_MU_CNTL::Register>),
+        (0x64 => AUX_MU_STAT: ReadOnly<u32>),
+        (0x68 => AUX_MU_BAUD: ReadWrite<u32, AUX_MU_BAUD::Register>),
+        (0x6c => @END),
+    }
+}
+
+/// Offset of the mini UART's own registers in the auxiliary peripherals block. This is where device
+/// trees point the mini UART's `reg`.
+pub const AUX_MU_OFFSET: usize = 0x40;
+
+// FIXME:
+// Currently this is unsafe since it gives shared mutability over the register block. Really this
+// should contain some sort of Mutex to guard interior mutability.
+pub struct MiniUart {
+    regs: &'static mut RegisterBlock,
+    /// Frequency of the VideoCore core clock, in Hz
+    core_clock_hz: u32,
+    config: UartConfig,
+    /// What's been received of a character that's split across reads
+    decoder: Utf8Decoder,
+    /// Whether `init` should route the UART to GPIO 14 and 15
+    route_pins: bool,
+    /// TX and RX, once they've been claimed by `claim_pins`
+    pins: Option<(Pin<Alt5, 14>, Pin<Alt5, 15>)>,
+}
+
+impl MiniUart {
+    /// # Safety
+    /// The user must verify that the address is the base of the auxiliary peripherals block, and
+    /// that `core_clock_hz` is the frequency of the VideoCore core clock.
+    ///
+    /// The UART is set up with `config` once the driver is initialized.
+    pub unsafe fn new(base_address: usize, core_clock_hz: u32, config: UartConfig) -> Self {
+        Self {
+            regs: &mut *(base_address as *mut _),
+            core_clock_hz,
+            config,
+            decoder: Utf8Decoder::new(),
+            route_pins: false,
+            pins: None,
+        }
+    }
+
+    /// Route the UART to GPIO pins 14 (TX) and 15 (RX) when it's initialized. This makes the driver
+    /// depend on the GPIO driver.
+    pub fn route_to_pins(&mut self) {
+        self.route_pins = true;
+    }
+}
+
+impl MiniUart {
+    fn claim_pins(&mut self, gpio: &mut Gpio) -> Result<(), driver::Error> {
+        let tx = gpio.claim::<14>()?;
+        let rx = match gpio.claim::<15>() {
+            Ok(rx) => rx,
+            Err(e) => {
+                gpio.release(tx);
+                return Err(e)
+            }
+        };
+        let mut tx = tx.into_alt::<Alt5>();
+        let mut rx = rx.into_alt::<Alt5>();
+        tx.set_pull(Pull::None);
+        rx.set_pull(Pull::None);
+        self.pins = Some((tx, rx));
+        Ok(())
+    }
+
+    /// Wait until everything that was written has been sent.
+    fn flush(&self) {
+        while !self.regs.AUX_MU_LSR.is_set(AUX_MU_LSR::TX_IDLE) {
+            arch::asm::nop()
+        }
+    }
+}
+
+/// Make sure the config only asks for what the mini UART can do. It has no parity, a single stop
+/// bit and either 7 or 8 data bits.
+fn check_config(config: UartConfig) -> Result<(), driver::Error> {
+    let word_length_ok = matches!(config.word_length, WordLength::Seven | WordLength::Eight);
+    if !word_length_ok || config.parity != Parity::None || config.stop_bits != StopBits::One {
+        return Err(driver::Error::BadConfiguration)
+    }
+    Ok(())
+}
+
+/// Compute the value of the baud rate register, rounding to the nearest rate.
+fn baud_divisor(core_clock_hz: u32, baud: u32) -> Result<u32, driver::Error> {
+    if baud == 0 {
+        return Err(driver::Error::UnsupportedBaudRate)
+    }
+
+    let divider = (u64::from(core_clock_hz) + 4 * u64::from(baud)) / (8 * u64::from(baud));
+    if divider == 0 || divider > 0x1_0000 {
+        return Err(driver::Error::UnsupportedBaudRate)
+    }
+
+    let actual = u64::from(core_clock_hz) / (8 * divider);
+    if !baud_within_tolerance(baud, actual) {
+        return Err(driver::Error::UnsupportedBaudRate)
+    }
+
+    Ok(divider as u32 - 1)
+}
+
+impl Uart for MiniUart {
+    fn send_ready(&self) -> bool {
+        self.regs.AUX_MU_LSR.is_set(AUX_MU_LSR::TX_EMPTY)
+    }
+
+    fn send(&mut self, byte: u8) {
+        while !self.send_ready() {
+            arch::asm::nop()
+        }
+        self.regs.AUX_MU_IO.set(byte as u32)
+    }
+
+    fn receive_ready(&self) -> bool {
+        self.regs.AUX_MU_LSR.is_set(AUX_MU_LSR::DATA_READY)
+    }
+
+    fn receive(&mut self) -> u8 {
+        while !self.receive_ready() {
+            arch::asm::nop()
+        }
+        self.regs.AUX_MU_IO.get() as u8
+    }
+
+    fn config(&self) -> UartConfig {
+        self.config
+    }
+
+    fn set_config(&mut self, config: UartConfig) -> Result<(), driver::Error> {
+        check_config(config)?;
+        let divisor = baud_divisor(self.core_clock_hz, config.baud)?;
+
+        // let anything that's still queued go out with the old settings
+        let was_enabled = self.regs.AUX_MU_CNTL.is_set(AUX_MU_CNTL::TX_ENABLE);
+        if was_enabled {
+            self.flush();
+        }
+        self.regs.AUX_MU_CNTL.set(0);
+
+        self.regs.AUX_MU_LCR.write(match config.word_length {
+            WordLength::Seven => AUX_MU_LCR::DATA_SIZE::SevenBit,
+            _ => AUX_MU_LCR::DATA_SIZE::EightBit,
+        });
+        self.regs.AUX_MU_BAUD.write(AUX_MU_BAUD::BAUD.val(divisor));
+        self.config = config;
+
+        self.regs.AUX_MU_CNTL.write(AUX_MU_CNTL::TX_ENABLE::SET + AUX_MU_CNTL::RX_ENABLE::SET);
+        Ok(())
+    }
+}
+
+impl ufmt::uWrite for MiniUart {
+    type Error = WriteError;
+
+    fn write_str(&mut self, msg: &str) -> Result<(), Self::Error> {
+        for byte in msg.bytes() {
+            if byte == b'\n' {
+                self.send(b'\r')
+            }
+            self.send(byte)
+        }
+        Ok(())
+    }
+}
+
+impl Driver for MiniUart {
+    const COMPATIBLE: &'static str = "brcm,bcm2835-aux-uart";
+
+    fn dependencies(&self) -> &'static [&'static str] {
+        if self.route_pins {
+            &[Gpio::COMPATIBLE]
+        } else {
+            &[]
+        }
+    }
+
+    fn init(&mut self, registry: &Registry) -> Result<(), driver::Error> {
+        // make sure the settings work before touching the hardware
+        let config = self.config;
+        check_config(config)?;
+        baud_divisor(self.core_clock_hz, config.baud)?;
+
+        if self.route_pins && self.pins.is_none() {
+            let gpio = registry.get::<SpinMutex<Gpio>>().ok_or(driver::Error::Unavailable)?;
+            gpio.with_lock(|gpio| self.claim_pins(gpio))?;
+        }
+
+        // the mini UART's registers can't be accessed until it's enabled
+        self.regs.AUX_ENABLES.modify(AUX_ENABLES::MINI_UART::SET);
+
+        // turn everything off while it's being set up
+        self.regs.AUX_MU_CNTL.set(0);
+        self.regs.AUX_MU_IER.set(0);
+        self.regs.AUX_MU_MCR.set(0);
+        self.regs.AUX_MU_IIR.write(AUX_MU_IIR::CLEAR_TX_FIFO::SET + AUX_MU_IIR::CLEAR_RX_FIFO::SET);
+
+        self.set_config(config)
+    }
+
+    fn shutdown(&mut self) {
+        if self.regs.AUX_MU_CNTL.is_set(AUX_MU_CNTL::TX_ENABLE) {
+            self.flush();
+        }
+        self.regs.AUX_MU_CNTL.set(0);
+    }
+}
+
+impl CharInput for MiniUart {
+    fn read_char(&mut self) -> Option<char> {
+        while self.receive_ready() {
+            let byte = self.receive_native();
+            if let Some(c) = self.decoder.push(byte) {
+                return Some(c)
+            }
+        }
+        None
+    }
+}
+
+impl AsMut<dyn ufmt::uWrite<Error=WriteError>> for MiniUart {
+    fn as_mut(&mut self) -> &mut (dyn ufmt::uWrite<Error=WriteError> + 'static) {
+        self
+    }
+}
+
+impl AsMut<dyn CharInput> for MiniUart {
+    fn as_mut(&mut self) -> &mut (dyn CharInput + 'static) {
+        self
+    }
+}
+
+/// Minimal, polled writer for a mini UART that was already set up, e.g. by the firmware.
+///
+/// Like [crate::driver::pl011::PL011Polled], this holds no state and takes no locks. It's meant
+/// for early boot output and panics only.
+pub struct MiniUartPolled {
+    base_address: usize,
+}
+
+impl MiniUartPolled {
+    /// # Safety
+    /// The user must verify that the address is the base of the auxiliary peripherals block.
+    pub const unsafe fn new(base_address: usize) -> Self {
+        Self { base_address }
+    }
+
+    fn regs(&self) -> &RegisterBlock {
+        // SAFETY: the address was checked by the caller of `new`
+        unsafe { &*(self.base_address as *const RegisterBlock) }
+    }
+
+    fn send(&self, byte: u8) {
+        let regs = self.regs();
+        while !regs.AUX_MU_LSR.is_set(AUX_MU_LSR::TX_EMPTY) {
+            arch::asm::nop()
+        }
+        regs.AUX_MU_IO.set(byte as u32)
+    }
+}
+
+impl ufmt::uWrite for MiniUartPolled {
+    type Error = WriteError;
+
+    fn write_str(&mut self, msg: &str) -> Result<(), Self::Error> {
+        // a disabled transmitter never drains its FIFO, so we'd spin forever once it filled up
+        let regs = self.regs();
+        if !regs.AUX_ENABLES.is_set(AUX_ENABLES::MINI_UART)
+            || !regs.AUX_MU_CNTL.is_set(AUX_MU_CNTL::TX_ENABLE)
+        {
+            return Err(WriteError::NotReady)
+        }
+
+        for byte in msg.bytes() {
+            if byte == b'\n' {
+                self.send(b'\r')
+            }
+            self.send(byte)
+        }
+        Ok(())
+    }
+}
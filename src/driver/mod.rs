@@ -2,48 +2,227 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
+#[cfg(target_arch = "x86_64")]
+pub mod ansi;
+#[cfg(target_arch = "aarch64")]
+pub mod arm_timer;
+#[cfg(feature = "bsp_rpi3")]
+pub mod bcm_intc;
+#[cfg(feature = "bsp_rpi3")]
+pub mod bcm_rng;
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+pub mod bcm_timer;
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+pub mod bcm_watchdog;
+#[cfg(target_arch = "x86_64")]
+pub mod cp437;
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+pub mod fb_console;
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+pub mod font8x8;
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+pub mod framebuffer;
+#[cfg(any(feature = "bsp_rpi4", feature = "bsp_qemu_virt"))]
+pub mod gicv2;
+#[cfg(feature = "bsp_qemu_virt")]
+pub mod gicv3;
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
 pub mod gpio;
+#[cfg(target_arch = "x86_64")]
+pub mod hpet;
+#[cfg(target_arch = "x86_64")]
+pub mod i8042;
+pub mod input;
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+pub mod mailbox;
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+pub mod mini_uart;
+#[cfg(target_arch = "x86_64")]
+pub mod pic8259;
+#[cfg(target_arch = "x86_64")]
+pub mod pit8254;
+#[cfg(target_arch = "aarch64")]
+pub mod pl011;
+#[cfg(target_arch = "x86_64")]
+pub mod ps2_keyboard;
+#[cfg(feature = "bsp_qemu_virt")]
+pub mod psci;
+#[cfg(target_arch = "x86_64")]
+pub mod rdrand;
+pub mod registry;
+#[cfg(feature = "bsp_rpi4")]
+pub mod rng200;
+#[cfg(target_arch = "x86_64")]
 pub mod text_vga;
 pub mod uart;
+#[cfg(target_arch = "x86_64")]
+pub mod uart_16550;
 
 #[derive(Debug)]
 pub enum WriteError {
-    /// Indicates that someone attempted to write unicode text to a device that doesn't support it.
+    /// The device can't accept output right now, e.g. because it's disabled.
     ///
-    /// This likely indicates that the device is ASCII-only.
-    UnicodeUnsupported,
+    /// Nothing was written, so the write can be retried later.
+    NotReady,
 }
 
-#[derive(Debug)]
+/// Why a driver couldn't do what it was asked to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Error {
+    /// The device didn't respond in time.
+    Timeout,
+
+    /// There's no device where the driver expected one, or it isn't the kind of device the driver
+    /// handles.
+    NotPresent,
+
+    /// The device reported an error, or sent a response that didn't make sense.
+    Io {
+        /// The `compatible` string of the driver that saw the error
+        device: &'static str,
+        /// What went wrong, for the log
+        reason: &'static str,
+    },
+
+    /// The requested baud rate can't be generated from the device's clock.
+    UnsupportedBaudRate,
+
+    /// The resource is already in use, e.g. a pin that another driver claimed.
+    Busy,
+
+    /// The requested configuration isn't valid for this device.
+    BadConfiguration,
+
+    /// A driver this one needs is missing or failed to initialize.
+    Unavailable,
+}
+
+impl Error {
+    /// An [Error::Io] raised by the driver `T`.
+    pub fn io<T: traits::Driver>(reason: &'static str) -> Self {
+        Error::Io { device: T::COMPATIBLE, reason }
+    }
+}
+
+impl ufmt::uDebug for Error {
+    fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: ufmt::uWrite + ?Sized
+    {
+        match self {
+            Error::Timeout => f.write_str("Timeout"),
+            Error::NotPresent => f.write_str("NotPresent"),
+            Error::Io { device, reason } => f
+                .debug_struct("Io")?
+                .field("device", device)?
+                .field("reason", reason)?
+                .finish(),
+            Error::UnsupportedBaudRate => f.write_str("UnsupportedBaudRate"),
+            Error::Busy => f.write_str("Busy"),
+            Error::BadConfiguration => f.write_str("BadConfiguration"),
+            Error::Unavailable => f.write_str("Unavailable"),
+        }
+    }
+}
+
+impl ufmt::uDisplay for Error {
+    fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: ufmt::uWrite + ?Sized
+    {
+        match self {
+            Error::Timeout => f.write_str("the device timed out"),
+            Error::NotPresent => f.write_str("no such device"),
+            Error::Io { device, reason } => ufmt::uwrite!(f, "I/O error on {}: {}", device, reason),
+            Error::UnsupportedBaudRate => f.write_str("unsupported baud rate"),
+            Error::Busy => f.write_str("the device or resource is busy"),
+            Error::BadConfiguration => f.write_str("invalid configuration"),
+            Error::Unavailable => f.write_str("a driver it depends on is unavailable"),
+        }
+    }
 }
 
 pub mod traits {
+    use super::{registry::Registry, Error};
     use crate::sync::*;
+    use core::any::Any;
 
     /// Trait that's implemented by all drivers
+    ///
+    /// Drivers are brought up by the [Registry] in two steps. [Driver::probe] is called on every
+    /// driver first, then [Driver::init] is called once everything in [Driver::dependencies] is
+    /// initialized.
     pub trait Driver {
+        /// The device tree `compatible` string of the devices this driver handles, or a descriptive
+        /// name if they aren't described by device trees.
         const COMPATIBLE: &'static str;
-    }
 
-    /// Object-safe trait that can be implemented for Mutex-protected drivers
-    pub trait Compatible {
-        fn compatible(&self) -> &'static str;
-    }
+        /// Other `compatible` strings of devices that work with this driver.
+        const ALSO_COMPATIBLE: &'static [&'static str] = &[];
 
-    impl<T: Driver> Compatible for T {
-        fn compatible(&self) -> &'static str {
-            T::COMPATIBLE
+        /// `compatible` strings of the drivers that have to be initialized before this one.
+        fn dependencies(&self) -> &'static [&'static str] {
+            &[]
+        }
+
+        /// Check that the device is there, without changing its state.
+        fn probe(&mut self) -> Result<(), Error> {
+            Ok(())
         }
+
+        /// Get the device ready to be used. The drivers it depends on can be looked up in
+        /// `registry`.
+        fn init(&mut self, _registry: &Registry) -> Result<(), Error> {
+            Ok(())
+        }
+
+        /// Quiesce the device, e.g. before the machine is turned off.
+        fn shutdown(&mut self) {}
+    }
+
+    /// Object-safe trait that's implemented for Mutex-protected drivers, so the [Registry] can hold
+    /// all of them
+    pub trait Compatible: Sync {
+        fn compatible(&self) -> &'static str;
+        fn is_compatible(&self, compatible: &str) -> bool;
+        fn dependencies(&self) -> &'static [&'static str];
+        fn probe(&self) -> Result<(), Error>;
+        fn init(&self, registry: &Registry) -> Result<(), Error>;
+        fn shutdown(&self);
+        fn as_any(&self) -> &dyn Any;
     }
 
     impl<R, T> Compatible for Mutex<R, T>
     where
-        R: RawMutex,
-        T: Driver,
+        R: RawMutex + Sync + 'static,
+        T: Driver + Send + 'static,
     {
         fn compatible(&self) -> &'static str {
             T::COMPATIBLE
         }
+
+        fn is_compatible(&self, compatible: &str) -> bool {
+            T::COMPATIBLE == compatible || T::ALSO_COMPATIBLE.iter().any(|&c| c == compatible)
+        }
+
+        fn dependencies(&self) -> &'static [&'static str] {
+            self.with_lock(|driver| driver.dependencies())
+        }
+
+        fn probe(&self) -> Result<(), Error> {
+            self.with_lock(|driver| driver.probe())
+        }
+
+        fn init(&self, registry: &Registry) -> Result<(), Error> {
+            self.with_lock(|driver| driver.init(registry))
+        }
+
+        fn shutdown(&self) {
+            self.try_with_lock(|driver| driver.shutdown());
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
     }
 }
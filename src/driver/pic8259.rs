@@ -0,0 +1,193 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Driver for the pair of cascaded 8259 programmable interrupt controllers that PCs have.
+//!
+//! The secondary controller is wired to line 2 of the primary one, which gives 15 usable lines
+//! numbered 0 to 15. They're delivered to the CPU on the vectors starting at
+//! [IRQ_VECTOR_BASE](crate::arch::exception::IRQ_VECTOR_BASE), out of the way of the CPU
+//! exceptions.
+
+use crate::arch::exception::IRQ_VECTOR_BASE;
+use crate::driver::{self, registry::Registry, traits::Driver};
+use crate::interrupt::{InterruptController, IrqNumber};
+use x86::io::{inb, outb};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+pub struct Pic8259 {
+    primary_port: u16,
+    secondary_port: u16,
+    /// Which lines are masked, with the primary's in the low byte and the secondary's in the high
+    masks: u16,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Lines per controller
+const LINE_COUNT: IrqNumber = 8;
+
+/// The primary's line that the secondary is connected to
+const CASCADE_LINE: u8 = 2;
+
+// Register offsets from each controller's base I/O port
+const COMMAND: u16 = 0;
+const DATA: u16 = 1;
+
+/// Start initialization. ICW4 follows.
+const ICW1_INIT: u8 = 0x11;
+/// Use 8086 mode rather than 8080 mode
+const ICW4_8086: u8 = 0x01;
+
+/// Make the next read of the command register return the in-service register.
+const OCW3_READ_ISR: u8 = 0x0b;
+/// Non-specific end of interrupt
+const OCW2_EOI: u8 = 0x20;
+
+/// An arbitrary mask that's written and read back to check that the controller is there
+const PROBE_MASK: u8 = 0b1010_0101;
+
+/// Port that's safe to write to, which gives the controllers time between initialization words
+const DELAY_PORT: u16 = 0x80;
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Pic8259 {
+    fn write(&mut self, port: u16, value: u8) {
+        // SAFETY: the ports were checked by the caller of `new`
+        unsafe {
+            outb(port, value);
+            outb(DELAY_PORT, 0);
+        }
+    }
+
+    fn read(&self, port: u16) -> u8 {
+        // SAFETY: the ports were checked by the caller of `new`
+        unsafe { inb(port) }
+    }
+
+    fn write_masks(&mut self) {
+        let [primary, secondary] = self.masks.to_le_bytes();
+        self.write(self.primary_port + DATA, primary);
+        self.write(self.secondary_port + DATA, secondary);
+    }
+
+    /// The lines that are being handled, with the same layout as `masks`.
+    fn in_service(&mut self) -> u16 {
+        self.write(self.primary_port + COMMAND, OCW3_READ_ISR);
+        self.write(self.secondary_port + COMMAND, OCW3_READ_ISR);
+        let primary = self.read(self.primary_port + COMMAND);
+        let secondary = self.read(self.secondary_port + COMMAND);
+        u16::from_le_bytes([primary, secondary])
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Pic8259 {
+    /// # Safety
+    /// The user must verify that the ports are the base I/O ports of the primary and secondary
+    /// controllers.
+    pub const unsafe fn new(primary_port: u16, secondary_port: u16) -> Self {
+        Self {
+            primary_port,
+            secondary_port,
+            masks: u16::MAX,
+        }
+    }
+}
+
+impl InterruptController for Pic8259 {
+    fn enable(&mut self, irq: IrqNumber) -> Result<(), driver::Error> {
+        if irq >= 2 * LINE_COUNT || irq == CASCADE_LINE as IrqNumber {
+            return Err(driver::Error::BadConfiguration)
+        }
+
+        self.masks &= !(1 << irq);
+        if irq >= LINE_COUNT {
+            self.masks &= !(1 << CASCADE_LINE);
+        }
+        self.write_masks();
+        Ok(())
+    }
+
+    fn disable(&mut self, irq: IrqNumber) {
+        if irq < 2 * LINE_COUNT && irq != CASCADE_LINE as IrqNumber {
+            self.masks |= 1 << irq;
+            self.write_masks();
+        }
+    }
+
+    fn acknowledge(&mut self) -> Option<IrqNumber> {
+        // a line that's in service on the secondary also shows up as the cascade on the primary
+        let in_service = self.in_service() & !(1 << CASCADE_LINE);
+        match in_service.trailing_zeros() {
+            16 => None,
+            irq => Some(irq as IrqNumber),
+        }
+    }
+
+    fn end_of_interrupt(&mut self, irq: IrqNumber) {
+        if irq >= LINE_COUNT {
+            self.write(self.secondary_port + COMMAND, OCW2_EOI);
+        }
+        self.write(self.primary_port + COMMAND, OCW2_EOI);
+    }
+}
+
+impl Driver for Pic8259 {
+    const COMPATIBLE: &'static str = "intel,i8259";
+
+    fn probe(&mut self) -> Result<(), driver::Error> {
+        // the mask register reads back what was written to it, unlike a port nothing answers on
+        let port = self.primary_port + DATA;
+        let original = self.read(port);
+        self.write(port, PROBE_MASK);
+        let present = self.read(port) == PROBE_MASK;
+        self.write(port, original);
+
+        if !present {
+            return Err(driver::Error::NotPresent)
+        }
+        Ok(())
+    }
+
+    fn init(&mut self, _registry: &Registry) -> Result<(), driver::Error> {
+        let (primary, secondary) = (self.primary_port, self.secondary_port);
+        self.write(primary + COMMAND, ICW1_INIT);
+        self.write(secondary + COMMAND, ICW1_INIT);
+        // ICW2: where the lines are delivered
+        self.write(primary + DATA, IRQ_VECTOR_BASE);
+        self.write(secondary + DATA, IRQ_VECTOR_BASE + LINE_COUNT as u8);
+        // ICW3: which line the secondary is on, as a mask for the primary and a number for it
+        self.write(primary + DATA, 1 << CASCADE_LINE);
+        self.write(secondary + DATA, CASCADE_LINE);
+        self.write(primary + DATA, ICW4_8086);
+        self.write(secondary + DATA, ICW4_8086);
+
+        // lines are enabled as drivers register handlers for them
+        self.masks = u16::MAX;
+        self.write_masks();
+        Ok(())
+    }
+
+    fn shutdown(&mut self) {
+        self.masks = u16::MAX;
+        self.write_masks();
+    }
+}
+
+impl AsMut<dyn InterruptController> for Pic8259 {
+    fn as_mut(&mut self) -> &mut (dyn InterruptController + 'static) {
+        self
+    }
+}
@@ -0,0 +1,225 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Driver for the 8254 programmable interval timer that PCs have.
+//!
+//! The timer has three 16-bit channels that count down at a fixed 1.193182 MHz. Channel 0 raises
+//! interrupt line 0 when it runs out, which makes it a [ClockEvent], if a poor one: it's slow to
+//! program, and can't be set further ahead than about 55 ms. Channel 1 used to refresh DRAM and
+//! isn't wired to anything anymore.
+//!
+//! Channel 2 drives the speaker, but its output can also be read back without an interrupt, so
+//! it's what [Pit8254::spin_for] counts with. That's how the TSC is calibrated, since nothing else
+//! tells us how fast it runs.
+
+use crate::driver::{self, registry::Registry, traits::Driver};
+use crate::interrupt::{self, IrqNumber};
+use crate::sync::IrqSafeSpinMutex;
+use crate::time::{ClockEvent, EventHandler, NS_PER_SEC};
+use core::convert::TryFrom;
+use core::time::Duration;
+use x86::io::{inb, outb};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+pub struct Pit8254 {
+    base_port: u16,
+    /// The interrupt line of channel 0
+    irq: IrqNumber,
+    /// Whether our handler has been registered for `irq`
+    irq_hooked: bool,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// How fast the channels count down
+const FREQUENCY_HZ: u64 = 1_193_182;
+
+// Register offsets from the base I/O port
+const CHANNEL_0: u16 = 0;
+const CHANNEL_2: u16 = 2;
+const MODE_COMMAND: u16 = 3;
+
+/// Load the count low byte first, and count down in binary in mode 0, which raises the output once
+/// the count runs out and leaves it there. The channel goes in the top two bits.
+const MODE_ONE_SHOT: u8 = 0b0011_0000;
+
+/// System control port B, which gates channel 2 and reads back its output
+const SYSTEM_CONTROL_PORT: u16 = 0x61;
+const CHANNEL_2_GATE: u8 = 1 << 0;
+/// Connects channel 2 to the speaker, which is kept off
+const SPEAKER_ENABLE: u8 = 1 << 1;
+const CHANNEL_2_OUTPUT: u8 = 1 << 5;
+
+/// How many times channel 2's output is read before the timer is taken to be missing. The other
+/// timers can't be trusted yet, and even emulated port reads take long enough that this is well
+/// over the longest count.
+const POLL_LIMIT: usize = 10_000_000;
+
+/// How the timer compares to others as a clock event device. Anything else is better.
+const RATING: u32 = 110;
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+/// The handler of the timer that's set, kept apart from [Pit8254] so that the interrupt handler
+/// doesn't have to wait for whoever holds the driver.
+static HANDLER: IrqSafeSpinMutex<Option<EventHandler>> = IrqSafeSpinMutex::new_irq_safe(None);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// How many ticks make up `delay`, if that fits in a channel's count.
+fn ticks(delay: Duration) -> Option<u16> {
+    let ticks = delay.as_nanos() * u128::from(FREQUENCY_HZ) / u128::from(NS_PER_SEC);
+    // a count of 0 would be taken as 65536
+    u16::try_from(ticks.max(1)).ok()
+}
+
+/// The longest delay a channel can count down.
+fn max_delay() -> Duration {
+    Duration::from_nanos(u64::from(u16::MAX) * NS_PER_SEC / FREQUENCY_HZ)
+}
+
+impl Pit8254 {
+    fn write(&mut self, port: u16, value: u8) {
+        // SAFETY: the ports were checked by the caller of `new`
+        unsafe { outb(port, value) }
+    }
+
+    fn read(&self, port: u16) -> u8 {
+        // SAFETY: the ports were checked by the caller of `new`
+        unsafe { inb(port) }
+    }
+
+    /// Start `channel` counting down from `ticks` in mode 0. Channel 2 only counts while it's
+    /// gated on.
+    fn load(&mut self, channel: u16, ticks: u16) {
+        let [low, high] = ticks.to_le_bytes();
+        self.write(self.base_port + MODE_COMMAND, (channel as u8) << 6 | MODE_ONE_SHOT);
+        self.write(self.base_port + channel, low);
+        self.write(self.base_port + channel, high);
+    }
+
+    /// Stop channel 0. It stays stopped until it's loaded again.
+    fn stop(&mut self) {
+        self.write(self.base_port + MODE_COMMAND, (CHANNEL_0 as u8) << 6 | MODE_ONE_SHOT);
+    }
+
+    /// Count `ticks` down on channel 2 and wait for it to run out.
+    fn wait_ticks(&mut self, ticks: u16) -> Result<(), driver::Error> {
+        let control = self.read(SYSTEM_CONTROL_PORT) & !(CHANNEL_2_GATE | SPEAKER_ENABLE);
+        self.write(SYSTEM_CONTROL_PORT, control);
+        self.load(CHANNEL_2, ticks);
+        self.write(SYSTEM_CONTROL_PORT, control | CHANNEL_2_GATE);
+
+        let done = (0..POLL_LIMIT)
+            .any(|_| self.read(SYSTEM_CONTROL_PORT) & CHANNEL_2_OUTPUT != 0);
+        self.write(SYSTEM_CONTROL_PORT, control);
+        if !done {
+            return Err(driver::Error::Timeout)
+        }
+        Ok(())
+    }
+}
+
+fn handle_irq(_irq: IrqNumber) {
+    // the channel is done counting and has to be loaded again, so there's nothing to turn off
+    if let Some(handler) = HANDLER.with_lock(|handler| handler.take()) {
+        handler()
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Pit8254 {
+    /// `irq` is the interrupt line of channel 0.
+    ///
+    /// # Safety
+    /// The user must verify that the port is the base I/O port of the timer.
+    pub const unsafe fn new(base_port: u16, irq: IrqNumber) -> Self {
+        Self { base_port, irq, irq_hooked: false }
+    }
+
+    /// Busy-wait for `duration` on channel 2. This works without interrupts, and before any
+    /// other timer is known to be right.
+    ///
+    /// Fails with [driver::Error::Timeout] if the channel never runs out.
+    pub fn spin_for(&mut self, duration: Duration) -> Result<(), driver::Error> {
+        let mut remaining = duration;
+        while remaining > Duration::ZERO {
+            let chunk = remaining.min(max_delay());
+            let ticks = ticks(chunk).ok_or(driver::Error::BadConfiguration)?;
+            self.wait_ticks(ticks)?;
+            remaining -= chunk;
+        }
+        Ok(())
+    }
+}
+
+impl Driver for Pit8254 {
+    const COMPATIBLE: &'static str = "intel,i8254";
+
+    fn init(&mut self, _registry: &Registry) -> Result<(), driver::Error> {
+        // the firmware may have left channel 0 counting
+        self.stop();
+        crate::arch::time::calibrate(|duration| self.spin_for(duration))
+    }
+
+    fn shutdown(&mut self) {
+        self.cancel();
+        if self.irq_hooked {
+            interrupt::unregister(self.irq);
+            self.irq_hooked = false;
+        }
+    }
+}
+
+impl ClockEvent for Pit8254 {
+    fn name(&self) -> &'static str {
+        "8254 PIT"
+    }
+
+    fn rating(&self) -> u32 {
+        RATING
+    }
+
+    fn max_delay(&self) -> Duration {
+        max_delay()
+    }
+
+    fn program(&mut self, delay: Duration, handler: EventHandler) -> Result<(), driver::Error> {
+        if delay > self.max_delay() {
+            return Err(driver::Error::BadConfiguration)
+        }
+        let ticks = ticks(delay).ok_or(driver::Error::BadConfiguration)?;
+        if !self.irq_hooked {
+            interrupt::register(self.irq, handle_irq)?;
+            self.irq_hooked = true;
+        }
+
+        HANDLER.with_lock(|current| *current = Some(handler));
+        self.load(CHANNEL_0, ticks);
+        interrupt::enable(self.irq)
+    }
+
+    fn cancel(&mut self) {
+        self.stop();
+        HANDLER.with_lock(|current| *current = None);
+    }
+}
+
+impl AsMut<dyn ClockEvent> for Pit8254 {
+    fn as_mut(&mut self) -> &mut (dyn ClockEvent + 'static) {
+        self
+    }
+}
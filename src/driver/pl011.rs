@@ -0,0 +1,459 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Driver for the ARM PL011 UART.
+
+use crate::arch;
+use crate::driver::{self, registry::Registry, traits::Driver, WriteError};
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+use crate::driver::gpio::{Alt0, Gpio, Pin, Pull};
+use crate::driver::input::{CharInput, Utf8Decoder};
+use crate::driver::uart::{baud_within_tolerance, Parity, StopBits, Uart, UartConfig, WordLength};
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+use crate::sync::SpinMutex;
+use tock_registers::interfaces::{Readable, Writeable};
+use tock_registers::registers::{ReadOnly, ReadWrite, WriteOnly};
+use tock_registers::{register_bitfields, register_structs};
+
+// PL011 UART registers.
+//
+// Descriptions taken from
+// https://github.com/raspberrypi/documentation/files/1888662/BCM2837-ARM-Peripherals.-.Revised.-.V2-1.pdf
+register_bitfields! {
+    u32,
+
+    /// Flag Register
+    FR [
+        /// Transmit FIFO empty. The meaning of this bit depends on the state of the FEN bit in the
+        /// Line Control Register, UARTLCR_ LCRH.
+        ///
+        /// If the FIFO is disabled, this bit is set when the transmit holding register is empty. If
+        /// the FIFO is enabled, the TXFE bit is set when the transmit FIFO is empty. This bit does
+        /// not indicate if there is data in the transmit shift register.
+        TXFE OFFSET(7) NUMBITS(1) [],
+
+        /// Transmit FIFO full. The meaning of this bit depends on the state of the FEN bit in the
+        /// UARTLCR_ LCRH Register.
+        ///
+        /// If the FIFO is disabled, this bit is set when the transmit holding register is full. If
+        /// the FIFO is enabled, the TXFF bit is set when the transmit FIFO is full.
+        TXFF OFFSET(5) NUMBITS(1) [],
+
+        /// Receive FIFO empty. The meaning of this bit depends on the state of the FEN bit in the
+        /// UARTLCR_H Register.
+        ///
+        /// If the FIFO is disabled, this bit is set when the receive holding register is empty. If
+        /// the FIFO is enabled, the RXFE bit is set when the receive FIFO is empty.
+        RXFE OFFSET(4) NUMBITS(1) [],
+
+        /// UART busy. If this bit is set to 1, the UART is busy transmitting data. This bit remains
+        /// set until the complete byte, including all the stop bits, has been sent from the shift
+        /// register.
+        BUSY OFFSET(3) NUMBITS(1) []
+    ],
+
+    /// Integer Baud rate divisor
+    IBRD [
+        /// Integer Baud rate divisor
+        BAUD_DIVINT OFFSET(0) NUMBITS(16) []
+    ],
+
+    /// Fractional Baud rate divisor
+    FBRD [
+        /// Fractional Baud rate divisor
+        BAUD_DIVFRAC OFFSET(0) NUMBITS(6) []
+    ],
+
+    /// Line Control register
+    LCRH [
+        /// Word length. These bits indicate the number of data bits transmitted or received in a
+        /// frame.
+        WLEN OFFSET(5) NUMBITS(2) [
+            FiveBit = 0b00,
+            SixBit = 0b01,
+            SevenBit = 0b10,
+            EightBit = 0b11
+        ],
+
+        /// Enable FIFOs:
+        ///
+        /// 0 = FIFOs are disabled (character mode) that is, the FIFOs become 1-byte-deep holding
+        /// registers
+        ///
+        /// 1 = transmit and receive FIFO buffers are enabled (FIFO mode).
+        FEN  OFFSET(4) NUMBITS(1) [
+            FifosDisabled = 0,
+            FifosEnabled = 1
+        ],
+
+        /// Two stop bits select. If this bit is set to 1, two stop bits are transmitted at the end
+        /// of the frame. The receive logic does not check for two stop bits being received.
+        STP2 OFFSET(3) NUMBITS(1) [],
+
+        /// Even parity select. Controls the type of parity the UART uses during transmission and
+        /// reception. This bit has no effect when the PEN bit disables parity.
+        EPS OFFSET(2) NUMBITS(1) [
+            Odd = 0,
+            Even = 1
+        ],
+
+        /// Parity enable. If this bit is set to 1, parity checking and generation is enabled.
+        PEN OFFSET(1) NUMBITS(1) []
+    ],
+
+    /// Control Register
+    CR [
+        /// Receive enable. If this bit is set to 1, the receive section of the UART is enabled.
+        /// Data reception occurs for UART signals. When the UART is disabled in the middle of
+        /// reception, it completes the current character before stopping.
+        RXE    OFFSET(9) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ],
+
+        /// Transmit enable. If this bit is set to 1, the transmit section of the UART is enabled.
+        /// Data transmission occurs for UART signals. When the UART is disabled in the middle of
+        /// transmission, it completes the current character before stopping.
+        TXE    OFFSET(8) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ],
+
+        /// UART enable
+        UARTEN OFFSET(0) NUMBITS(1) [
+            /// If the UART is disabled in the middle of transmission or reception, it completes the
+            /// current character before stopping.
+            Disabled = 0,
+            Enabled = 1
+        ]
+    ],
+
+    /// Interrupt Clear Register
+    ICR [
+        /// Meta field for all pending interrupts
+        ALL OFFSET(0) NUMBITS(11) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => DR: ReadWrite<u32>),
+        (0x04 => _reserved1),
+        (0x18 => FR: ReadOnly<u32, FR::Register>),
+        (0x1c => _reserved2),
+        (0x24 => IBRD: WriteOnly<u32, IBRD::Register>),
+        (0x28 => FBRD: WriteOnly<u32, FBRD::Register>),
+        (0x2c => LCRH: WriteOnly<u32, LCRH::Register>),
+        (0x30 => CR: ReadWrite<u32, CR::Register>),
+        (0x34 => _reserved3),
+        (0x44 => ICR: WriteOnly<u32, ICR::Register>),
+        (0x48 => @END),
+    }
+}
+
+// FIXME:
+// Currently this is unsafe since it gives shared mutability over the register block. Really this
+// should contain some sort of Mutex to guard interior mutability.
+pub struct PL011Uart {
+    regs: &'static mut RegisterBlock,
+    /// Frequency of the UART reference clock, in Hz
+    clock_hz: u32,
+    config: UartConfig,
+    /// What's been received of a character that's split across reads
+    decoder: Utf8Decoder,
+    /// Whether `init` should route the UART to GPIO 14 and 15
+    #[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+    route_pins: bool,
+    /// TX and RX, once they've been claimed by `claim_pins`
+    #[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+    pins: Option<(Pin<Alt0, 14>, Pin<Alt0, 15>)>,
+}
+
+impl PL011Uart {
+    /// # Safety
+    /// The user must verify that the address for the register block is correct, and that
+    /// `clock_hz` is the frequency of the clock feeding the UART.
+    ///
+    /// The UART is set up with `config` once the driver is initialized.
+    pub unsafe fn new(base_address: usize, clock_hz: u32, config: UartConfig) -> Self {
+        Self {
+            regs: &mut *(base_address as *mut _),
+            clock_hz,
+            config,
+            decoder: Utf8Decoder::new(),
+            #[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+            route_pins: false,
+            #[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+            pins: None,
+        }
+    }
+
+    /// Route the UART to GPIO pins 14 (TX) and 15 (RX) when it's initialized. This makes the driver
+    /// depend on the GPIO driver.
+    #[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+    pub fn route_to_pins(&mut self) {
+        self.route_pins = true;
+    }
+
+    /// Check whether a reference clock of `clock_hz` can generate `baud` closely enough.
+    pub fn supports_baud(clock_hz: u32, baud: u32) -> bool {
+        divisors(clock_hz, baud).is_ok()
+    }
+}
+
+impl PL011Uart {
+    #[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+    fn claim_pins(&mut self, gpio: &mut Gpio) -> Result<(), driver::Error> {
+        let tx = gpio.claim::<14>()?;
+        let rx = match gpio.claim::<15>() {
+            Ok(rx) => rx,
+            Err(e) => {
+                gpio.release(tx);
+                return Err(e)
+            }
+        };
+        let mut tx = tx.into_alt::<Alt0>();
+        let mut rx = rx.into_alt::<Alt0>();
+        tx.set_pull(Pull::None);
+        rx.set_pull(Pull::None);
+        self.pins = Some((tx, rx));
+        Ok(())
+    }
+
+    /// Wait until everything that was written has left the transmit shift register.
+    fn flush(&self) {
+        while !self.regs.FR.is_set(FR::TXFE) || self.regs.FR.is_set(FR::BUSY) {
+            arch::asm::nop()
+        }
+    }
+}
+
+/// Compute the integer and fractional baud rate divisors.
+///
+/// The PL011 divides its reference clock by 16 times `IBRD + FBRD / 64`, so the divisor is
+/// calculated in 64ths and rounded to the nearest one.
+fn divisors(clock_hz: u32, baud: u32) -> Result<(u32, u32), driver::Error> {
+    if baud == 0 {
+        return Err(driver::Error::UnsupportedBaudRate)
+    }
+
+    // clock / (16 * baud) * 64
+    let div64 = (4 * u64::from(clock_hz) + u64::from(baud) / 2) / u64::from(baud);
+    let ibrd = div64 >> 6;
+    let fbrd = div64 & 0x3f;
+    if ibrd == 0 || ibrd > 0xffff {
+        return Err(driver::Error::UnsupportedBaudRate)
+    }
+
+    let actual = 4 * u64::from(clock_hz) / div64;
+    if !baud_within_tolerance(baud, actual) {
+        return Err(driver::Error::UnsupportedBaudRate)
+    }
+
+    Ok((ibrd as u32, fbrd as u32))
+}
+
+impl Uart for PL011Uart {
+    fn send_ready(&self) -> bool {
+        !self.regs.FR.is_set(FR::TXFF)
+    }
+
+    fn send(&mut self, byte: u8) {
+        while !self.send_ready() {
+            arch::asm::nop()
+        }
+
+        // write the character to the buffer
+        // Since we have the FIFO enabled, writing to this buffer may not immediately result in a
+        // full buffer. `self.regs.FR` could still indicate that we're ready to send.
+        self.regs.DR.set(byte as u32)
+    }
+
+    fn receive_ready(&self) -> bool {
+        !self.regs.FR.is_set(FR::RXFE)
+    }
+
+    fn receive(&mut self) -> u8 {
+        while !self.receive_ready() {
+            arch::asm::nop()
+        }
+
+        // read the character from the buffer
+        self.regs.DR.get() as u8
+    }
+
+    fn config(&self) -> UartConfig {
+        self.config
+    }
+
+    fn set_config(&mut self, config: UartConfig) -> Result<(), driver::Error> {
+        let (ibrd, fbrd) = divisors(self.clock_hz, config.baud)?;
+
+        // The line settings may only be changed while the UART is disabled. Let the transmit FIFO
+        // drain first so that nothing is sent with the wrong settings.
+        let control = self.regs.CR.get();
+        let was_enabled = self.regs.CR.is_set(CR::UARTEN);
+        if was_enabled {
+            self.flush();
+        }
+        self.regs.CR.set(0);
+
+        let word_length = match config.word_length {
+            WordLength::Five => LCRH::WLEN::FiveBit,
+            WordLength::Six => LCRH::WLEN::SixBit,
+            WordLength::Seven => LCRH::WLEN::SevenBit,
+            WordLength::Eight => LCRH::WLEN::EightBit,
+        };
+        let parity = match config.parity {
+            Parity::None => LCRH::PEN::CLEAR,
+            Parity::Even => LCRH::PEN::SET + LCRH::EPS::Even,
+            Parity::Odd => LCRH::PEN::SET + LCRH::EPS::Odd,
+        };
+        let stop_bits = match config.stop_bits {
+            StopBits::One => LCRH::STP2::CLEAR,
+            StopBits::Two => LCRH::STP2::SET,
+        };
+
+        // the divisors only take effect once LCRH is written, so it has to come last
+        self.regs.IBRD.write(IBRD::BAUD_DIVINT.val(ibrd));
+        self.regs.FBRD.write(FBRD::BAUD_DIVFRAC.val(fbrd));
+        self.regs.LCRH.write(word_length + parity + stop_bits + LCRH::FEN::FifosEnabled);
+        self.config = config;
+
+        if was_enabled {
+            self.regs.CR.set(control);
+        } else {
+            // enable UART + enable transmit + enable receive
+            self.regs.CR.write(CR::UARTEN::Enabled + CR::TXE::Enabled + CR::RXE::Enabled);
+        }
+        Ok(())
+    }
+}
+
+impl ufmt::uWrite for PL011Uart {
+    type Error = WriteError;
+
+    fn write_str(&mut self, msg: &str) -> Result<(), Self::Error> {
+        for byte in msg.bytes() {
+            if byte == b'\n' {
+                self.send(b'\r')
+            }
+            self.send(byte)
+        }
+        Ok(())
+    }
+}
+
+impl Driver for PL011Uart {
+    const COMPATIBLE: &'static str = "arm,pl011";
+
+    #[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+    fn dependencies(&self) -> &'static [&'static str] {
+        if self.route_pins {
+            &[Gpio::COMPATIBLE]
+        } else {
+            &[]
+        }
+    }
+
+    #[cfg_attr(not(any(feature = "bsp_rpi3", feature = "bsp_rpi4")), allow(unused_variables))]
+    fn init(&mut self, registry: &Registry) -> Result<(), driver::Error> {
+        // make sure the baud rate works before touching the hardware
+        let config = self.config;
+        divisors(self.clock_hz, config.baud)?;
+
+        #[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+        if self.route_pins && self.pins.is_none() {
+            let gpio = registry.get::<SpinMutex<Gpio>>().ok_or(driver::Error::Unavailable)?;
+            gpio.with_lock(|gpio| self.claim_pins(gpio))?;
+        }
+
+        // set control register to 0 to turn off during initialization phase
+        self.regs.CR.set(0);
+
+        // initialize UART
+        self.regs.ICR.write(ICR::ALL::CLEAR); // clear pending interrupts
+        self.set_config(config)
+    }
+
+    fn shutdown(&mut self) {
+        if self.regs.CR.is_set(CR::UARTEN) {
+            self.flush();
+        }
+        self.regs.CR.set(0);
+    }
+}
+
+impl CharInput for PL011Uart {
+    fn read_char(&mut self) -> Option<char> {
+        while self.receive_ready() {
+            let byte = self.receive_native();
+            if let Some(c) = self.decoder.push(byte) {
+                return Some(c)
+            }
+        }
+        None
+    }
+}
+
+impl AsMut<dyn ufmt::uWrite<Error=WriteError>> for PL011Uart {
+    fn as_mut(&mut self) -> &mut (dyn ufmt::uWrite<Error=WriteError> + 'static) {
+        self
+    }
+}
+
+impl AsMut<dyn CharInput> for PL011Uart {
+    fn as_mut(&mut self) -> &mut (dyn CharInput + 'static) {
+        self
+    }
+}
+
+/// Minimal, polled writer for a PL011 that was already set up, e.g. by the firmware.
+///
+/// This holds no state and takes no locks, so it can be used before the bss section is zeroed and
+/// from inside of driver initialization. It's meant for early boot output and panics only.
+pub struct PL011Polled {
+    base_address: usize,
+}
+
+impl PL011Polled {
+    /// # Safety
+    /// The user must verify that the address for the register block is correct.
+    pub const unsafe fn new(base_address: usize) -> Self {
+        Self { base_address }
+    }
+
+    fn regs(&self) -> &RegisterBlock {
+        // SAFETY: the address was checked by the caller of `new`
+        unsafe { &*(self.base_address as *const RegisterBlock) }
+    }
+
+    fn send(&self, byte: u8) {
+        let regs = self.regs();
+        while regs.FR.is_set(FR::TXFF) {
+            arch::asm::nop()
+        }
+        regs.DR.set(byte as u32)
+    }
+}
+
+impl ufmt::uWrite for PL011Polled {
+    type Error = WriteError;
+
+    fn write_str(&mut self, msg: &str) -> Result<(), Self::Error> {
+        // a disabled UART never drains its FIFO, so we'd spin forever once it filled up
+        if !self.regs().CR.is_set(CR::UARTEN) {
+            return Err(WriteError::NotReady)
+        }
+
+        for byte in msg.bytes() {
+            if byte == b'\n' {
+                self.send(b'\r')
+            }
+            self.send(byte)
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,473 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Driver for a PS/2 keyboard on the i8042 controller, with a US layout.
+//!
+//! The keyboard raises IRQ 1 for every byte it sends. The interrupt handler decodes the scancodes,
+//! keeps track of the modifier keys and turns each key press into characters that are queued up
+//! for [CharInput::read_char]. Either scancode set 1, which the controller translates to by
+//! default, or set 2, which is what keyboards actually send, can be decoded. Set 2 codes are
+//! converted to their set 1 equivalents first, so there's only one keymap.
+//!
+//! Before a key press is queued, it's passed to the hook set by [Ps2Keyboard::set_hook], which can
+//! handle it instead, e.g. to scroll the console.
+
+use crate::driver::i8042::I8042;
+use crate::driver::input::{CharInput, Key, KeyEvent, Modifiers};
+use crate::driver::pic8259::Pic8259;
+use crate::driver::{self, registry::Registry, traits::Driver};
+use crate::interrupt::{self, IrqNumber};
+use crate::sync::IrqSafeSpinMutex;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The encodings a PS/2 keyboard can send key presses in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ScancodeSet {
+    /// The original PC/XT set, which the controller translates set 2 into
+    Set1,
+    /// The PC/AT set, which the keyboard sends unless it's told otherwise
+    Set2,
+}
+
+/// Function that gets to see every key press before it's queued up.
+///
+/// It runs in the interrupt handler, and returns `true` if it handled the key, so it shouldn't be
+/// queued.
+pub type KeyHook = fn(KeyEvent) -> bool;
+
+pub struct Ps2Keyboard {
+    controller: I8042,
+    data_port: u16,
+    command_port: u16,
+    irq: IrqNumber,
+    scancode_set: ScancodeSet,
+    hook: Option<KeyHook>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Keyboard commands
+const KEYBOARD_RESET: u8 = 0xff;
+const KEYBOARD_SET_SCANCODE_SET: u8 = 0xf0;
+const KEYBOARD_ENABLE_SCANNING: u8 = 0xf4;
+
+/// What the keyboard sends once it has reset
+const KEYBOARD_SELF_TEST_PASSED: u8 = 0xaa;
+
+// Prefixes in both scancode sets
+const PREFIX_EXTENDED: u8 = 0xe0;
+const PREFIX_PAUSE: u8 = 0xe1;
+/// Prefix for key releases in set 2. Set 1 sets the top bit of the code instead.
+const PREFIX_RELEASE: u8 = 0xf0;
+const SET1_RELEASE: u8 = 0x80;
+
+/// Pause sends `E1` followed by this many bytes, with no release
+const PAUSE_LEN_SET1: u8 = 5;
+const PAUSE_LEN_SET2: u8 = 7;
+
+// Set 1 codes of the keys that don't type anything
+const CODE_LEFT_CONTROL: u8 = 0x1d;
+const CODE_LEFT_SHIFT: u8 = 0x2a;
+const CODE_RIGHT_SHIFT: u8 = 0x36;
+const CODE_LEFT_ALT: u8 = 0x38;
+const CODE_CAPS_LOCK: u8 = 0x3a;
+const CODE_F1: u8 = 0x3b;
+const CODE_F10: u8 = 0x44;
+const CODE_NUM_LOCK: u8 = 0x45;
+const CODE_KEYPAD_FIRST: u8 = 0x47;
+const CODE_KEYPAD_LAST: u8 = 0x53;
+const CODE_F11: u8 = 0x57;
+const CODE_F12: u8 = 0x58;
+
+/// The set 1 code for each set 2 code, or 0 for codes that aren't keys.
+#[rustfmt::skip]
+const SET2_TO_SET1: [u8; 0x84] = [
+    0x00, 0x43, 0x00, 0x3f, 0x3d, 0x3b, 0x3c, 0x58, 0x00, 0x44, 0x42, 0x40, 0x3e, 0x0f, 0x29, 0x00,
+    0x00, 0x38, 0x2a, 0x00, 0x1d, 0x10, 0x02, 0x00, 0x00, 0x00, 0x2c, 0x1f, 0x1e, 0x11, 0x03, 0x5b,
+    0x00, 0x2e, 0x2d, 0x20, 0x12, 0x05, 0x04, 0x5c, 0x00, 0x39, 0x2f, 0x21, 0x14, 0x13, 0x06, 0x5d,
+    0x00, 0x31, 0x30, 0x23, 0x22, 0x15, 0x07, 0x00, 0x00, 0x00, 0x32, 0x24, 0x16, 0x08, 0x09, 0x00,
+    0x00, 0x33, 0x25, 0x17, 0x18, 0x0b, 0x0a, 0x00, 0x00, 0x34, 0x35, 0x26, 0x27, 0x19, 0x0c, 0x00,
+    0x00, 0x00, 0x28, 0x00, 0x1a, 0x0d, 0x00, 0x00, 0x3a, 0x36, 0x1c, 0x1b, 0x00, 0x2b, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0e, 0x00, 0x00, 0x4f, 0x00, 0x4b, 0x47, 0x00, 0x00, 0x00,
+    0x52, 0x53, 0x50, 0x4c, 0x4d, 0x48, 0x01, 0x45, 0x57, 0x4e, 0x51, 0x4a, 0x37, 0x49, 0x46, 0x00,
+    0x00, 0x00, 0x00, 0x41,
+];
+
+/// What the keys up to the space bar type on a US keyboard, indexed by set 1 code. 0 means the key
+/// doesn't type anything.
+const US_LAYOUT: &[u8; 0x3a] =
+    b"\0\x1b1234567890-=\x08\tqwertyuiop[]\n\0asdfghjkl;'`\0\\zxcvbnm,./\0*\0 ";
+
+/// The same, with shift held down.
+const US_LAYOUT_SHIFTED: &[u8; 0x3a] =
+    b"\0\x1b!@#$%^&*()_+\x08\tQWERTYUIOP{}\n\0ASDFGHJKL:\"~\0|ZXCVBNM<>?\0*\0 ";
+
+/// What the keypad types with num lock on, from 7 in the top left to the decimal point.
+const KEYPAD_DIGITS: &[u8; 13] = b"789-456+1230.";
+
+/// What the keypad does with num lock off. The 5 in the middle doesn't do anything.
+const KEYPAD_NAVIGATION: [Option<Key>; 13] = [
+    Some(Key::Home),
+    Some(Key::Up),
+    Some(Key::PageUp),
+    Some(Key::Char('-')),
+    Some(Key::Left),
+    None,
+    Some(Key::Right),
+    Some(Key::Char('+')),
+    Some(Key::End),
+    Some(Key::Down),
+    Some(Key::PageDown),
+    Some(Key::Insert),
+    Some(Key::Delete),
+];
+
+/// How many characters can be waiting to be read. More are dropped.
+const QUEUE_LEN: usize = 64;
+
+/// Turns the bytes from the keyboard into key presses.
+struct Decoder {
+    scancode_set: ScancodeSet,
+    /// The last byte was [PREFIX_EXTENDED]
+    extended: bool,
+    /// The last byte was [PREFIX_RELEASE]
+    released: bool,
+    /// How many more bytes of a Pause sequence to ignore
+    skip: u8,
+    left_shift: bool,
+    right_shift: bool,
+    left_control: bool,
+    right_control: bool,
+    left_alt: bool,
+    right_alt: bool,
+    caps_lock: bool,
+    num_lock: bool,
+}
+
+/// Characters waiting to be read.
+struct Queue {
+    buffer: [char; QUEUE_LEN],
+    start: usize,
+    len: usize,
+}
+
+/// Everything the interrupt handler needs.
+struct IrqState {
+    controller: Option<I8042>,
+    decoder: Decoder,
+    hook: Option<KeyHook>,
+    queue: Queue,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static IRQ_STATE: IrqSafeSpinMutex<IrqState> = IrqSafeSpinMutex::new_irq_safe(IrqState {
+    controller: None,
+    decoder: Decoder::new(ScancodeSet::Set1),
+    hook: None,
+    queue: Queue::new(),
+});
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Decoder {
+    const fn new(scancode_set: ScancodeSet) -> Self {
+        Self {
+            scancode_set,
+            extended: false,
+            released: false,
+            skip: 0,
+            left_shift: false,
+            right_shift: false,
+            left_control: false,
+            right_control: false,
+            left_alt: false,
+            right_alt: false,
+            caps_lock: false,
+            // most firmware turns num lock on at boot
+            num_lock: true,
+        }
+    }
+
+    fn modifiers(&self) -> Modifiers {
+        Modifiers {
+            shift: self.left_shift || self.right_shift,
+            control: self.left_control || self.right_control,
+            alt: self.left_alt || self.right_alt,
+            caps_lock: self.caps_lock,
+            num_lock: self.num_lock,
+        }
+    }
+
+    /// Feed the next byte from the keyboard to the decoder, which returns a key press once it has
+    /// seen all of one.
+    fn feed(&mut self, byte: u8) -> Option<KeyEvent> {
+        if self.skip > 0 {
+            self.skip -= 1;
+            return None
+        }
+
+        let (code, released) = match (self.scancode_set, byte) {
+            (_, PREFIX_EXTENDED) => {
+                self.extended = true;
+                return None
+            }
+            (ScancodeSet::Set1, PREFIX_PAUSE) => {
+                self.skip = PAUSE_LEN_SET1;
+                return None
+            }
+            (ScancodeSet::Set2, PREFIX_PAUSE) => {
+                self.skip = PAUSE_LEN_SET2;
+                return None
+            }
+            (ScancodeSet::Set2, PREFIX_RELEASE) => {
+                self.released = true;
+                return None
+            }
+            (ScancodeSet::Set1, byte) => (byte & !SET1_RELEASE, byte & SET1_RELEASE != 0),
+            (ScancodeSet::Set2, byte) => {
+                let code = SET2_TO_SET1.get(byte as usize).copied().unwrap_or(0);
+                (code, self.released)
+            }
+        };
+        let extended = self.extended;
+        self.extended = false;
+        self.released = false;
+
+        // a code of 0 is either an error from the keyboard or a byte that isn't a key
+        if code == 0 {
+            return None
+        }
+        self.key(code, extended, released).map(|key| KeyEvent { key, modifiers: self.modifiers() })
+    }
+
+    /// Update the modifiers for a key press or release, and return the key that was pressed, if
+    /// it wasn't a modifier.
+    fn key(&mut self, code: u8, extended: bool, released: bool) -> Option<Key> {
+        let pressed = !released;
+        match (code, extended) {
+            (CODE_LEFT_SHIFT, false) => self.left_shift = pressed,
+            (CODE_RIGHT_SHIFT, false) => self.right_shift = pressed,
+            // the extended shifts are sent around some keys when num lock is on, and mean nothing
+            (CODE_LEFT_SHIFT, true) | (CODE_RIGHT_SHIFT, true) => {}
+            (CODE_LEFT_CONTROL, false) => self.left_control = pressed,
+            (CODE_LEFT_CONTROL, true) => self.right_control = pressed,
+            (CODE_LEFT_ALT, false) => self.left_alt = pressed,
+            (CODE_LEFT_ALT, true) => self.right_alt = pressed,
+            (CODE_CAPS_LOCK, false) if pressed => self.caps_lock = !self.caps_lock,
+            (CODE_NUM_LOCK, false) if pressed => self.num_lock = !self.num_lock,
+            _ if released => {}
+            (code, true) => return extended_key(code),
+            (code, false) => return self.plain_key(code),
+        }
+        None
+    }
+
+    fn plain_key(&self, code: u8) -> Option<Key> {
+        let modifiers = self.modifiers();
+        match code {
+            CODE_F1..=CODE_F10 => Some(Key::Function(code - CODE_F1 + 1)),
+            CODE_F11 => Some(Key::Function(11)),
+            CODE_F12 => Some(Key::Function(12)),
+            CODE_KEYPAD_FIRST..=CODE_KEYPAD_LAST => {
+                let index = (code - CODE_KEYPAD_FIRST) as usize;
+                if modifiers.num_lock {
+                    Some(Key::Char(KEYPAD_DIGITS[index] as char))
+                } else {
+                    KEYPAD_NAVIGATION[index]
+                }
+            }
+            _ => {
+                let byte = *US_LAYOUT.get(code as usize)?;
+                if byte == 0 {
+                    return None
+                }
+
+                // caps lock only applies to letters, and shift undoes it
+                let shifted = modifiers.shift ^ (modifiers.caps_lock && byte.is_ascii_alphabetic());
+                let byte = if shifted { US_LAYOUT_SHIFTED[code as usize] } else { byte };
+                if modifiers.control {
+                    // control turns @, A-Z and [\]^_ into the control characters 0 to 0x1f
+                    let upper = byte.to_ascii_uppercase();
+                    if let b'@'..=b'_' = upper {
+                        return Some(Key::Char((upper & 0x1f) as char))
+                    }
+                }
+                Some(Key::Char(byte as char))
+            }
+        }
+    }
+}
+
+/// The key for a set 1 code that came after [PREFIX_EXTENDED].
+fn extended_key(code: u8) -> Option<Key> {
+    let key = match code {
+        // keypad enter and slash
+        0x1c => Key::Char('\n'),
+        0x35 => Key::Char('/'),
+        0x47 => Key::Home,
+        0x48 => Key::Up,
+        0x49 => Key::PageUp,
+        0x4b => Key::Left,
+        0x4d => Key::Right,
+        0x4f => Key::End,
+        0x50 => Key::Down,
+        0x51 => Key::PageDown,
+        0x52 => Key::Insert,
+        0x53 => Key::Delete,
+        _ => return None,
+    };
+    Some(key)
+}
+
+impl Queue {
+    const fn new() -> Self {
+        Self {
+            buffer: ['\0'; QUEUE_LEN],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, c: char) {
+        if self.len < QUEUE_LEN {
+            self.buffer[(self.start + self.len) % QUEUE_LEN] = c;
+            self.len += 1;
+        }
+    }
+
+    fn push_key(&mut self, key: Key) {
+        match (key, key.escape_sequence()) {
+            (Key::Char(c), _) => self.push(c),
+            (_, Some(sequence)) => sequence.chars().for_each(|c| self.push(c)),
+            (_, None) => {}
+        }
+    }
+
+    fn pop(&mut self) -> Option<char> {
+        if self.len == 0 {
+            return None
+        }
+        let c = self.buffer[self.start];
+        self.start = (self.start + 1) % QUEUE_LEN;
+        self.len -= 1;
+        Some(c)
+    }
+}
+
+fn handle_irq(_irq: IrqNumber) {
+    let event = IRQ_STATE.with_lock(|state| {
+        let byte = state.controller.as_mut()?.try_receive()?;
+        Some((state.decoder.feed(byte)?, state.hook))
+    });
+
+    if let Some((event, hook)) = event {
+        // the hook runs without the lock held, so it's free to take its time
+        if !hook.map_or(false, |hook| hook(event)) {
+            IRQ_STATE.with_lock(|state| state.queue.push_key(event.key));
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Ps2Keyboard {
+    /// `irq` is the interrupt line of the controller's first port, which is 1 on PCs.
+    ///
+    /// # Safety
+    /// The user must verify that the ports are the data and command ports of an i8042.
+    pub const unsafe fn new(
+        data_port: u16,
+        command_port: u16,
+        irq: IrqNumber,
+        scancode_set: ScancodeSet,
+    ) -> Self {
+        Self {
+            controller: I8042::new(data_port, command_port),
+            data_port,
+            command_port,
+            irq,
+            scancode_set,
+            hook: None,
+        }
+    }
+
+    /// Let `hook` see every key press before it's queued up, and take the ones it handles.
+    pub fn set_hook(&mut self, hook: KeyHook) {
+        self.hook = Some(hook);
+        IRQ_STATE.with_lock(|state| state.hook = Some(hook));
+    }
+}
+
+impl CharInput for Ps2Keyboard {
+    fn read_char(&mut self) -> Option<char> {
+        IRQ_STATE.with_lock(|state| state.queue.pop())
+    }
+}
+
+impl Driver for Ps2Keyboard {
+    const COMPATIBLE: &'static str = "PS/2 keyboard";
+
+    fn dependencies(&self) -> &'static [&'static str] {
+        &[Pic8259::COMPATIBLE]
+    }
+
+    fn probe(&mut self) -> Result<(), driver::Error> {
+        if !self.controller.is_present() {
+            return Err(driver::Error::NotPresent)
+        }
+        Ok(())
+    }
+
+    fn init(&mut self, _registry: &Registry) -> Result<(), driver::Error> {
+        self.controller.init(self.scancode_set == ScancodeSet::Set1)?;
+
+        // there's no keyboard if nothing answers the reset
+        self.controller.send(KEYBOARD_RESET).map_err(|e| match e {
+            driver::Error::Timeout => driver::Error::NotPresent,
+            e => e,
+        })?;
+        if self.controller.receive()? != KEYBOARD_SELF_TEST_PASSED {
+            return Err(driver::Error::io::<Self>("the keyboard failed its self test"))
+        }
+        if self.scancode_set == ScancodeSet::Set2 {
+            self.controller.send(KEYBOARD_SET_SCANCODE_SET)?;
+            self.controller.send(2)?;
+        }
+        self.controller.send(KEYBOARD_ENABLE_SCANNING)?;
+
+        IRQ_STATE.with_lock(|state| {
+            // SAFETY: the interrupt handler only reads what the keyboard sends, which nothing else
+            // does once the interrupt is enabled
+            state.controller = Some(unsafe { I8042::new(self.data_port, self.command_port) });
+            state.decoder = Decoder::new(self.scancode_set);
+            state.hook = self.hook;
+        });
+        interrupt::register(self.irq, handle_irq)?;
+        interrupt::enable(self.irq)?;
+        self.controller.set_irq_enabled(true)
+    }
+
+    fn shutdown(&mut self) {
+        self.controller.disable();
+        interrupt::unregister(self.irq);
+    }
+}
+
+impl AsMut<dyn CharInput> for Ps2Keyboard {
+    fn as_mut(&mut self) -> &mut (dyn CharInput + 'static) {
+        self
+    }
+}
@@ -0,0 +1,161 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Interface to the ARM Power State Coordination Interface.
+//!
+//! PSCI is implemented by whatever runs at a higher exception level than the kernel, e.g. the
+//! hypervisor or the secure firmware, or by QEMU itself. It's used for starting the other cores and
+//! for turning the machine off. Calls are made with either `hvc` or `smc`, depending on who
+//! provides it.
+
+use crate::driver::{self, traits::Driver};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The instruction used to call into the PSCI implementation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Conduit {
+    /// Provided by the hypervisor at EL2.
+    Hvc,
+    /// Provided by the secure firmware at EL3.
+    Smc,
+}
+
+/// PSCI calls are stateless, so this can be freely copied and used without a lock.
+#[derive(Clone, Copy)]
+pub struct Psci {
+    conduit: Conduit,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Function IDs, using the 64-bit calling convention where there is one.
+mod function {
+    pub const PSCI_VERSION: u32 = 0x8400_0000;
+    pub const CPU_ON: u32 = 0xc400_0003;
+    pub const SYSTEM_OFF: u32 = 0x8400_0008;
+    pub const SYSTEM_RESET: u32 = 0x8400_0009;
+}
+
+/// Return codes.
+mod status {
+    pub const SUCCESS: i32 = 0;
+    pub const NOT_SUPPORTED: i32 = -1;
+    pub const INVALID_PARAMETERS: i32 = -2;
+    pub const ALREADY_ON: i32 = -4;
+    pub const ON_PENDING: i32 = -5;
+    pub const NOT_PRESENT: i32 = -7;
+    pub const INVALID_ADDRESS: i32 = -9;
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn status_to_result(status: i32) -> Result<(), driver::Error> {
+    match status {
+        status::SUCCESS => Ok(()),
+        status::NOT_SUPPORTED
+        | status::INVALID_PARAMETERS
+        | status::NOT_PRESENT
+        | status::INVALID_ADDRESS => Err(driver::Error::BadConfiguration),
+        status::ALREADY_ON | status::ON_PENDING => Err(driver::Error::Busy),
+        _ => Err(driver::Error::io::<Psci>("unexpected status")),
+    }
+}
+
+impl Psci {
+    /// Make a call following the SMC calling convention, which allows x4-x17 to be clobbered.
+    fn call(&self, function: u32, arg0: u64, arg1: u64, arg2: u64) -> u64 {
+        let mut result = u64::from(function);
+        // SAFETY: the PSCI implementation only touches the registers declared here, and none of
+        // the functions we call can affect memory the kernel owns
+        unsafe {
+            match self.conduit {
+                Conduit::Hvc => asm!(
+                    "hvc #0",
+                    inout("x0") result,
+                    inout("x1") arg0 => _,
+                    inout("x2") arg1 => _,
+                    inout("x3") arg2 => _,
+                    lateout("x4") _, lateout("x5") _, lateout("x6") _, lateout("x7") _,
+                    lateout("x8") _, lateout("x9") _, lateout("x10") _, lateout("x11") _,
+                    lateout("x12") _, lateout("x13") _, lateout("x14") _, lateout("x15") _,
+                    lateout("x16") _, lateout("x17") _,
+                    options(nostack),
+                ),
+                Conduit::Smc => asm!(
+                    "smc #0",
+                    inout("x0") result,
+                    inout("x1") arg0 => _,
+                    inout("x2") arg1 => _,
+                    inout("x3") arg2 => _,
+                    lateout("x4") _, lateout("x5") _, lateout("x6") _, lateout("x7") _,
+                    lateout("x8") _, lateout("x9") _, lateout("x10") _, lateout("x11") _,
+                    lateout("x12") _, lateout("x13") _, lateout("x14") _, lateout("x15") _,
+                    lateout("x16") _, lateout("x17") _,
+                    options(nostack),
+                ),
+            }
+        }
+        result
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Psci {
+    pub const fn new(conduit: Conduit) -> Self {
+        Self { conduit }
+    }
+
+    /// The major and minor version of PSCI that's implemented.
+    pub fn version(&self) -> (u16, u16) {
+        let version = self.call(function::PSCI_VERSION, 0, 0, 0) as u32;
+        ((version >> 16) as u16, version as u16)
+    }
+
+    /// Start the core identified by `target`, which is given in the format of `MPIDR_EL1`.
+    ///
+    /// The core begins executing at the physical address `entry_point` at the kernel's exception
+    /// level, with the MMU off and `context` in x0.
+    ///
+    /// # Safety
+    ///
+    /// `entry_point` must be code that's able to run on a freshly started core, setting up its own
+    /// stack before it touches any memory.
+    pub unsafe fn cpu_on(
+        &self,
+        target: u64,
+        entry_point: usize,
+        context: u64,
+    ) -> Result<(), driver::Error> {
+        let status = self.call(function::CPU_ON, target, entry_point as u64, context);
+        status_to_result(status as i32)
+    }
+
+    /// Turn the whole machine off.
+    ///
+    /// This only returns if the implementation doesn't support it, so the caller should be ready to
+    /// halt some other way.
+    pub fn system_off(&self) {
+        self.call(function::SYSTEM_OFF, 0, 0, 0);
+    }
+
+    /// Reset the whole machine. Like [Psci::system_off], this only returns on failure.
+    pub fn system_reset(&self) {
+        self.call(function::SYSTEM_RESET, 0, 0, 0);
+    }
+}
+
+impl Driver for Psci {
+    const COMPATIBLE: &'static str = "arm,psci-0.2";
+    const ALSO_COMPATIBLE: &'static [&'static str] = &["arm,psci-1.0"];
+}
@@ -0,0 +1,106 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Driver for the random number generator built into x86 processors, which is read with the
+//! RDSEED and RDRAND instructions.
+//!
+//! RDSEED returns the output of the entropy source itself, which is what a seed should be made of,
+//! but it runs out when it's asked too often. RDRAND goes through a generator that the entropy
+//! source reseeds, so it's used when RDSEED isn't there or keeps failing. Both are optional, and
+//! QEMU only passes them through with a CPU model that has them, e.g. `-cpu max`.
+
+use crate::driver::{self, traits::Driver};
+use crate::rand::EntropySource;
+use core::arch::x86_64::{__cpuid, __cpuid_count, _rdrand64_step, _rdseed64_step};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+pub struct Rdrand {
+    has_rdseed: bool,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// CPUID leaf 1, ECX
+const CPUID_RDRAND: u32 = 1 << 30;
+/// CPUID leaf 7, EBX
+const CPUID_RDSEED: u32 = 1 << 18;
+
+/// How often to try each instruction before giving up, as Intel recommends for RDRAND. RDSEED
+/// fails more often, but then there's RDRAND to fall back on.
+const RETRIES: usize = 10;
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Rdrand {
+    /// The next random word, from RDSEED if possible.
+    fn read_word(&self) -> Result<u64, driver::Error> {
+        let mut word = 0;
+        if self.has_rdseed {
+            for _ in 0..RETRIES {
+                // SAFETY: probing found that the processor has RDSEED
+                if unsafe { _rdseed64_step(&mut word) } == 1 {
+                    return Ok(word)
+                }
+                core::hint::spin_loop();
+            }
+        }
+        for _ in 0..RETRIES {
+            // SAFETY: probing found that the processor has RDRAND
+            let success = unsafe { _rdrand64_step(&mut word) } == 1;
+            // some AMD processors report success with all ones after they were suspended
+            if success && word != u64::MAX {
+                return Ok(word)
+            }
+        }
+        Err(driver::Error::io::<Self>("the processor ran out of random numbers"))
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Rdrand {
+    pub const fn new() -> Self {
+        Self { has_rdseed: false }
+    }
+}
+
+impl Driver for Rdrand {
+    const COMPATIBLE: &'static str = "x86 RDRAND";
+
+    fn probe(&mut self) -> Result<(), driver::Error> {
+        // SAFETY: cpuid is available on every x86_64 processor
+        let (max_leaf, features) = unsafe { (__cpuid(0).eax, __cpuid(1).ecx) };
+        if features & CPUID_RDRAND == 0 {
+            return Err(driver::Error::NotPresent)
+        }
+        // SAFETY: as above, and leaf 7 exists
+        self.has_rdseed = max_leaf >= 7 && unsafe { __cpuid_count(7, 0).ebx } & CPUID_RDSEED != 0;
+        Ok(())
+    }
+}
+
+impl EntropySource for Rdrand {
+    fn fill(&mut self, bytes: &mut [u8]) -> Result<(), driver::Error> {
+        for chunk in bytes.chunks_mut(8) {
+            let word = self.read_word()?.to_ne_bytes();
+            chunk.copy_from_slice(&word[..chunk.len()]);
+        }
+        Ok(())
+    }
+}
+
+impl AsMut<dyn EntropySource> for Rdrand {
+    fn as_mut(&mut self) -> &mut (dyn EntropySource + 'static) {
+        self
+    }
+}
@@ -0,0 +1,227 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! The table of every driver on the board.
+//!
+//! The BSP creates its drivers and registers them, after which [Registry::init_all] probes each
+//! one and initializes the ones that found their device, every driver after the ones it depends on.
+//! A driver that fails is logged and left out of the lookups, without holding up the others.
+
+use crate::driver::{traits::Compatible, Error};
+use crate::sync::OnceCell;
+use crate::warn;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// How many drivers can be registered.
+pub const MAX_DRIVERS: usize = 16;
+
+/// Where a driver is in its lifecycle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    /// Waiting to be probed.
+    Registered,
+    /// The device is there, and the driver is waiting for its dependencies.
+    Probed,
+    /// Ready to be used.
+    Initialized,
+    /// Probing or initialization failed, so the driver can't be used.
+    Failed(Error),
+}
+
+pub struct Registry {
+    entries: [Option<Entry>; MAX_DRIVERS],
+    len: usize,
+    /// Indices into `entries`, in the order the drivers were initialized
+    init_order: [usize; MAX_DRIVERS],
+    initialized: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+#[derive(Clone, Copy)]
+struct Entry {
+    driver: &'static dyn Compatible,
+    state: State,
+}
+
+/// Whether a driver's dependencies allow it to be initialized.
+enum Dependencies {
+    Ready,
+    Waiting,
+    Failed,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Registry {
+    fn entries(&self) -> impl DoubleEndedIterator<Item = &Entry> {
+        self.entries[..self.len].iter().flatten()
+    }
+
+    fn set_state(&mut self, index: usize, state: State) {
+        if let Some(entry) = &mut self.entries[index] {
+            entry.state = state;
+        }
+        if state == State::Initialized {
+            self.init_order[self.initialized] = index;
+            self.initialized += 1;
+        }
+    }
+
+    /// A dependency is met once any driver compatible with it is initialized, and can't be met
+    /// once every such driver has failed.
+    fn dependencies(&self, driver: &dyn Compatible) -> Dependencies {
+        let mut result = Dependencies::Ready;
+        for &dependency in driver.dependencies() {
+            let (mut initialized, mut pending) = (false, false);
+            for candidate in self.entries().filter(|entry| entry.driver.is_compatible(dependency)) {
+                match candidate.state {
+                    State::Initialized => initialized = true,
+                    State::Registered | State::Probed => pending = true,
+                    State::Failed(_) => {}
+                }
+            }
+
+            if initialized {
+                continue
+            } else if pending {
+                result = Dependencies::Waiting;
+            } else {
+                return Dependencies::Failed
+            }
+        }
+        result
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Registry {
+    pub const fn new() -> Self {
+        Self {
+            entries: [None; MAX_DRIVERS],
+            len: 0,
+            init_order: [0; MAX_DRIVERS],
+            initialized: 0,
+        }
+    }
+
+    /// Move `driver` into `slot`, which gives it a fixed home, and add it to the registry.
+    ///
+    /// # Panics
+    ///
+    /// If the registry is full, or `slot` already holds a driver.
+    pub fn register<T: Compatible>(&mut self, slot: &'static OnceCell<T>, driver: T) {
+        assert!(self.len < MAX_DRIVERS, "too many drivers, raise MAX_DRIVERS");
+        assert!(!slot.is_initialized(), "driver registered twice");
+
+        let driver = slot.get_or_init(|| driver);
+        self.entries[self.len] = Some(Entry { driver, state: State::Registered });
+        self.len += 1;
+    }
+
+    /// Probe every registered driver, then initialize the ones that found their device, each after
+    /// the drivers it depends on.
+    pub fn init_all(&mut self) {
+        for index in 0..self.len {
+            let driver = match self.entries[index] {
+                Some(entry) if entry.state == State::Registered => entry.driver,
+                _ => continue,
+            };
+            let state = match driver.probe() {
+                Ok(()) => State::Probed,
+                Err(e) => {
+                    warn!("Driver '{}' didn't find its device: {}", driver.compatible(), e);
+                    State::Failed(e)
+                }
+            };
+            self.set_state(index, state);
+        }
+
+        // keep making passes until nothing changes, so dependencies can be registered in any order
+        let mut progress = true;
+        while progress {
+            progress = false;
+            for index in 0..self.len {
+                let driver = match self.entries[index] {
+                    Some(entry) if entry.state == State::Probed => entry.driver,
+                    _ => continue,
+                };
+                let result = match self.dependencies(driver) {
+                    Dependencies::Waiting => continue,
+                    Dependencies::Ready => driver.init(self),
+                    Dependencies::Failed => Err(Error::Unavailable),
+                };
+                let state = match result {
+                    Ok(()) => State::Initialized,
+                    Err(e) => {
+                        warn!("Failed to initialize driver '{}': {}", driver.compatible(), e);
+                        State::Failed(e)
+                    }
+                };
+                self.set_state(index, state);
+                progress = true;
+            }
+        }
+
+        // whatever is still waiting depends on itself, directly or through other drivers
+        for index in 0..self.len {
+            if let Some(entry) = self.entries[index] {
+                if entry.state == State::Probed {
+                    warn!("Driver '{}' has circular dependencies", entry.driver.compatible());
+                    self.set_state(index, State::Failed(Error::Unavailable));
+                }
+            }
+        }
+    }
+
+    /// Shut down every initialized driver, in the reverse of the order they were initialized in.
+    ///
+    /// Drivers that are locked, e.g. by the code that panicked, are skipped.
+    pub fn shutdown_all(&self) {
+        for &index in self.init_order[..self.initialized].iter().rev() {
+            if let Some(entry) = &self.entries[index] {
+                entry.driver.shutdown();
+            }
+        }
+    }
+
+    /// The initialized driver of type `T`, e.g. `SpinMutex<Gpio>`.
+    pub fn get<T: 'static>(&self) -> Option<&'static T> {
+        self.iter().find_map(|driver| driver.as_any().downcast_ref::<T>())
+    }
+
+    /// The initialized driver for devices that are compatible with `compatible`.
+    pub fn find(&self, compatible: &str) -> Option<&'static dyn Compatible> {
+        self.iter().find(|driver| driver.is_compatible(compatible))
+    }
+
+    /// The `compatible` string and state of every registered driver, including the ones that
+    /// failed, in the order they were registered.
+    pub fn states(&self) -> impl Iterator<Item = (&'static str, State)> + '_ {
+        self.entries().map(|entry| (entry.driver.compatible(), entry.state))
+    }
+
+    /// Every initialized driver, in the order they were registered.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &'static dyn Compatible> + '_ {
+        self.entries()
+            .filter(|entry| entry.state == State::Initialized)
+            .map(|entry| entry.driver)
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,148 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Driver for the RNG200 hardware random number generator in the BCM2711.
+//!
+//! It sits where the older generator was on the BCM2837, but works differently. Random words are
+//! read from a FIFO, and the generator stops if its self-tests fail, in which case it has to be
+//! reset.
+
+use crate::driver::{self, registry::Registry, traits::Driver};
+use crate::rand::EntropySource;
+use crate::time::SimpleTimer;
+use core::time::Duration;
+use tock_registers::interfaces::{Readable, Writeable};
+use tock_registers::register_structs;
+use tock_registers::registers::{ReadOnly, ReadWrite};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+pub struct Rng200 {
+    regs: &'static mut RegisterBlock,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Description taken from Linux's iproc-rng200.c, since the datasheet leaves the generator out
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => CTRL: ReadWrite<u32>),
+        (0x04 => SOFT_RESET: ReadWrite<u32>),
+        (0x08 => RBG_SOFT_RESET: ReadWrite<u32>),
+        (0x0c => _reserved0),
+        (0x18 => INT_STATUS: ReadWrite<u32>),
+        (0x1c => _reserved1),
+        (0x20 => FIFO_DATA: ReadOnly<u32>),
+        (0x24 => FIFO_COUNT: ReadOnly<u32>),
+        (0x28 => @END),
+    }
+}
+
+/// The bits of CTRL that configure the generator, of which the lowest enables it
+const CTRL_RBGEN_MASK: u32 = 0x1fff;
+const CTRL_RBGEN_ENABLE: u32 = 0x1;
+
+const SOFT_RESET: u32 = 0x1;
+
+/// The generator failed its self-tests and stopped
+const INT_STATUS_MASTER_FAIL_LOCKUP: u32 = 0x8000_0000;
+const INT_STATUS_NIST_FAIL: u32 = 0x20;
+const INT_STATUS_FAILED: u32 = INT_STATUS_MASTER_FAIL_LOCKUP | INT_STATUS_NIST_FAIL;
+
+const FIFO_COUNT_MASK: u32 = 0xff;
+
+/// How long to wait for a word, which has to be long enough for the generator to start up.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Rng200 {
+    fn set_enabled(&self, enabled: bool) {
+        let control = self.regs.CTRL.get() & !CTRL_RBGEN_MASK;
+        let enable = if enabled { CTRL_RBGEN_ENABLE } else { 0 };
+        self.regs.CTRL.set(control | enable);
+    }
+
+    /// Reset the generator and its FIFO, and start it again.
+    fn reset(&self) {
+        self.set_enabled(false);
+        self.regs.INT_STATUS.set(u32::MAX);
+
+        let reset = self.regs.SOFT_RESET.get();
+        self.regs.SOFT_RESET.set(reset | SOFT_RESET);
+        self.regs.SOFT_RESET.set(reset & !SOFT_RESET);
+        let reset = self.regs.RBG_SOFT_RESET.get();
+        self.regs.RBG_SOFT_RESET.set(reset | SOFT_RESET);
+        self.regs.RBG_SOFT_RESET.set(reset & !SOFT_RESET);
+
+        self.regs.INT_STATUS.set(u32::MAX);
+        self.set_enabled(true);
+    }
+
+    /// Wait for the next word from the FIFO.
+    fn read_word(&self) -> Result<u32, driver::Error> {
+        let timer = crate::time::arch_timer();
+        let deadline = timer.uptime() + TIMEOUT;
+        while self.regs.FIFO_COUNT.get() & FIFO_COUNT_MASK == 0 {
+            if self.regs.INT_STATUS.get() & INT_STATUS_FAILED != 0 {
+                self.reset();
+                return Err(driver::Error::io::<Self>("the generator failed its self-tests"))
+            }
+            if timer.uptime() > deadline {
+                return Err(driver::Error::Timeout)
+            }
+            core::hint::spin_loop();
+        }
+        Ok(self.regs.FIFO_DATA.get())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Rng200 {
+    /// # Safety
+    /// The user must verify that the address is the base of the random number generator.
+    pub unsafe fn new(base_address: usize) -> Self {
+        Self { regs: &mut *(base_address as *mut _) }
+    }
+}
+
+impl Driver for Rng200 {
+    const COMPATIBLE: &'static str = "brcm,bcm2711-rng200";
+    const ALSO_COMPATIBLE: &'static [&'static str] = &["brcm,iproc-rng200"];
+
+    fn init(&mut self, _registry: &Registry) -> Result<(), driver::Error> {
+        self.reset();
+        Ok(())
+    }
+
+    fn shutdown(&mut self) {
+        self.set_enabled(false);
+    }
+}
+
+impl EntropySource for Rng200 {
+    fn fill(&mut self, bytes: &mut [u8]) -> Result<(), driver::Error> {
+        for chunk in bytes.chunks_mut(4) {
+            let word = self.read_word()?.to_ne_bytes();
+            chunk.copy_from_slice(&word[..chunk.len()]);
+        }
+        Ok(())
+    }
+}
+
+impl AsMut<dyn EntropySource> for Rng200 {
+    fn as_mut(&mut self) -> &mut (dyn EntropySource + 'static) {
+        self
+    }
+}
@@ -3,43 +3,102 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
 //! Driver for writing to the text-mode VGA that all x86 systems have.
+//!
+//! Output goes through an [ansi] parser, so the usual VT100 escape sequences work:
+//!
+//! - SGR (`ESC [ ... m`): reset, bold, blink, reverse video, and the 8 normal and 8 bright
+//!   foreground and background colors
+//! - CUP (`ESC [ row ; column H`) and the relative moves (`A`, `B`, `C` and `D`)
+//! - ED (`ESC [ n J`) and EL (`ESC [ n K`) to clear the screen or the current line
+//! - `ESC [ ? 25 h` and `ESC [ ? 25 l` to show and hide the cursor
+//!
+//! Text is converted to [code page 437](cp437), which is what the VGA's font is laid out in.
+//! Characters it doesn't have are replaced with a single glyph that's chosen by the BSP.
+//!
+//! The hardware cursor is moved to wherever the next character will go after every write.
+//!
+//! Lines that scroll off the top of the screen are kept in a scrollback buffer that the BSP
+//! provides, so its depth is up to the BSP. [TextVga::page_up] and [TextVga::page_down] page
+//! through it, and anything written brings the live output back.
 
-use crate::driver::WriteError;
-use crate::driver::traits::Driver;
+use crate::driver::ansi::{self, Action, ControlSequence};
+use crate::driver::cp437;
+use crate::driver::{self, registry::Registry, traits::Driver, WriteError};
+use x86::io::{inb, outb};
 
-const COLUMN_COUNT: u8 = 80;
-const LINE_COUNT: u8 = 25;
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A line of text, as it's kept in the scrollback.
+pub type Line = [VgaChar; COLUMN_COUNT as usize];
 
+/// An empty line, e.g. for initializing the scrollback.
+pub const BLANK_LINE: Line =
+    [VgaChar::from_ascii(b' ', Attributes::DEFAULT.style()); COLUMN_COUNT as usize];
+
+/// A character on the screen, with its colors.
 #[repr(C)]
 #[derive(Copy, Clone)]
-struct VgaChar {
+pub struct VgaChar {
     character: u8,
     style: VgaStyle,
 }
 
-impl VgaChar {
-    const WHITESPACE: Self = Self::from_ascii(b' ');
+pub struct TextVga {
+    /// Index of the line currently being written to
+    line_offset: u8,
+    /// Index of the column currently being written to
+    column_offset: u8,
+    /// The attributes set by SGR sequences, which new text is written with
+    attributes: Attributes,
+    /// What's shown in place of characters that code page 437 doesn't have
+    replacement: u8,
+    /// Whether the cursor is shown when the screen shows the live output
+    cursor_visible: bool,
+    parser: ansi::Parser,
+    /// Lines that scrolled off the top of the screen, as a ring starting at `history_start`
+    history: &'static mut [Line],
+    history_start: usize,
+    history_len: usize,
+    /// How many lines back from the live output the screen is showing
+    view_offset: usize,
+    /// The live output, saved while the screen is showing the scrollback
+    live: [Line; LINE_COUNT as usize],
+    /// The VGA text buffer
+    buffer: *mut [Line; LINE_COUNT as usize],
+}
 
-    const fn from_char(c: char) -> Option<Self> {
-        if c.is_ascii() {
-            Some(Self::from_ascii(c as u8))
-        } else {
-            None
-        }
-    }
+unsafe impl Sync for TextVga {}
+unsafe impl Send for TextVga {}
 
-    const fn from_ascii(c: u8) -> Self {
-        Self {
-            character: c,
-            style: VgaStyle::WHITE_ON_BLACK,
-        }
-    }
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
 
-    const fn style(mut self, style: VgaStyle) -> Self {
-        self.style = style;
-        self
-    }
-}
+const COLUMN_COUNT: u8 = 80;
+const LINE_COUNT: u8 = 25;
+
+const TAB_WIDTH: u8 = 8;
+
+// CRT controller registers, which are selected by writing their index to CRTC_ADDRESS and then
+// accessed through CRTC_DATA
+const CRTC_ADDRESS: u16 = 0x3d4;
+const CRTC_DATA: u16 = 0x3d5;
+const CRTC_CURSOR_START: u8 = 0x0a;
+const CRTC_CURSOR_END: u8 = 0x0b;
+const CRTC_CURSOR_LOCATION_HIGH: u8 = 0x0e;
+const CRTC_CURSOR_LOCATION_LOW: u8 = 0x0f;
+
+const CURSOR_START_DISABLE: u8 = 1 << 5;
+const CURSOR_START_SCANLINE: u8 = 0b0001_1111;
+const CURSOR_END_SCANLINE: u8 = 0b0001_1111;
+
+/// How many lines a page up or down moves, which leaves one line of overlap.
+const PAGE_LINES: usize = LINE_COUNT as usize - 1;
+
+/// Draw the cursor as an underline on the bottom two scanlines of the 16 in each character.
+const CURSOR_SCANLINES: (u8, u8) = (14, 15);
 
 #[repr(u8)]
 #[derive(Copy, Clone)]
@@ -58,9 +117,46 @@ enum VgaColor {
 #[derive(Copy, Clone)]
 struct VgaStyle(u8);
 
-impl VgaStyle {
-    const WHITE_ON_BLACK: Self = Self::new(VgaColor::LightGrey, true, VgaColor::Black, false);
+/// The SGR state, which is turned into a [VgaStyle] for each character.
+#[derive(Copy, Clone)]
+struct Attributes {
+    foreground: VgaColor,
+    background: VgaColor,
+    bright: bool,
+    blink: bool,
+    reverse: bool,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl VgaChar {
+    const fn from_ascii(c: u8, style: VgaStyle) -> Self {
+        Self {
+            character: c,
+            style,
+        }
+    }
+}
 
+impl VgaColor {
+    /// Convert one of the 8 ANSI colors, which are numbered differently than the VGA ones.
+    const fn from_ansi(color: u16) -> Self {
+        match color % 8 {
+            0 => VgaColor::Black,
+            1 => VgaColor::Red,
+            2 => VgaColor::Green,
+            3 => VgaColor::Brown,
+            4 => VgaColor::Blue,
+            5 => VgaColor::Magenta,
+            6 => VgaColor::Cyan,
+            _ => VgaColor::LightGrey,
+        }
+    }
+}
+
+impl VgaStyle {
     const fn new(foreground: VgaColor, bright: bool, background: VgaColor, blink: bool) -> Self {
         let foreground = foreground as u8;
         let bright = (bright as u8) << 3;
@@ -70,25 +166,86 @@ impl VgaStyle {
     }
 }
 
-pub struct TextVga {
-    /// Index of the line currently being written to
-    line_offset: u8,
-    /// Index of the column currently being written to
-    column_offset: u8,
-    /// The VGA text buffer
-    buffer: *mut [[VgaChar; COLUMN_COUNT as usize]; LINE_COUNT as usize],
+impl Attributes {
+    const DEFAULT: Self = Self {
+        foreground: VgaColor::LightGrey,
+        background: VgaColor::Black,
+        bright: false,
+        blink: false,
+        reverse: false,
+    };
+
+    const fn style(self) -> VgaStyle {
+        if self.reverse {
+            VgaStyle::new(self.background, self.bright, self.foreground, self.blink)
+        } else {
+            VgaStyle::new(self.foreground, self.bright, self.background, self.blink)
+        }
+    }
+
+    /// Apply the parameters of an SGR sequence.
+    fn apply(&mut self, params: &[u16]) {
+        // `ESC [ m` is the same as `ESC [ 0 m`
+        if params.is_empty() {
+            *self = Self::DEFAULT;
+        }
+
+        for &param in params {
+            match param {
+                0 => *self = Self::DEFAULT,
+                1 => self.bright = true,
+                2 | 22 => self.bright = false,
+                5 | 6 => self.blink = true,
+                25 => self.blink = false,
+                7 => self.reverse = true,
+                27 => self.reverse = false,
+                30..=37 => self.foreground = VgaColor::from_ansi(param),
+                39 => self.foreground = Self::DEFAULT.foreground,
+                40..=47 => self.background = VgaColor::from_ansi(param),
+                49 => self.background = Self::DEFAULT.background,
+                // there's only one brightness bit, so bright backgrounds come out normal
+                90..=97 => {
+                    self.foreground = VgaColor::from_ansi(param);
+                    self.bright = true;
+                }
+                100..=107 => self.background = VgaColor::from_ansi(param),
+                _ => {}
+            }
+        }
+    }
 }
 
-unsafe impl Sync for TextVga {}
-unsafe impl Send for TextVga {}
+/// Write a CRT controller register.
+fn write_crtc(index: u8, value: u8) {
+    // SAFETY: the CRT controller is at the same ports on every VGA
+    unsafe {
+        outb(CRTC_ADDRESS, index);
+        outb(CRTC_DATA, value);
+    }
+}
+
+/// Read a CRT controller register.
+fn read_crtc(index: u8) -> u8 {
+    // SAFETY: the CRT controller is at the same ports on every VGA
+    unsafe {
+        outb(CRTC_ADDRESS, index);
+        inb(CRTC_DATA)
+    }
+}
 
 impl TextVga {
-    pub unsafe fn new(base_address: usize) -> Self {
-        Self {
-            line_offset: 0,
-            column_offset: 0,
-            buffer: base_address as *mut _,
-        }
+    fn blank(&self) -> VgaChar {
+        VgaChar::from_ascii(b' ', self.attributes.style())
+    }
+
+    fn read_line(&self, line: usize) -> Line {
+        // SAFETY: the line is inside the text buffer
+        unsafe { core::ptr::addr_of!((*self.buffer)[line]).read_volatile() }
+    }
+
+    fn write_line(&mut self, line: usize, contents: Line) {
+        // SAFETY: the line is inside the text buffer
+        unsafe { core::ptr::addr_of_mut!((*self.buffer)[line]).write_volatile(contents) }
     }
 
     fn set_char(&mut self, line: usize, column: usize, c: VgaChar) {
@@ -101,6 +258,21 @@ impl TextVga {
         }
     }
 
+    /// Blank the columns from `start` up to, but not including, `end` on `line`.
+    fn clear_columns(&mut self, line: u8, start: u8, end: u8) {
+        let blank = self.blank();
+        for column in start..end {
+            self.set_char(line as usize, column as usize, blank);
+        }
+    }
+
+    /// Blank the lines from `start` up to, but not including, `end`.
+    fn clear_lines(&mut self, start: u8, end: u8) {
+        for line in start..end {
+            self.clear_columns(line, 0, COLUMN_COUNT);
+        }
+    }
+
     fn bump_cursor(&mut self) {
         self.column_offset += 1;
         if self.column_offset >= COLUMN_COUNT {
@@ -120,77 +292,260 @@ impl TextVga {
     }
 
     fn scroll(&mut self) {
+        let top = self.read_line(0);
+        self.push_history(top);
+
         // scroll the text upwards
-        let dest = self.buffer as *mut [[VgaChar; COLUMN_COUNT as usize]; LINE_COUNT as usize - 1];
+        let dest = self.buffer as *mut [Line; LINE_COUNT as usize - 1];
         // SAFETY: we offset by 1, so we can only copy LINE_COUNT - 1 entries. Type type
         // cast above ensures that's the case
         unsafe {
-            let src = (self.buffer as *mut [VgaChar; COLUMN_COUNT as usize]).offset(1) as *mut _;
+            let src = (self.buffer as *mut Line).offset(1) as *mut _;
             core::ptr::copy(src, dest, 1);
         }
 
         // overwrite the last line with spaces
-        let last_line = (LINE_COUNT - 1) as usize;
-        let whitespace = VgaChar::from_ascii(b' ');
-        for column in 0..COLUMN_COUNT {
-            self.set_char(last_line, column as usize, whitespace);
+        self.clear_lines(LINE_COUNT - 1, LINE_COUNT);
+    }
+
+    /// Move the cursor to the 0-based `line` and `column`, clamped to the screen.
+    fn move_cursor(&mut self, line: i32, column: i32) {
+        self.line_offset = line.clamp(0, i32::from(LINE_COUNT) - 1) as u8;
+        self.column_offset = column.clamp(0, i32::from(COLUMN_COUNT) - 1) as u8;
+    }
+
+    /// Move the hardware cursor to where the next character will be written.
+    fn update_cursor(&self) {
+        let position = u16::from(self.line_offset) * u16::from(COLUMN_COUNT)
+            + u16::from(self.column_offset);
+        write_crtc(CRTC_CURSOR_LOCATION_HIGH, (position >> 8) as u8);
+        write_crtc(CRTC_CURSOR_LOCATION_LOW, position as u8);
+    }
+
+    fn push_history(&mut self, line: Line) {
+        let capacity = self.history.len();
+        if capacity == 0 {
+            return
+        }
+
+        self.history[(self.history_start + self.history_len) % capacity] = line;
+        if self.history_len < capacity {
+            self.history_len += 1;
+        } else {
+            // the oldest line was just overwritten
+            self.history_start = (self.history_start + 1) % capacity;
         }
     }
 
-    fn write_vga_char(&mut self, c: VgaChar) {
-        match c {
-            VgaChar { character: b'\n', .. } => {
-                self.bump_line();
+    /// Show the output from `offset` lines before the live output, or the live output if it's 0.
+    fn show(&mut self, offset: usize) {
+        let offset = offset.min(self.history_len);
+        if offset == self.view_offset {
+            return
+        }
+
+        if self.view_offset == 0 {
+            for line in 0..LINE_COUNT as usize {
+                self.live[line] = self.read_line(line);
             }
-            VgaChar { character: b'\r', .. } => {
-                self.column_offset = 0;
+            self.show_cursor(false);
+        }
+
+        let first = self.history_len - offset;
+        for row in 0..LINE_COUNT as usize {
+            let index = first + row;
+            let line = match index.checked_sub(self.history_len) {
+                Some(live_index) => self.live[live_index],
+                None => self.history[(self.history_start + index) % self.history.len()],
+            };
+            self.write_line(row, line);
+        }
+
+        if offset == 0 {
+            self.show_cursor(self.cursor_visible);
+        }
+        self.view_offset = offset;
+    }
+
+    fn set_cursor_visible(&mut self, visible: bool) {
+        self.cursor_visible = visible;
+        if self.view_offset == 0 {
+            self.show_cursor(visible);
+        }
+    }
+
+    /// Turn the hardware cursor on or off.
+    fn show_cursor(&self, visible: bool) {
+        let start = read_crtc(CRTC_CURSOR_START);
+        if visible {
+            let (first, last) = CURSOR_SCANLINES;
+            let start = start & !(CURSOR_START_DISABLE | CURSOR_START_SCANLINE);
+            write_crtc(CRTC_CURSOR_START, start | first);
+            let end = read_crtc(CRTC_CURSOR_END) & !CURSOR_END_SCANLINE;
+            write_crtc(CRTC_CURSOR_END, end | last);
+        } else {
+            write_crtc(CRTC_CURSOR_START, start | CURSOR_START_DISABLE);
+        }
+    }
+
+    fn write_byte(&mut self, c: u8) {
+        let c = VgaChar::from_ascii(c, self.attributes.style());
+        self.set_char(self.line_offset as usize, self.column_offset as usize, c);
+        self.bump_cursor();
+    }
+
+    /// Carry out a control character, or write any other character to the screen.
+    fn put_char(&mut self, c: char) {
+        match c {
+            '\n' => self.bump_line(),
+            '\r' => self.column_offset = 0,
+            '\t' => {
+                let next_stop = (self.column_offset / TAB_WIDTH + 1) * TAB_WIDTH;
+                for _ in self.column_offset..next_stop.min(COLUMN_COUNT) {
+                    self.write_byte(b' ');
+                }
             }
-            VgaChar { character: b'\t', .. } => {
-                for _ in 0..8 {
-                    self.set_char(
-                        self.line_offset as usize,
-                        self.column_offset as usize,
-                        VgaChar::WHITESPACE
-                    );
-                    self.bump_cursor();
+            '\x08' => self.column_offset = self.column_offset.saturating_sub(1),
+            c if c.is_ascii_control() => {}
+            c => self.write_byte(cp437::encode(c).unwrap_or(self.replacement)),
+        }
+    }
+
+    fn control(&mut self, sequence: ControlSequence) {
+        let line = i32::from(self.line_offset);
+        let column = i32::from(self.column_offset);
+        let count = |index| i32::from(sequence.param(index, 1));
+
+        match (sequence.private, sequence.command) {
+            (false, 'm') => self.attributes.apply(sequence.params()),
+            (false, 'H') | (false, 'f') => self.move_cursor(count(0) - 1, count(1) - 1),
+            (false, 'A') => self.move_cursor(line - count(0), column),
+            (false, 'B') => self.move_cursor(line + count(0), column),
+            (false, 'C') => self.move_cursor(line, column + count(0)),
+            (false, 'D') => self.move_cursor(line, column - count(0)),
+            (false, 'J') => {
+                let (line, column) = (self.line_offset, self.column_offset);
+                match sequence.param(0, 0) {
+                    0 => {
+                        self.clear_columns(line, column, COLUMN_COUNT);
+                        self.clear_lines(line + 1, LINE_COUNT);
+                    }
+                    1 => {
+                        self.clear_lines(0, line);
+                        self.clear_columns(line, 0, column + 1);
+                    }
+                    _ => self.clear_lines(0, LINE_COUNT),
                 }
             }
-            c => {
-                self.set_char(self.line_offset as usize, self.column_offset as usize, c);
-                self.bump_cursor();
+            (false, 'K') => {
+                let (line, column) = (self.line_offset, self.column_offset);
+                match sequence.param(0, 0) {
+                    0 => self.clear_columns(line, column, COLUMN_COUNT),
+                    1 => self.clear_columns(line, 0, column + 1),
+                    _ => self.clear_columns(line, 0, COLUMN_COUNT),
+                }
             }
+            (true, 'h') if sequence.params() == [25] => self.set_cursor_visible(true),
+            (true, 'l') if sequence.params() == [25] => self.set_cursor_visible(false),
+            _ => {}
+        }
+    }
+
+    fn write(&mut self, c: char) {
+        match self.parser.advance(c) {
+            Action::None => {}
+            Action::Print(c) => self.put_char(c),
+            Action::Control(sequence) => self.control(sequence),
         }
     }
 }
 
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl TextVga {
+    /// Characters that code page 437 doesn't have are shown as `replacement`, or as `?` if it
+    /// doesn't have that either.
+    ///
+    /// Lines that scroll off the screen are kept in `history`, until it fills up and the oldest
+    /// ones are overwritten.
+    ///
+    /// # Safety
+    /// The user must verify that `base_address` is the start of the VGA text buffer.
+    pub unsafe fn new(
+        base_address: usize,
+        replacement: char,
+        history: &'static mut [Line],
+    ) -> Self {
+        Self {
+            line_offset: 0,
+            column_offset: 0,
+            attributes: Attributes::DEFAULT,
+            replacement: cp437::encode(replacement).unwrap_or(b'?'),
+            cursor_visible: true,
+            parser: ansi::Parser::new(),
+            history,
+            history_start: 0,
+            history_len: 0,
+            view_offset: 0,
+            live: [BLANK_LINE; LINE_COUNT as usize],
+            buffer: base_address as *mut _,
+        }
+    }
+
+    /// Show the page of the scrollback before the one that's on the screen.
+    pub fn page_up(&mut self) {
+        self.show(self.view_offset + PAGE_LINES);
+    }
+
+    /// Show the page of the scrollback after the one that's on the screen, or the live output if
+    /// that's next.
+    pub fn page_down(&mut self) {
+        self.show(self.view_offset.saturating_sub(PAGE_LINES));
+    }
+
+    /// Show the live output again, if the screen is showing the scrollback.
+    pub fn return_to_live(&mut self) {
+        self.show(0);
+    }
+
+    /// Reset the colors, blank the screen and move the cursor to the top left.
+    pub fn clear(&mut self) {
+        self.show(0);
+        self.attributes = Attributes::DEFAULT;
+        self.clear_lines(0, LINE_COUNT);
+        self.move_cursor(0, 0);
+        self.update_cursor();
+    }
+}
+
 impl ufmt::uWrite for TextVga {
     type Error = WriteError;
 
     fn write_char(&mut self, c: char) -> Result<(), Self::Error> {
-        match VgaChar::from_char(c) {
-            Some(c) => {
-                self.write_vga_char(c);
-                Ok(())
-            }
-            None => Err(WriteError::UnicodeUnsupported),
-        }
+        self.show(0);
+        self.write(c);
+        self.update_cursor();
+        Ok(())
     }
 
     fn write_str(&mut self, s: &str) -> Result<(), Self::Error> {
-        for c in s.bytes() {
-            // We assume here that the user knows the encoding of the textmode VGA.
-            // Artifacts produced by using invalid ascii are hopefully visible enough to tell
-            // someone they've used the device wrong, because we still need to support printing
-            // those symbols.
-            let c = VgaChar::from_ascii(c);
-            self.write_vga_char(c);
-        }
-        return Ok(())
+        self.show(0);
+        s.chars().for_each(|c| self.write(c));
+        self.update_cursor();
+        Ok(())
     }
 }
 
 impl Driver for TextVga {
     const COMPATIBLE: &'static str = "Textmode VGA";
+
+    fn init(&mut self, _registry: &Registry) -> Result<(), driver::Error> {
+        self.clear();
+        self.set_cursor_visible(true);
+        Ok(())
+    }
 }
 
 impl AsMut<dyn ufmt::uWrite<Error=WriteError>> for TextVga {
@@ -0,0 +1,195 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Driver for the 16550-compatible serial ports that PCs have, accessed through I/O ports.
+
+use crate::arch;
+use crate::driver::{self, WriteError};
+use crate::driver::traits::Driver;
+use crate::driver::input::{CharInput, Utf8Decoder};
+use crate::driver::uart::{baud_within_tolerance, Parity, StopBits, Uart, UartConfig, WordLength};
+use x86::io::{inb, outb};
+
+// Register offsets from the base I/O port
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+
+// When LINE_CONTROL_DLAB is set, the first two registers hold the baud rate divisor instead
+const DIVISOR_LOW: u16 = 0;
+const DIVISOR_HIGH: u16 = 1;
+
+const LINE_CONTROL_WORD_LENGTH_5: u8 = 0b0000_0000;
+const LINE_CONTROL_WORD_LENGTH_6: u8 = 0b0000_0001;
+const LINE_CONTROL_WORD_LENGTH_7: u8 = 0b0000_0010;
+const LINE_CONTROL_WORD_LENGTH_8: u8 = 0b0000_0011;
+const LINE_CONTROL_TWO_STOP_BITS: u8 = 0b0000_0100;
+const LINE_CONTROL_PARITY_ENABLE: u8 = 0b0000_1000;
+const LINE_CONTROL_EVEN_PARITY: u8 = 0b0001_0000;
+const LINE_CONTROL_DLAB: u8 = 0b1000_0000;
+
+/// Enable and clear both FIFOs, interrupt once 14 bytes have been received
+const FIFO_CONTROL_ENABLE: u8 = 0b1100_0111;
+
+/// Assert DTR and RTS, and enable the auxiliary output that gates the interrupt line
+const MODEM_CONTROL_READY: u8 = 0b0000_1011;
+
+const LINE_STATUS_DATA_READY: u8 = 1 << 0;
+const LINE_STATUS_THR_EMPTY: u8 = 1 << 5;
+
+/// Frequency of the clock feeding the baud rate generator, divided by 16
+const BASE_BAUD: u32 = 115_200;
+
+pub struct Uart16550 {
+    port: u16,
+    config: UartConfig,
+    /// What's been received of a character that's split across reads
+    decoder: Utf8Decoder,
+}
+
+impl Uart16550 {
+    /// # Safety
+    /// The user must verify that `port` is the base I/O port of a 16550-compatible UART.
+    pub const unsafe fn new(port: u16) -> Self {
+        Self {
+            port,
+            config: UartConfig::new(BASE_BAUD),
+            decoder: Utf8Decoder::new(),
+        }
+    }
+
+    fn read_reg(&self, offset: u16) -> u8 {
+        // SAFETY: the port was checked by the caller of `new`
+        unsafe { inb(self.port + offset) }
+    }
+
+    fn write_reg(&mut self, offset: u16, value: u8) {
+        // SAFETY: the port was checked by the caller of `new`
+        unsafe { outb(self.port + offset, value) }
+    }
+
+    /// Configure the port with the given line settings, with FIFOs enabled and interrupts off.
+    pub fn init(&mut self, config: UartConfig) -> Result<(), driver::Error> {
+        self.write_reg(INTERRUPT_ENABLE, 0);
+        self.set_config(config)?;
+        self.write_reg(FIFO_CONTROL, FIFO_CONTROL_ENABLE);
+        self.write_reg(MODEM_CONTROL, MODEM_CONTROL_READY);
+        Ok(())
+    }
+}
+
+/// Compute the baud rate divisor, rounded to the nearest one.
+fn divisor(baud: u32) -> Result<u16, driver::Error> {
+    if baud == 0 {
+        return Err(driver::Error::UnsupportedBaudRate)
+    }
+
+    let divisor = (BASE_BAUD + baud / 2) / baud;
+    if divisor == 0 || divisor > u32::from(u16::MAX) {
+        return Err(driver::Error::UnsupportedBaudRate)
+    }
+    if !baud_within_tolerance(baud, u64::from(BASE_BAUD / divisor)) {
+        return Err(driver::Error::UnsupportedBaudRate)
+    }
+    Ok(divisor as u16)
+}
+
+impl Uart for Uart16550 {
+    fn send_ready(&self) -> bool {
+        self.read_reg(LINE_STATUS) & LINE_STATUS_THR_EMPTY != 0
+    }
+
+    fn send(&mut self, byte: u8) {
+        while !self.send_ready() {
+            arch::asm::nop()
+        }
+        self.write_reg(DATA, byte)
+    }
+
+    fn receive_ready(&self) -> bool {
+        self.read_reg(LINE_STATUS) & LINE_STATUS_DATA_READY != 0
+    }
+
+    fn receive(&mut self) -> u8 {
+        while !self.receive_ready() {
+            arch::asm::nop()
+        }
+        self.read_reg(DATA)
+    }
+
+    fn config(&self) -> UartConfig {
+        self.config
+    }
+
+    fn set_config(&mut self, config: UartConfig) -> Result<(), driver::Error> {
+        let divisor = divisor(config.baud)?;
+
+        let mut line_control = match config.word_length {
+            WordLength::Five => LINE_CONTROL_WORD_LENGTH_5,
+            WordLength::Six => LINE_CONTROL_WORD_LENGTH_6,
+            WordLength::Seven => LINE_CONTROL_WORD_LENGTH_7,
+            WordLength::Eight => LINE_CONTROL_WORD_LENGTH_8,
+        };
+        line_control |= match config.parity {
+            Parity::None => 0,
+            Parity::Even => LINE_CONTROL_PARITY_ENABLE | LINE_CONTROL_EVEN_PARITY,
+            Parity::Odd => LINE_CONTROL_PARITY_ENABLE,
+        };
+        if config.stop_bits == StopBits::Two {
+            line_control |= LINE_CONTROL_TWO_STOP_BITS;
+        }
+
+        self.write_reg(LINE_CONTROL, LINE_CONTROL_DLAB);
+        self.write_reg(DIVISOR_LOW, divisor as u8);
+        self.write_reg(DIVISOR_HIGH, (divisor >> 8) as u8);
+        self.write_reg(LINE_CONTROL, line_control);
+        self.config = config;
+        Ok(())
+    }
+}
+
+impl ufmt::uWrite for Uart16550 {
+    type Error = WriteError;
+
+    fn write_str(&mut self, msg: &str) -> Result<(), Self::Error> {
+        for byte in msg.bytes() {
+            if byte == b'\n' {
+                self.send(b'\r')
+            }
+            self.send(byte)
+        }
+        Ok(())
+    }
+}
+
+impl Driver for Uart16550 {
+    const COMPATIBLE: &'static str = "ns16550a";
+}
+
+impl CharInput for Uart16550 {
+    fn read_char(&mut self) -> Option<char> {
+        while self.receive_ready() {
+            let byte = self.receive_native();
+            if let Some(c) = self.decoder.push(byte) {
+                return Some(c)
+            }
+        }
+        None
+    }
+}
+
+impl AsMut<dyn ufmt::uWrite<Error=WriteError>> for Uart16550 {
+    fn as_mut(&mut self) -> &mut (dyn ufmt::uWrite<Error=WriteError> + 'static) {
+        self
+    }
+}
+
+impl AsMut<dyn CharInput> for Uart16550 {
+    fn as_mut(&mut self) -> &mut (dyn CharInput + 'static) {
+        self
+    }
+}
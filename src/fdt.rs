@@ -0,0 +1,610 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Parser for flattened device trees (DTBs).
+//!
+//! Whoever loads the kernel may pass it a device tree describing the hardware, see
+//! [`crate::runtime_init::device_tree`]. Nothing here allocates: nodes and properties are read
+//! straight out of the blob as they're iterated over, and everything returned borrows from it.
+//!
+//! Nodes remember the `#address-cells`, `#size-cells` and `ranges` of the buses above them, so
+//! that [`Node::reg`] can give addresses as the CPU sees them rather than as the bus does.
+
+use crate::driver::traits::Driver;
+use crate::fmt::Hex;
+use crate::interrupt::{InterruptController, IrqNumber};
+use crate::warn;
+use core::convert::TryInto;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Nodes nested deeper than this are ignored.
+pub const MAX_DEPTH: usize = 16;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The blob doesn't start with the device tree magic number.
+    BadMagic,
+    /// The blob uses a format version that this parser doesn't understand.
+    UnsupportedVersion,
+    /// The header points outside of the blob.
+    Truncated,
+}
+
+/// A device tree blob.
+#[derive(Clone, Copy)]
+pub struct Fdt<'a> {
+    /// The structure block, holding the nodes and properties
+    structs: &'a [u8],
+    /// The strings block, holding property names
+    strings: &'a [u8],
+}
+
+/// A node in the tree.
+#[derive(Clone, Copy)]
+pub struct Node<'a> {
+    fdt: Fdt<'a>,
+    name: &'a str,
+    /// Offset of the node's first property in the structure block
+    properties: usize,
+    /// Distance from the root node, which has a depth of 0
+    depth: usize,
+    /// Describes the bus of every ancestor, from the root down to this node's parent
+    ancestors: [Bus<'a>; MAX_DEPTH],
+    /// Phandle of the node that handles this node's interrupts
+    interrupt_parent: Option<u32>,
+}
+
+/// A property of a node.
+#[derive(Clone, Copy)]
+pub struct Property<'a> {
+    pub name: &'a str,
+    pub value: &'a [u8],
+}
+
+/// A property value made up of big-endian 32-bit cells.
+#[derive(Clone, Copy)]
+pub struct Cells<'a>(&'a [u8]);
+
+/// A range of addresses from a `reg` property.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    /// The physical address, as seen by the CPU
+    pub address: u64,
+    pub size: u64,
+}
+
+/// Iterator over all of the nodes in the tree, depth first.
+pub struct Nodes<'a> {
+    fdt: Fdt<'a>,
+    offset: usize,
+    depth: usize,
+    buses: [Bus<'a>; MAX_DEPTH],
+    interrupt_parents: [Option<u32>; MAX_DEPTH],
+}
+
+/// Iterator over the properties of a node.
+pub struct Properties<'a> {
+    fdt: Fdt<'a>,
+    offset: usize,
+}
+
+/// Iterator over the strings in a string list property, such as `compatible`.
+pub struct Strings<'a>(&'a [u8]);
+
+/// Iterator over the regions in a `reg` property.
+pub struct Reg<'a> {
+    node: Node<'a>,
+    cells: Cells<'a>,
+}
+
+/// Iterator over the specifiers in an `interrupts` property.
+///
+/// Each specifier is in the format of the node's interrupt parent, so it's up to the interrupt
+/// controller driver to make sense of it.
+pub struct Interrupts<'a> {
+    cells: Cells<'a>,
+    /// Number of cells in each specifier
+    specifier_cells: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const MAGIC: u32 = 0xd00d_feed;
+
+/// The format version this parser reads. Later versions are fine as long as they're backwards
+/// compatible with it.
+const VERSION: u32 = 17;
+
+const HEADER_LEN: usize = 40;
+
+/// Tokens in the structure block.
+mod token {
+    pub const BEGIN_NODE: u32 = 1;
+    pub const END_NODE: u32 = 2;
+    pub const PROP: u32 = 3;
+    pub const NOP: u32 = 4;
+}
+
+/// What a node tells its children about the bus they're on.
+#[derive(Clone, Copy)]
+struct Bus<'a> {
+    address_cells: u32,
+    size_cells: u32,
+    /// How the children's addresses map onto the parent's, if they do at all
+    ranges: Option<&'a [u8]>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_be_bytes(bytes.try_into().ok()?))
+}
+
+const fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+/// Read the null-terminated string at `offset`, returning it with the offset just past the null.
+fn read_str(bytes: &[u8], offset: usize) -> Option<(&str, usize)> {
+    let bytes = bytes.get(offset..)?;
+    let len = bytes.iter().position(|&b| b == 0)?;
+    let s = core::str::from_utf8(&bytes[..len]).ok()?;
+    Some((s, offset + len + 1))
+}
+
+impl<'a> Bus<'a> {
+    /// The values the specification says to assume when a node doesn't set them.
+    const DEFAULT: Self = Self {
+        address_cells: 2,
+        size_cells: 1,
+        ranges: None,
+    };
+}
+
+impl<'a> Fdt<'a> {
+    /// Read the property at `offset` in the structure block, which must be just past a PROP token.
+    fn property_at(&self, offset: usize) -> Option<(Property<'a>, usize)> {
+        let len = read_u32(self.structs, offset)? as usize;
+        let name_offset = read_u32(self.structs, offset + 4)? as usize;
+        let start = offset + 8;
+        let value = self.structs.get(start..start.checked_add(len)?)?;
+        let (name, _) = read_str(self.strings, name_offset)?;
+        Some((Property { name, value }, align4(start + len)))
+    }
+}
+
+impl<'a> Node<'a> {
+    /// The bus this node provides to its children.
+    fn bus(&self) -> Bus<'a> {
+        let mut bus = Bus::DEFAULT;
+        for property in self.properties() {
+            match property.name {
+                "#address-cells" => bus.address_cells = property.as_u32().unwrap_or(2),
+                "#size-cells" => bus.size_cells = property.as_u32().unwrap_or(1),
+                "ranges" => bus.ranges = Some(property.value),
+                _ => {}
+            }
+        }
+        bus
+    }
+
+    /// The bus this node sits on.
+    fn parent_bus(&self) -> Bus<'a> {
+        match self.depth {
+            0 => Bus::DEFAULT,
+            depth => self.ancestors[depth - 1],
+        }
+    }
+
+    /// Map an address on this node's bus to the address the CPU sees, using the `ranges` of every
+    /// ancestor in turn. Returns `None` if some bus along the way isn't memory mapped.
+    fn translate(&self, mut address: u64) -> Option<u64> {
+        for level in (1..self.depth).rev() {
+            let bus = self.ancestors[level];
+            let parent = self.ancestors[level - 1];
+
+            // an empty ranges property means that the addresses are the same on both sides
+            let ranges = bus.ranges?;
+            if ranges.is_empty() {
+                continue
+            }
+
+            let mut cells = Cells(ranges);
+            let mut translated = None;
+            while let (Some(child), Some(parent_address), Some(size)) = (
+                cells.read(bus.address_cells),
+                cells.read(parent.address_cells),
+                cells.read(bus.size_cells),
+            ) {
+                if address >= child && address - child < size {
+                    translated = Some(address - child + parent_address);
+                    break
+                }
+            }
+            address = translated?;
+        }
+        Some(address)
+    }
+}
+
+impl<'a> Cells<'a> {
+    /// Read a number made up of `count` cells. Only the low 64 bits are kept if it's any bigger.
+    fn read(&mut self, count: u32) -> Option<u64> {
+        let len = 4 * count as usize;
+        if self.0.len() < len {
+            return None
+        }
+
+        let (number, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(number.chunks_exact(4).fold(0, |value, cell| {
+            value << 32 | u64::from(u32::from_be_bytes(cell.try_into().unwrap()))
+        }))
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl ufmt::uDebug for Error {
+    fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: ufmt::uWrite + ?Sized
+    {
+        f.write_str(match self {
+            Error::BadMagic => "BadMagic",
+            Error::UnsupportedVersion => "UnsupportedVersion",
+            Error::Truncated => "Truncated",
+        })
+    }
+}
+
+/// The device tree the kernel was booted with, if it was given a valid one.
+pub fn boot() -> Option<Fdt<'static>> {
+    let address = crate::runtime_init::device_tree()?;
+
+    // SAFETY: the address was passed in by whoever loaded the kernel, and the blob is left alone
+    // after that
+    match unsafe { Fdt::from_address(address) } {
+        Ok(fdt) => Some(fdt),
+        Err(e) => {
+            warn!("Ignoring the device tree at {}: {:?}", Hex(address), e);
+            None
+        }
+    }
+}
+
+/// Find the address of register block `index` of the device that driver `T` handles.
+///
+/// The device tree is searched if there is one. `fallback` is used if there isn't, or if the device
+/// is missing from it.
+pub fn discover<T: Driver>(fdt: Option<&Fdt<'_>>, index: usize, fallback: usize) -> usize {
+    let fdt = match fdt {
+        Some(fdt) => fdt,
+        None => return fallback,
+    };
+
+    match fdt.find_driver::<T>().and_then(|node| node.reg().nth(index)) {
+        Some(region) => region.address as usize,
+        None => {
+            warn!("No {} in the device tree, assuming it's at {}", T::COMPATIBLE, Hex(fallback));
+            fallback
+        }
+    }
+}
+
+impl Fdt<'static> {
+    /// # Safety
+    /// The user must verify that `address` points to memory that can be read, and that the blob
+    /// there is never modified or freed.
+    pub unsafe fn from_address(address: usize) -> Result<Self, Error> {
+        let header = core::slice::from_raw_parts(address as *const u8, HEADER_LEN);
+        if read_u32(header, 0) != Some(MAGIC) {
+            return Err(Error::BadMagic)
+        }
+
+        let total_size = read_u32(header, 4).ok_or(Error::Truncated)? as usize;
+        Self::new(core::slice::from_raw_parts(address as *const u8, total_size))
+    }
+}
+
+impl<'a> Fdt<'a> {
+    pub fn new(blob: &'a [u8]) -> Result<Self, Error> {
+        let field = |index: usize| read_u32(blob, 4 * index).ok_or(Error::Truncated);
+
+        if field(0)? != MAGIC {
+            return Err(Error::BadMagic)
+        }
+        if field(5)? < VERSION || field(6)? > VERSION {
+            return Err(Error::UnsupportedVersion)
+        }
+
+        let struct_offset = field(2)? as usize;
+        let strings_offset = field(3)? as usize;
+        let strings_size = field(8)? as usize;
+        let struct_size = field(9)? as usize;
+        let block = |offset: usize, size: usize| {
+            let end = offset.checked_add(size).ok_or(Error::Truncated)?;
+            blob.get(offset..end).ok_or(Error::Truncated)
+        };
+
+        Ok(Self {
+            structs: block(struct_offset, struct_size)?,
+            strings: block(strings_offset, strings_size)?,
+        })
+    }
+
+    pub fn nodes(&self) -> Nodes<'a> {
+        Nodes {
+            fdt: *self,
+            offset: 0,
+            depth: 0,
+            buses: [Bus::DEFAULT; MAX_DEPTH],
+            interrupt_parents: [None; MAX_DEPTH],
+        }
+    }
+
+    /// Find the first enabled node that driver `T` can handle.
+    pub fn find_driver<T: Driver>(&self) -> Option<Node<'a>> {
+        self.nodes().find(|node| node.is_enabled() && node.is_compatible_with::<T>())
+    }
+
+    /// Find the node that's referred to by `phandle`.
+    pub fn find_phandle(&self, phandle: u32) -> Option<Node<'a>> {
+        self.nodes().find(|node| node.phandle() == Some(phandle))
+    }
+}
+
+impl<'a> Iterator for Nodes<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Node<'a>> {
+        let structs = self.fdt.structs;
+        loop {
+            let token = read_u32(structs, self.offset)?;
+            self.offset += 4;
+
+            match token {
+                token::BEGIN_NODE => {
+                    let (name, end) = read_str(structs, self.offset)?;
+                    self.offset = align4(end);
+                    if self.depth >= MAX_DEPTH {
+                        return None
+                    }
+
+                    let mut node = Node {
+                        fdt: self.fdt,
+                        name,
+                        properties: self.offset,
+                        depth: self.depth,
+                        ancestors: self.buses,
+                        interrupt_parent: None,
+                    };
+                    // the interrupt parent is inherited from the node's ancestors
+                    let inherited = match self.depth {
+                        0 => None,
+                        depth => self.interrupt_parents[depth - 1],
+                    };
+                    node.interrupt_parent = node
+                        .property("interrupt-parent")
+                        .and_then(|property| property.as_u32())
+                        .or(inherited);
+
+                    self.buses[self.depth] = node.bus();
+                    self.interrupt_parents[self.depth] = node.interrupt_parent;
+                    self.depth += 1;
+                    return Some(node)
+                }
+                token::END_NODE => self.depth = self.depth.checked_sub(1)?,
+                token::PROP => {
+                    let (_, next) = self.fdt.property_at(self.offset)?;
+                    self.offset = next;
+                }
+                token::NOP => {}
+                // FDT_END, or something we don't understand
+                _ => return None,
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Properties<'a> {
+    type Item = Property<'a>;
+
+    fn next(&mut self) -> Option<Property<'a>> {
+        loop {
+            let token = read_u32(self.fdt.structs, self.offset)?;
+            self.offset += 4;
+
+            match token {
+                token::PROP => {
+                    let (property, next) = self.fdt.property_at(self.offset)?;
+                    self.offset = next;
+                    return Some(property)
+                }
+                token::NOP => {}
+                // the properties end where the first child node (or the end of this node) starts
+                _ => return None,
+            }
+        }
+    }
+}
+
+impl<'a> Node<'a> {
+    /// The node's name, including the unit address, e.g. `serial@7e201000`.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    pub fn properties(&self) -> Properties<'a> {
+        Properties {
+            fdt: self.fdt,
+            offset: self.properties,
+        }
+    }
+
+    pub fn property(&self, name: &str) -> Option<Property<'a>> {
+        self.properties().find(|property| property.name == name)
+    }
+
+    pub fn compatible(&self) -> Strings<'a> {
+        Strings(self.property("compatible").map_or(&[], |property| property.value))
+    }
+
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.compatible().any(|s| s == compatible)
+    }
+
+    /// Check whether driver `T` can handle this node.
+    pub fn is_compatible_with<T: Driver>(&self) -> bool {
+        self.compatible().any(|s| s == T::COMPATIBLE || T::ALSO_COMPATIBLE.contains(&s))
+    }
+
+    /// Whether the device is usable. Nodes without a `status` property are.
+    pub fn is_enabled(&self) -> bool {
+        match self.property("status").and_then(|property| property.as_str()) {
+            Some(status) => status == "okay" || status == "ok",
+            None => true,
+        }
+    }
+
+    pub fn phandle(&self) -> Option<u32> {
+        self.property("phandle")
+            .or_else(|| self.property("linux,phandle"))
+            .and_then(|property| property.as_u32())
+    }
+
+    /// The memory-mapped regions of the device, with their addresses translated for the CPU.
+    ///
+    /// Regions that aren't visible to the CPU are skipped.
+    pub fn reg(&self) -> Reg<'a> {
+        Reg {
+            node: *self,
+            cells: Cells(self.property("reg").map_or(&[], |property| property.value)),
+        }
+    }
+
+    /// The interrupts the device raises, or `None` if it doesn't have any or its interrupt parent
+    /// can't be found.
+    pub fn interrupts(&self) -> Option<Interrupts<'a>> {
+        let interrupts = self.property("interrupts")?;
+        let parent = self.fdt.find_phandle(self.interrupt_parent?)?;
+        let specifier_cells = parent.property("#interrupt-cells")?.as_u32()? as usize;
+        if specifier_cells == 0 {
+            return None
+        }
+
+        Some(Interrupts {
+            cells: Cells(interrupts.value),
+            specifier_cells,
+        })
+    }
+
+    /// The first `N` interrupt lines of the device, numbered by `controller`. Returns `None` unless
+    /// all of them are found.
+    pub fn irqs<const N: usize>(
+        &self,
+        controller: &dyn InterruptController,
+    ) -> Option<[IrqNumber; N]> {
+        let mut interrupts = self.interrupts()?;
+        let mut irqs = [0; N];
+        for irq in irqs.iter_mut() {
+            *irq = controller.translate(interrupts.next()?)?;
+        }
+        Some(irqs)
+    }
+}
+
+impl<'a> Property<'a> {
+    /// The value as a single cell.
+    pub fn as_u32(&self) -> Option<u32> {
+        match self.value.len() {
+            4 => read_u32(self.value, 0),
+            _ => None,
+        }
+    }
+
+    /// The value as one or two cells, like the `reg` of a node on a bus without sizes.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self.value.len() {
+            4 => read_u32(self.value, 0).map(u64::from),
+            8 => {
+                let high = u64::from(read_u32(self.value, 0)?);
+                Some(high << 32 | u64::from(read_u32(self.value, 4)?))
+            }
+            _ => None,
+        }
+    }
+
+    /// The value as a single null-terminated string.
+    pub fn as_str(&self) -> Option<&'a str> {
+        let (s, end) = read_str(self.value, 0)?;
+        if end == self.value.len() {
+            Some(s)
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a> Iterator for Cells<'a> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        self.read(1).map(|cell| cell as u32)
+    }
+}
+
+impl<'a> Iterator for Strings<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let (s, end) = read_str(self.0, 0)?;
+        self.0 = &self.0[end..];
+        Some(s)
+    }
+}
+
+impl<'a> Iterator for Reg<'a> {
+    type Item = Region;
+
+    fn next(&mut self) -> Option<Region> {
+        let bus = self.node.parent_bus();
+        if bus.address_cells == 0 {
+            return None
+        }
+
+        loop {
+            let address = self.cells.read(bus.address_cells)?;
+            let size = self.cells.read(bus.size_cells)?;
+            if let Some(address) = self.node.translate(address) {
+                return Some(Region { address, size })
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Interrupts<'a> {
+    type Item = Cells<'a>;
+
+    fn next(&mut self) -> Option<Cells<'a>> {
+        let len = 4 * self.specifier_cells;
+        if self.cells.0.len() < len {
+            return None
+        }
+
+        let (specifier, rest) = self.cells.0.split_at(len);
+        self.cells.0 = rest;
+        Some(Cells(specifier))
+    }
+}
@@ -0,0 +1,41 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Formatting helpers for things `ufmt` doesn't support out of the box.
+
+/// Display an integer in hexadecimal, prefixed with `0x`.
+pub struct Hex<T>(pub T);
+
+macro_rules! impl_hex {
+    ($($ty:ty),*) => {$(
+        impl ufmt::uDisplay for Hex<$ty> {
+            #[allow(trivial_numeric_casts)] // for `Hex<usize>`
+            fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+            where
+                W: ufmt::uWrite + ?Sized
+            {
+                const DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+                let mut buffer = [0; 2 + 2 * core::mem::size_of::<$ty>()];
+                let mut start = buffer.len();
+                let mut value = self.0;
+                loop {
+                    start -= 1;
+                    buffer[start] = DIGITS[(value & 0xf) as usize];
+                    value >>= 4;
+                    if value == 0 {
+                        break
+                    }
+                }
+                start -= 2;
+                buffer[start..start + 2].copy_from_slice(b"0x");
+
+                // SAFETY: the buffer only contains ASCII characters
+                f.write_str(unsafe { core::str::from_utf8_unchecked(&buffer[start..]) })
+            }
+        }
+    )*}
+}
+
+impl_hex!(u8, u16, u32, u64, usize);
@@ -0,0 +1,141 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Architecture-independent interrupt handling.
+//!
+//! Interrupt lines are numbered by the board's interrupt controller. Drivers register a handler for
+//! the lines they use and then enable them. Whenever the CPU takes an IRQ, the architecture's
+//! exception code calls [handle_irq], which asks the controller which lines are pending and runs
+//! their handlers.
+//!
+//! Handlers run with interrupts masked. Data they share with the rest of the kernel should be
+//! protected with a [crate::sync::IrqSafeSpinMutex].
+
+use crate::driver;
+#[cfg(target_arch = "aarch64")]
+use crate::fdt::Cells;
+use crate::sync::{IrqSafeSpinMutex, IrqSafeSpinMutexMut};
+use crate::warn;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Number of an interrupt line, as understood by the board's interrupt controller.
+pub type IrqNumber = usize;
+
+/// Function that's called when an interrupt line fires, given the number of the line.
+pub type Handler = fn(IrqNumber);
+
+/// Interrupt lines numbered this high or higher can't have handlers.
+pub const MAX_IRQS: usize = 256;
+
+/// Object-safe interface to an interrupt controller.
+pub trait InterruptController {
+    /// Let the interrupt line through to the CPU.
+    ///
+    /// Returns [driver::Error::BadConfiguration] if the controller doesn't have that line.
+    fn enable(&mut self, irq: IrqNumber) -> Result<(), driver::Error>;
+
+    /// Stop the interrupt line from reaching the CPU.
+    fn disable(&mut self, irq: IrqNumber);
+
+    /// Find a pending interrupt and mark it as being handled.
+    ///
+    /// Returns `None` once there's nothing left to handle.
+    fn acknowledge(&mut self) -> Option<IrqNumber>;
+
+    /// Tell the controller that the handler for an acknowledged interrupt has finished.
+    fn end_of_interrupt(&mut self, irq: IrqNumber);
+
+    /// Turn an interrupt specifier from the device tree into the number of the line it refers to.
+    ///
+    /// Returns `None` if the specifier doesn't make sense for this controller.
+    #[cfg(target_arch = "aarch64")]
+    fn translate(&self, specifier: Cells<'_>) -> Option<IrqNumber>;
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static HANDLERS: IrqSafeSpinMutex<[Option<Handler>; MAX_IRQS]> =
+    IrqSafeSpinMutex::new_irq_safe([None; MAX_IRQS]);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// The board's interrupt controller, once its driver is initialized.
+fn controller() -> Option<IrqSafeSpinMutexMut<'static, dyn InterruptController>> {
+    crate::DRIVERS.try_get().and_then(crate::bsp::interrupt_controller)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Set the function that runs when `irq` fires.
+///
+/// Each line can only have one handler. Returns [driver::Error::Busy] if there already is one, or
+/// [driver::Error::BadConfiguration] if the line number is out of range.
+pub fn register(irq: IrqNumber, handler: Handler) -> Result<(), driver::Error> {
+    HANDLERS.with_lock(|handlers| match handlers.get_mut(irq) {
+        None => Err(driver::Error::BadConfiguration),
+        Some(Some(_)) => Err(driver::Error::Busy),
+        Some(slot) => {
+            *slot = Some(handler);
+            Ok(())
+        }
+    })
+}
+
+/// Remove the handler for `irq`, disabling the line first.
+pub fn unregister(irq: IrqNumber) {
+    disable(irq);
+    HANDLERS.with_lock(|handlers| {
+        if let Some(slot) = handlers.get_mut(irq) {
+            *slot = None;
+        }
+    })
+}
+
+/// Let `irq` through to the CPU. A handler should be registered first.
+///
+/// Fails with [driver::Error::Unavailable] until the interrupt controller is initialized.
+pub fn enable(irq: IrqNumber) -> Result<(), driver::Error> {
+    controller()
+        .ok_or(driver::Error::Unavailable)?
+        .with_lock(|controller| controller.enable(irq))
+}
+
+/// Stop `irq` from reaching the CPU.
+pub fn disable(irq: IrqNumber) {
+    if let Some(controller) = controller() {
+        controller.with_lock(|controller| controller.disable(irq))
+    }
+}
+
+/// Run the handlers for all pending interrupts.
+///
+/// Called by the architecture's exception code when an IRQ is taken, with interrupts masked.
+pub fn handle_irq() {
+    let controller = match controller() {
+        Some(controller) => controller,
+        None => return,
+    };
+
+    while let Some(irq) = controller.with_lock(|controller| controller.acknowledge()) {
+        let handler = HANDLERS.with_lock(|handlers| handlers.get(irq).copied().flatten());
+        match handler {
+            Some(handler) => handler(irq),
+            None => {
+                // nobody is going to clear whatever raised this, so it would keep firing forever
+                warn!("Disabling interrupt {}, which has no handler", irq);
+                controller.with_lock(|controller| controller.disable(irq));
+            }
+        }
+        controller.with_lock(|controller| controller.end_of_interrupt(irq));
+    }
+}
@@ -0,0 +1,508 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Kernel logging.
+//!
+//! All of the logging macros ([`trace!`](crate::trace), [`debug!`](crate::debug),
+//! [`info!`](crate::info), [`warn!`](crate::warn) and [`error!`](crate::error)) funnel into
+//! [`log!`](crate::log). A record is only formatted if its level passes both the compile-time
+//! maximum ([`STATIC_MAX_LEVEL`], selected with the `max_level_*` cargo features) and the runtime
+//! filters. Since the compile-time check compares two constants, disabled levels are eliminated
+//! from the binary entirely.
+//!
+//! The runtime filters consist of a global level plus a small table of per-module overrides. A
+//! module override applies to the module itself and everything nested inside of it, with the
+//! longest matching module path taking priority.
+//!
+//! Each record is prefixed with the uptime, the id of the core that logged it and the module path
+//! it came from:
+//!
+//! ```text
+//! [12.000345] INFO  core0 octopoda::driver::uart: some message
+//! ```
+//!
+//! Records aren't written to the console directly. They're stored in the [`ring`] first, and then
+//! everything the console hasn't printed yet is flushed to it. This means records logged while
+//! somebody else held the console lock are printed late rather than lost.
+//!
+//! Until the drivers are initialized, records are flushed to the BSP's early console instead,
+//! which is a polled writer that needs no initialization or locking. Once the drivers are up, the
+//! log is handed off to [`crate::stdout`]. If the two write to different devices, the real console
+//! starts by replaying the whole ring.
+//!
+//! If the BSP's consoles understand ANSI escape sequences, records are colored by level as they're
+//! printed, so errors and warnings stand out. The colors never make it into the ring.
+
+pub mod ring;
+
+use crate::driver::WriteError;
+use crate::sync::{IrqSafeSpinMutex, SpinMutex};
+use crate::time::DurationExt;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The severity of a log record. Lower levels are more severe.
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+
+/// The most verbose level that's allowed through a filter.
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LevelFilter {
+    Off = 0,
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+
+/// Reasons a module filter couldn't be installed.
+#[derive(Debug)]
+pub enum FilterError {
+    /// All of the module filter slots are in use.
+    TableFull,
+    /// The module path is longer than [`MAX_MODULE_PATH_LEN`].
+    PathTooLong,
+}
+
+/// Maximum number of per-module filters that can be active at once.
+pub const MAX_MODULE_FILTERS: usize = 8;
+
+/// Maximum length of a module path used in a per-module filter.
+pub const MAX_MODULE_PATH_LEN: usize = 48;
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "max_level_off")] {
+        pub const STATIC_MAX_LEVEL: LevelFilter = LevelFilter::Off;
+    } else if #[cfg(feature = "max_level_error")] {
+        pub const STATIC_MAX_LEVEL: LevelFilter = LevelFilter::Error;
+    } else if #[cfg(feature = "max_level_warn")] {
+        pub const STATIC_MAX_LEVEL: LevelFilter = LevelFilter::Warn;
+    } else if #[cfg(feature = "max_level_info")] {
+        pub const STATIC_MAX_LEVEL: LevelFilter = LevelFilter::Info;
+    } else if #[cfg(feature = "max_level_debug")] {
+        pub const STATIC_MAX_LEVEL: LevelFilter = LevelFilter::Debug;
+    } else {
+        /// The most verbose level compiled into the kernel.
+        pub const STATIC_MAX_LEVEL: LevelFilter = LevelFilter::Trace;
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+#[derive(Copy, Clone)]
+struct ModuleFilter {
+    path: [u8; MAX_MODULE_PATH_LEN],
+    path_len: u8,
+    level: LevelFilter,
+}
+
+struct Filters {
+    global: LevelFilter,
+    modules: [Option<ModuleFilter>; MAX_MODULE_FILTERS],
+}
+
+struct ConsoleState {
+    /// Sequence number of the next record the console needs to print
+    next_seq: u64,
+    /// Whether the log has moved from the early console to the real one
+    handed_off: bool,
+}
+
+/// SGR escape sequence that undoes [Level::color].
+const RESET_COLOR: &str = "\x1b[0m";
+
+/// Fixed-size buffer that a record is formatted into before being pushed to the ring.
+struct RecordBuffer {
+    buffer: [u8; ring::MAX_RECORD_LEN],
+    len: usize,
+    truncated: bool,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static FILTERS: IrqSafeSpinMutex<Filters> = IrqSafeSpinMutex::new_irq_safe(Filters {
+    global: STATIC_MAX_LEVEL,
+    modules: [None; MAX_MODULE_FILTERS],
+});
+
+static CONSOLE: SpinMutex<ConsoleState> = SpinMutex::new_spin(ConsoleState {
+    next_seq: 0,
+    handed_off: false,
+});
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl ModuleFilter {
+    fn path(&self) -> &str {
+        // SAFETY: the path was copied from a `&str` and is never modified afterwards
+        unsafe { core::str::from_utf8_unchecked(&self.path[..self.path_len as usize]) }
+    }
+
+    /// Check whether `module` is the filtered module or one nested inside of it.
+    fn matches(&self, module: &str) -> bool {
+        let path = self.path();
+        match module.strip_prefix(path) {
+            Some(rest) => rest.is_empty() || rest.starts_with("::"),
+            None => false,
+        }
+    }
+}
+
+impl Filters {
+    fn level_for(&self, module: &str) -> LevelFilter {
+        let mut best: Option<&ModuleFilter> = None;
+        for filter in self.modules.iter().flatten() {
+            if filter.matches(module) && best.map_or(true, |b| b.path_len < filter.path_len) {
+                best = Some(filter);
+            }
+        }
+        best.map_or(self.global, |filter| filter.level)
+    }
+}
+
+impl RecordBuffer {
+    const TRUNCATION_MARKER: &'static str = "...";
+
+    const fn new() -> Self {
+        Self {
+            buffer: [0; ring::MAX_RECORD_LEN],
+            len: 0,
+            truncated: false,
+        }
+    }
+
+    fn finish(&mut self) -> &str {
+        if self.truncated {
+            let marker = Self::TRUNCATION_MARKER.as_bytes();
+            self.buffer[self.len..self.len + marker.len()].copy_from_slice(marker);
+            self.len += marker.len();
+            self.truncated = false;
+        }
+        // SAFETY: only whole `&str`s, or prefixes of them cut on a char boundary, are copied in
+        unsafe { core::str::from_utf8_unchecked(&self.buffer[..self.len]) }
+    }
+}
+
+impl ufmt::uWrite for RecordBuffer {
+    type Error = WriteError;
+
+    fn write_str(&mut self, s: &str) -> Result<(), Self::Error> {
+        if self.truncated {
+            return Ok(())
+        }
+
+        // always leave room for the truncation marker
+        let capacity = ring::MAX_RECORD_LEN - Self::TRUNCATION_MARKER.len();
+        let mut len = s.len();
+        if self.len + len > capacity {
+            len = capacity - self.len;
+            while !s.is_char_boundary(len) {
+                len -= 1;
+            }
+            self.truncated = true;
+        }
+
+        self.buffer[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+/// Print every record the console hasn't seen yet, stopping early if the console reports an error.
+fn replay(console: &mut ConsoleState, w: &mut dyn ufmt::uWrite<Error = WriteError>) {
+    use ufmt::uwriteln;
+
+    let mut buffer = [0; ring::MAX_RECORD_LEN];
+    while let Some(record) = ring::read(console.next_seq, &mut buffer) {
+        if record.seq > console.next_seq {
+            if uwriteln!(w, "[... {} records lost ...]", record.seq - console.next_seq).is_err() {
+                return
+            }
+            console.next_seq = record.seq;
+        }
+
+        let color = record.level.color().filter(|_| crate::bsp::CONSOLE_SUPPORTS_ANSI);
+        let written = match color {
+            Some(color) => w.write_str(color)
+                .and_then(|()| w.write_str(record.text))
+                .and_then(|()| w.write_str(RESET_COLOR)),
+            None => w.write_str(record.text),
+        };
+        if written.and_then(|()| w.write_str("\n")).is_err() {
+            return
+        }
+        console.next_seq = record.seq + 1;
+    }
+}
+
+fn write_header(
+    w: &mut dyn ufmt::uWrite<Error = WriteError>,
+    level: Level,
+    module: &str,
+) -> Result<(), WriteError> {
+    use ufmt::uwrite;
+
+    let uptime = crate::time::uptime();
+    let core = crate::arch::cpu::core_id();
+    uwrite!(w, "[{}] {} core{} {}: ", uptime.display_timestamp(), level, core, module)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Level {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+
+    /// The SGR escape sequence that records of this level are printed in, or `None` to use the
+    /// console's default colors.
+    pub const fn color(self) -> Option<&'static str> {
+        match self {
+            Level::Error => Some("\x1b[1;31m"),
+            Level::Warn => Some("\x1b[1;33m"),
+            Level::Info => None,
+            Level::Debug => Some("\x1b[36m"),
+            Level::Trace => Some("\x1b[90m"),
+        }
+    }
+}
+
+impl ufmt::uDisplay for Level {
+    fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: ufmt::uWrite + ?Sized
+    {
+        // pad to the longest level name so that messages line up
+        f.write_str(self.as_str())?;
+        match self {
+            Level::Warn | Level::Info => f.write_str(" "),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl LevelFilter {
+    /// Check whether a record of the given level passes this filter.
+    #[inline(always)]
+    pub const fn allows(self, level: Level) -> bool {
+        level as u8 <= self as u8
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        let filter = match name {
+            "off" => LevelFilter::Off,
+            "error" => LevelFilter::Error,
+            "warn" => LevelFilter::Warn,
+            "info" => LevelFilter::Info,
+            "debug" => LevelFilter::Debug,
+            "trace" => LevelFilter::Trace,
+            _ => return None,
+        };
+        Some(filter)
+    }
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            LevelFilter::Off => "off",
+            LevelFilter::Error => "error",
+            LevelFilter::Warn => "warn",
+            LevelFilter::Info => "info",
+            LevelFilter::Debug => "debug",
+            LevelFilter::Trace => "trace",
+        }
+    }
+}
+
+impl ufmt::uDisplay for LevelFilter {
+    fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: ufmt::uWrite + ?Sized
+    {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The global runtime log level.
+pub fn max_level() -> LevelFilter {
+    FILTERS.with_lock(|filters| filters.global)
+}
+
+/// Set the global runtime log level.
+///
+/// Levels more verbose than [`STATIC_MAX_LEVEL`] are still discarded.
+pub fn set_max_level(level: LevelFilter) {
+    FILTERS.with_lock(|filters| filters.global = level)
+}
+
+/// Override the log level for `module` and all of the modules nested inside it.
+///
+/// `module` is a path as returned by `module_path!()`, e.g. `octopoda::driver`. Setting the level
+/// for a module that already has an override replaces the old level.
+pub fn set_module_level(module: &str, level: LevelFilter) -> Result<(), FilterError> {
+    if module.len() > MAX_MODULE_PATH_LEN {
+        return Err(FilterError::PathTooLong)
+    }
+
+    FILTERS.with_lock(|filters| {
+        let existing = filters.modules.iter_mut()
+            .flatten()
+            .find(|filter| filter.path() == module);
+        if let Some(filter) = existing {
+            filter.level = level;
+            return Ok(())
+        }
+
+        let slot = filters.modules.iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(FilterError::TableFull)?;
+        let mut path = [0; MAX_MODULE_PATH_LEN];
+        path[..module.len()].copy_from_slice(module.as_bytes());
+        *slot = Some(ModuleFilter {
+            path,
+            path_len: module.len() as u8,
+            level,
+        });
+        Ok(())
+    })
+}
+
+/// Remove the override for `module`, if there is one.
+pub fn clear_module_level(module: &str) {
+    FILTERS.with_lock(|filters| {
+        for slot in filters.modules.iter_mut() {
+            if matches!(slot, Some(filter) if filter.path() == module) {
+                *slot = None;
+            }
+        }
+    })
+}
+
+/// Call `f` with every active per-module override.
+pub fn for_each_module_level<F: FnMut(&str, LevelFilter)>(mut f: F) {
+    FILTERS.with_lock(|filters| {
+        for filter in filters.modules.iter().flatten() {
+            f(filter.path(), filter.level)
+        }
+    })
+}
+
+/// Check whether a record from `module` at `level` would be logged.
+pub fn enabled(level: Level, module: &str) -> bool {
+    STATIC_MAX_LEVEL.allows(level)
+        && FILTERS.with_lock(|filters| filters.level_for(module)).allows(level)
+}
+
+/// Format a record into the ring and flush it to the console. Use the logging macros instead of
+/// calling this directly.
+#[doc(hidden)]
+pub fn write_record<F>(level: Level, module: &str, message: F)
+where
+    F: FnOnce(&mut dyn ufmt::uWrite<Error = WriteError>) -> Result<(), WriteError>,
+{
+    let mut record = RecordBuffer::new();
+    let _ = write_header(&mut record, level, module).and_then(|()| message(&mut record));
+    ring::push(level, record.finish());
+    flush();
+}
+
+/// Print every record the console hasn't seen yet.
+///
+/// If the console is busy, e.g. because we were called from inside a write to it, the records stay
+/// pending until the next flush.
+pub fn flush() {
+    // the early console stays in use if the real one failed to come up
+    let stdout = crate::DRIVERS.try_get().and_then(crate::bsp::stdout);
+    CONSOLE.try_with_lock(|console| match stdout {
+        Some(stdout) => {
+            if !console.handed_off {
+                console.handed_off = true;
+                if !crate::bsp::EARLY_CONSOLE_SHARES_STDOUT {
+                    console.next_seq = 0;
+                }
+            }
+            stdout.try_with_lock(|w| replay(console, w));
+        }
+        None => replay(console, &mut crate::bsp::early_console()),
+    });
+}
+
+//--------------------------------------------------------------------------------------------------
+// Macros
+//--------------------------------------------------------------------------------------------------
+
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $formatter:literal$(, $($args:expr),*)?) => {{
+        let level: $crate::log::Level = $level;
+        // the first check is against two constants, so disabled levels compile away entirely
+        if $crate::log::STATIC_MAX_LEVEL.allows(level)
+            && $crate::log::enabled(level, module_path!())
+        {
+            $crate::log::write_record(level, module_path!(), |w| {
+                use ufmt::uwrite;
+                uwrite!(w, $formatter $(, $($args),*)?)
+            });
+        }
+    }}
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($formatter:literal$(, $($args:expr),*)?) => {
+        $crate::log!($crate::log::Level::Trace, $formatter $(, $($args),*)?)
+    }
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($formatter:literal$(, $($args:expr),*)?) => {
+        $crate::log!($crate::log::Level::Debug, $formatter $(, $($args),*)?)
+    }
+}
+
+#[macro_export]
+macro_rules! info {
+    ($formatter:literal$(, $($args:expr),*)?) => {
+        $crate::log!($crate::log::Level::Info, $formatter $(, $($args),*)?)
+    }
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($formatter:literal$(, $($args:expr),*)?) => {
+        $crate::log!($crate::log::Level::Warn, $formatter $(, $($args),*)?)
+    }
+}
+
+#[macro_export]
+macro_rules! error {
+    ($formatter:literal$(, $($args:expr),*)?) => {
+        $crate::log!($crate::log::Level::Error, $formatter $(, $($args),*)?)
+    }
+}
@@ -0,0 +1,232 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Fixed-size, in-memory history of log records (the kernel's `dmesg`).
+//!
+//! Records are stored back-to-back in a byte buffer, each preceded by a small header holding its
+//! length and level. A record is never split across the end of the buffer: if it doesn't fit in
+//! the remaining space, a wrap marker is left behind and the record is written at the start
+//! instead. Once the buffer is full, the oldest records are discarded to make room.
+//!
+//! Every record is given a sequence number, which lets readers notice when records they haven't
+//! seen yet were discarded.
+
+use super::Level;
+use crate::sync::IrqSafeSpinMutex;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Total size of the log history, including record headers.
+pub const BUFFER_SIZE: usize = 16 * 1024;
+
+/// Longest record that can be stored. Longer records are truncated before they're pushed.
+pub const MAX_RECORD_LEN: usize = 256;
+
+/// A single log record, as read back from the ring.
+pub struct Record<'a> {
+    /// Sequence number of this record. These are handed out consecutively, starting from 0.
+    pub seq: u64,
+    pub level: Level,
+    /// The formatted record, without a trailing newline.
+    pub text: &'a str,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Length (2 bytes), level and one byte of padding.
+const HEADER_LEN: usize = 4;
+
+/// Length value that marks the rest of the buffer as unused.
+const WRAP_MARKER: u16 = u16::MAX;
+
+struct LogRing {
+    buffer: [u8; BUFFER_SIZE],
+    /// Offset of the oldest record
+    head: usize,
+    /// Offset that the next record will be written to
+    tail: usize,
+    /// Sequence number of the oldest record
+    first_seq: u64,
+    /// Sequence number that the next record will be given
+    next_seq: u64,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static RING: IrqSafeSpinMutex<LogRing> = IrqSafeSpinMutex::new_irq_safe(LogRing::new());
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl LogRing {
+    const fn new() -> Self {
+        Self {
+            buffer: [0; BUFFER_SIZE],
+            head: 0,
+            tail: 0,
+            first_seq: 0,
+            next_seq: 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.first_seq == self.next_seq
+    }
+
+    fn len_at(&self, offset: usize) -> u16 {
+        u16::from_le_bytes([self.buffer[offset], self.buffer[offset + 1]])
+    }
+
+    /// Follow a wrap marker, if there is one at `offset`.
+    ///
+    /// A wrap is implied if there isn't enough room left in the buffer for a header.
+    fn normalize(&self, offset: usize) -> usize {
+        if offset + HEADER_LEN > BUFFER_SIZE || self.len_at(offset) == WRAP_MARKER {
+            0
+        } else {
+            offset
+        }
+    }
+
+    /// Offset of the record following the one at `offset`.
+    fn next_offset(&self, offset: usize) -> usize {
+        self.normalize(offset + HEADER_LEN + self.len_at(offset) as usize)
+    }
+
+    fn drop_oldest(&mut self) {
+        self.head = self.next_offset(self.head);
+        self.first_seq += 1;
+        if self.is_empty() {
+            self.head = self.tail;
+        }
+    }
+
+    fn push(&mut self, level: Level, text: &[u8]) -> u64 {
+        let len = text.len().min(MAX_RECORD_LEN);
+        let needed = HEADER_LEN + len;
+
+        // make room for the record
+        loop {
+            if self.is_empty() {
+                self.head = 0;
+                self.tail = 0;
+                break
+            }
+
+            if self.tail + needed > BUFFER_SIZE {
+                if self.head >= self.tail {
+                    // the oldest records sit between the tail and the end of the buffer, and the
+                    // newest ones at the start. Those at the end have to go before we can wrap.
+                    self.drop_oldest();
+                } else {
+                    if self.tail + HEADER_LEN <= BUFFER_SIZE {
+                        self.buffer[self.tail..self.tail + 2]
+                            .copy_from_slice(&WRAP_MARKER.to_le_bytes());
+                    }
+                    self.tail = 0;
+                }
+                continue
+            }
+
+            if self.head >= self.tail && self.head < self.tail + needed {
+                // the record would overwrite the oldest one
+                self.drop_oldest();
+                continue
+            }
+            break
+        }
+
+        let offset = self.tail;
+        self.buffer[offset..offset + 2].copy_from_slice(&(len as u16).to_le_bytes());
+        self.buffer[offset + 2] = level as u8;
+        self.buffer[offset + 3] = 0;
+        self.buffer[offset + HEADER_LEN..offset + needed].copy_from_slice(&text[..len]);
+        self.tail = offset + needed;
+
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        seq
+    }
+
+    /// Copy out the oldest record with a sequence number of at least `seq`.
+    fn read<'b>(&self, seq: u64, out: &'b mut [u8; MAX_RECORD_LEN]) -> Option<Record<'b>> {
+        let seq = seq.max(self.first_seq);
+        if seq >= self.next_seq {
+            return None
+        }
+
+        let mut offset = self.head;
+        for _ in self.first_seq..seq {
+            offset = self.next_offset(offset);
+        }
+
+        let len = self.len_at(offset) as usize;
+        let level = level_from_u8(self.buffer[offset + 2]);
+        let start = offset + HEADER_LEN;
+        out[..len].copy_from_slice(&self.buffer[start..start + len]);
+
+        // SAFETY: records are only ever pushed from `&str`s truncated on a char boundary
+        let text = unsafe { core::str::from_utf8_unchecked(&out[..len]) };
+        Some(Record { seq, level, text })
+    }
+}
+
+fn level_from_u8(level: u8) -> Level {
+    match level {
+        1 => Level::Error,
+        2 => Level::Warn,
+        3 => Level::Info,
+        4 => Level::Debug,
+        _ => Level::Trace,
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Append a record to the ring, discarding the oldest records if there isn't enough room.
+///
+/// `text` must be at most [`MAX_RECORD_LEN`] bytes. Returns the record's sequence number.
+pub fn push(level: Level, text: &str) -> u64 {
+    debug_assert!(text.len() <= MAX_RECORD_LEN);
+    RING.with_lock(|ring| ring.push(level, text.as_bytes()))
+}
+
+/// Copy out the oldest record that's still stored and has a sequence number of at least `seq`.
+///
+/// If the returned record's sequence number is larger than `seq`, the records in between were
+/// discarded before they could be read. Returns `None` once there are no newer records.
+pub fn read(seq: u64, out: &mut [u8; MAX_RECORD_LEN]) -> Option<Record<'_>> {
+    RING.with_lock(move |ring| ring.read(seq, out))
+}
+
+/// The sequence number that the next record will be given.
+pub fn next_seq() -> u64 {
+    RING.with_lock(|ring| ring.next_seq)
+}
+
+/// Call `f` with every record currently in the ring, from oldest to newest.
+///
+/// The ring isn't locked while `f` runs, so it's fine to log from inside of it. Records logged
+/// after the call started aren't visited.
+pub fn for_each<F: FnMut(Record<'_>)>(mut f: F) {
+    let end = next_seq();
+    let mut seq = 0;
+    let mut buffer = [0; MAX_RECORD_LEN];
+    while let Some(record) = read(seq, &mut buffer) {
+        if record.seq >= end {
+            break
+        }
+        seq = record.seq + 1;
+        f(record);
+    }
+}
@@ -11,7 +11,7 @@
 #![no_std]
 #![no_main]
 
-#![feature(asm, naked_functions, maybe_uninit_extra)]
+#![feature(asm, global_asm, naked_functions, maybe_uninit_extra, abi_x86_interrupt)]
 
 #[cfg(target_arch = "x86_64")]
 extern crate bootloader;
@@ -20,42 +20,78 @@ mod arch;
 mod bsp;
 mod defer;
 mod driver;
+#[cfg(target_arch = "aarch64")]
+mod fdt;
+mod fmt;
+mod interrupt;
 mod log;
 mod memory;
 mod panic_wait;
+mod power;
+mod rand;
 mod runtime_init;
+mod shell;
+mod stdin;
 mod sync;
 mod time;
+mod watchdog;
 
 use core::time::Duration;
 use time::{DurationExt, SimpleTimer};
 use ufmt::uwriteln;
-use bsp::DriverManager;
+use driver::registry::Registry;
 use driver::WriteError;
 
-pub static DRIVERS: sync::Lazy<DriverManager> = sync::Lazy::new(|| unsafe { DriverManager::new() });
+pub static DRIVERS: sync::Lazy<Registry> = sync::Lazy::new(|| {
+    let mut drivers = Registry::new();
+    // SAFETY: the Lazy only runs this once
+    unsafe { bsp::register_drivers(&mut drivers) };
+    drivers.init_all();
+    drivers
+});
 
 /// The "main" entrypoint of the kernel. Called after stopping other cores
 /// and initializing the bss section.
 fn main() -> ! {
-    stdout().with_lock(|w| {
-        let _ = uwriteln!(w, "Hello, World!");
-        for driver in DRIVERS.get().iter() {
-            let _ = uwriteln!(w, "Loaded driver '{}'", driver.compatible());
-        }
-    });
+    // bring up the drivers and hand the log over from the early console
+    DRIVERS.force();
+    log::flush();
+    time::select(DRIVERS.get());
+
+    // SAFETY: the exception vectors and the interrupt controller are ready now
+    unsafe { arch::irq::enable() };
+
+    if let Some(stdout) = stdout() {
+        stdout.with_lock(|w| {
+            let _ = uwriteln!(w, "Hello, World!");
+            for driver in DRIVERS.get().iter() {
+                let _ = uwriteln!(w, "Loaded driver '{}'", driver.compatible());
+            }
+        });
+    }
 
     // let mut count = 0;
     // loop {
     //     trace!("Hello {}", count);
     //     count += 1;
     // }
+
+    // the shell only returns if there's nothing to read commands from
+    let error = shell::run();
+    warn!("Not starting the shell: {}", error);
     loop {
+        watchdog::heartbeat();
         time::arch_timer().spin_for(Duration::from_secs(5));
-        trace!("Current uptime: {}", time::arch_timer().uptime().display_human());
+        trace!("Current uptime: {}", time::uptime().display_human());
     }
 }
 
-pub fn stdout() -> sync::SpinMutexMut<'static, dyn ufmt::uWrite<Error=WriteError>> {
-    DRIVERS.get().stdout()
+/// The console, or `None` if its driver failed to initialize.
+pub fn stdout() -> Option<sync::SpinMutexMut<'static, dyn ufmt::uWrite<Error=WriteError>>> {
+    bsp::stdout(DRIVERS.get())
+}
+
+/// The kernel's input, which reads from the BSP's input devices through a line discipline.
+pub fn stdin() -> stdin::Stdin {
+    stdin::Stdin::new()
 }
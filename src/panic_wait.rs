@@ -2,11 +2,34 @@
 //
 // Copyright (c) 2018-2020 Andre Richter <andre.o.richter@gmail.com>
 
-//! A panic handler that infinitely waits.
+//! A panic handler that reports the panic and then does whatever the
+//! [PanicAction](crate::power::PanicAction) says.
 
+use crate::power::{self, PanicAction};
+use crate::time::{DurationExt, SimpleTimer};
 use core::panic::PanicInfo;
 
 #[panic_handler]
-fn panic(_info: &PanicInfo) -> ! {
-    crate::arch::asm::wait_forever()
+fn panic(info: &PanicInfo) -> ! {
+    use ufmt::uwriteln;
+
+    // whatever panicked may be holding the console lock, or be the console itself, so report
+    // through the lock-free early console
+    let mut console = crate::bsp::early_console();
+    let _ = match info.location() {
+        Some(location) => {
+            uwriteln!(console, "\nKernel panic at {}:{}", location.file(), location.line())
+        }
+        None => uwriteln!(console, "\nKernel panic"),
+    };
+
+    match power::panic_action() {
+        PanicAction::Halt => crate::arch::asm::wait_forever(),
+        PanicAction::PowerOff => power::poweroff(),
+        PanicAction::Reboot(delay) => {
+            let _ = uwriteln!(console, "Rebooting in {}", delay.display_human());
+            crate::time::arch_timer().spin_for(delay);
+            power::reboot()
+        }
+    }
 }
@@ -0,0 +1,80 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Restarting and turning off the machine.
+//!
+//! [reboot] and [poweroff] shut the drivers down before handing over to the BSP, which knows how
+//! the board does it. If the BSP's way fails, the core is stopped instead, so neither returns.
+//!
+//! What happens after a panic is up to the [PanicAction], which starts out as the BSP's
+//! `config::PANIC_ACTION` and can be changed with [set_panic_action].
+
+use crate::sync::SpinMutex;
+use core::time::Duration;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// What the panic handler does once it has reported the panic.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PanicAction {
+    /// Stop the core, leaving the machine as it is for somebody to look at
+    Halt,
+    /// Turn the machine off
+    PowerOff,
+    /// Restart the machine once the given time has passed, which leaves time to read the report
+    Reboot(Duration),
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static PANIC_ACTION: SpinMutex<PanicAction> =
+    SpinMutex::new_spin(crate::bsp::config::PANIC_ACTION);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Quiesce the devices and make sure nothing interrupts what comes next.
+fn prepare() {
+    crate::arch::irq::disable();
+    // the drivers can only be running if they were initialized, and shouldn't be brought up now
+    if let Some(drivers) = crate::DRIVERS.try_get() {
+        drivers.shutdown_all();
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Restart the machine.
+pub fn reboot() -> ! {
+    prepare();
+    crate::bsp::reboot();
+    crate::arch::asm::wait_forever()
+}
+
+/// Turn the machine off, or halt it if it can't be turned off.
+pub fn poweroff() -> ! {
+    prepare();
+    crate::bsp::power_off();
+    crate::arch::asm::wait_forever()
+}
+
+/// What the panic handler will do.
+///
+/// This doesn't wait for the lock, since the panic may have happened while it was held. The
+/// BSP's default is returned in that case.
+pub fn panic_action() -> PanicAction {
+    PANIC_ACTION.try_with_lock(|action| *action).unwrap_or(crate::bsp::config::PANIC_ACTION)
+}
+
+/// Change what the panic handler does.
+pub fn set_panic_action(action: PanicAction) {
+    PANIC_ACTION.with_lock(|current| *current = action)
+}
@@ -0,0 +1,78 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! The ChaCha20 block function, as described by Bernstein in "ChaCha, a variant of Salsa20".
+//!
+//! Only the keystream is needed for generating random numbers, so there's no encryption here. The
+//! counter is 64 bits wide and the nonce is always zero, since every key is only used once.
+
+use core::convert::TryInto;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Length of a key in bytes.
+pub const KEY_LEN: usize = 32;
+
+/// Length of a block of keystream in bytes.
+pub const BLOCK_LEN: usize = 64;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// "expand 32-byte k"
+const CONSTANTS: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+
+/// Each iteration does a column round and a diagonal round, for 20 rounds in total.
+const DOUBLE_ROUNDS: usize = 10;
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+#[inline(always)]
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Write block number `counter` of the keystream for `key` to `out`.
+pub fn block(key: &[u8; KEY_LEN], counter: u64, out: &mut [u8; BLOCK_LEN]) {
+    let mut input = [0; 16];
+    input[..4].copy_from_slice(&CONSTANTS);
+    for (word, bytes) in input[4..12].iter_mut().zip(key.chunks_exact(4)) {
+        *word = u32::from_le_bytes(bytes.try_into().unwrap());
+    }
+    input[12] = counter as u32;
+    input[13] = (counter >> 32) as u32;
+    // the nonce in words 14 and 15 stays zero
+
+    let mut state = input;
+    for _ in 0..DOUBLE_ROUNDS {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+
+    for ((bytes, word), input) in out.chunks_exact_mut(4).zip(state.iter()).zip(input.iter()) {
+        bytes.copy_from_slice(&word.wrapping_add(*input).to_le_bytes());
+    }
+}
@@ -0,0 +1,207 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Random numbers for the rest of the kernel, e.g. for stack canaries, address randomization and
+//! seeding hash tables.
+//!
+//! The numbers come from a ChaCha20 keystream. The generator seeds itself the first time it's used,
+//! from the board's hardware random number generator if there is one ([crate::bsp::entropy_source])
+//! or from timer jitter otherwise. It reseeds from the hardware every [RESEED_INTERVAL] bytes, so a
+//! generator that had to start out with jitter, e.g. because it was used before the drivers were
+//! up, switches over once it can.
+//!
+//! Every time the generator's buffer is refilled, the first part of the new keystream becomes the
+//! next key, and whatever was handed out is wiped from the buffer. Finding the state later doesn't
+//! tell anyone which numbers came before.
+
+mod chacha20;
+
+use crate::driver;
+use crate::sync::SpinMutex;
+use crate::time::SimpleTimer;
+use crate::{info, warn};
+use chacha20::{BLOCK_LEN, KEY_LEN};
+use core::convert::TryInto;
+use core::ptr;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Object-safe interface to a hardware random number generator.
+pub trait EntropySource {
+    /// Fill `bytes` with random bits straight from the hardware.
+    fn fill(&mut self, bytes: &mut [u8]) -> Result<(), driver::Error>;
+}
+
+/// How many bytes the generator hands out before it reseeds from the hardware.
+pub const RESEED_INTERVAL: usize = 1 << 20;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// How many blocks of keystream are made at once. The first [KEY_LEN] bytes are the next key.
+const BUFFER_BLOCKS: usize = 4;
+const BUFFER_LEN: usize = BUFFER_BLOCKS * BLOCK_LEN;
+
+/// How many timer readings go into a seed made from jitter. Only the low bits of each reading are
+/// unpredictable, so this takes quite a few.
+const JITTER_SAMPLES: usize = 4096;
+
+/// Where the generator's seed came from.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Source {
+    Hardware,
+    Jitter,
+}
+
+struct Generator {
+    key: [u8; KEY_LEN],
+    buffer: [u8; BUFFER_LEN],
+    /// Where the bytes that haven't been handed out start in the buffer
+    position: usize,
+    /// How many bytes were handed out since the last seeding
+    since_reseed: usize,
+    /// `None` until the generator is seeded
+    source: Option<Source>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static GENERATOR: SpinMutex<Generator> = SpinMutex::new_spin(Generator::new());
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Read a seed from the board's hardware random number generator.
+fn hardware_seed(seed: &mut [u8; KEY_LEN]) -> Result<(), driver::Error> {
+    let drivers = crate::DRIVERS.try_get().ok_or(driver::Error::NotPresent)?;
+    let source = crate::bsp::entropy_source(drivers).ok_or(driver::Error::NotPresent)?;
+    source.with_lock(|source| source.fill(seed))
+}
+
+/// Make a seed out of how long the same work takes each time, which varies with the state of the
+/// caches and pipelines, among other things.
+///
+/// This is weak compared to a hardware generator, especially where the timer is slow, but it's
+/// better than nothing.
+fn jitter_seed(seed: &mut [u8; KEY_LEN]) {
+    let timer = crate::time::arch_timer();
+    let mut scratch = [0; BLOCK_LEN];
+    let mut previous = timer.uptime();
+    for sample in 0..JITTER_SAMPLES {
+        // volatile, so the work isn't optimized out
+        for i in 0..scratch.len() {
+            let index = (i * 7 + sample) % scratch.len();
+            // SAFETY: the pointers come from references into the scratch buffer
+            unsafe {
+                let value = ptr::read_volatile(&scratch[index]);
+                ptr::write_volatile(&mut scratch[index], value.wrapping_add(i as u8));
+            }
+        }
+
+        let now = timer.uptime();
+        let nanos = now.saturating_sub(previous).as_nanos() as u32;
+        previous = now;
+        let byte = &mut seed[sample % KEY_LEN];
+        *byte = byte.rotate_left(3) ^ (nanos ^ nanos >> 8) as u8;
+    }
+}
+
+impl Generator {
+    const fn new() -> Self {
+        Self {
+            key: [0; KEY_LEN],
+            buffer: [0; BUFFER_LEN],
+            position: BUFFER_LEN,
+            since_reseed: 0,
+            source: None,
+        }
+    }
+
+    /// Mix `seed` into the key and throw away what's left of the old keystream.
+    fn reseed(&mut self, seed: &[u8; KEY_LEN], source: Source) {
+        for (key, seed) in self.key.iter_mut().zip(seed.iter()) {
+            *key ^= seed;
+        }
+        self.position = BUFFER_LEN;
+        self.since_reseed = 0;
+        self.source = Some(source);
+    }
+
+    /// Seed the generator if it wasn't yet, or reseed it if it's time to.
+    fn ensure_seeded(&mut self) {
+        let mut seed = [0; KEY_LEN];
+        match self.source {
+            None => match hardware_seed(&mut seed) {
+                Ok(()) => {
+                    info!("Seeded the random number generator from the hardware");
+                    self.reseed(&seed, Source::Hardware);
+                }
+                Err(e) => {
+                    warn!("Seeding the random number generator from timer jitter: {}", e);
+                    jitter_seed(&mut seed);
+                    self.reseed(&seed, Source::Jitter);
+                }
+            },
+            Some(source) if self.since_reseed >= RESEED_INTERVAL => {
+                match hardware_seed(&mut seed) {
+                    Ok(()) => {
+                        if source == Source::Jitter {
+                            info!("Reseeded the random number generator from the hardware");
+                        }
+                        self.reseed(&seed, Source::Hardware);
+                    }
+                    // keep going with the old seed, there's nothing better to be had
+                    Err(_) => self.since_reseed = 0,
+                }
+            }
+            Some(_) => {}
+        }
+    }
+
+    /// Make the next stretch of keystream, and replace the key with its start.
+    fn refill(&mut self) {
+        for (counter, block) in self.buffer.chunks_exact_mut(BLOCK_LEN).enumerate() {
+            chacha20::block(&self.key, counter as u64, block.try_into().unwrap());
+        }
+        self.key.copy_from_slice(&self.buffer[..KEY_LEN]);
+        self.buffer[..KEY_LEN].fill(0);
+        self.position = KEY_LEN;
+    }
+
+    fn fill(&mut self, bytes: &mut [u8]) {
+        self.ensure_seeded();
+        for chunk in bytes.chunks_mut(BUFFER_LEN - KEY_LEN) {
+            if self.position + chunk.len() > BUFFER_LEN {
+                self.refill();
+            }
+            let end = self.position + chunk.len();
+            chunk.copy_from_slice(&self.buffer[self.position..end]);
+            self.buffer[self.position..end].fill(0);
+            self.position = end;
+        }
+        self.since_reseed = self.since_reseed.saturating_add(bytes.len());
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Fill `bytes` with random bytes.
+pub fn fill_bytes(bytes: &mut [u8]) {
+    GENERATOR.with_lock(|generator| generator.fill(bytes))
+}
+
+/// A random `u64`.
+pub fn next_u64() -> u64 {
+    let mut bytes = [0; 8];
+    fill_bytes(&mut bytes);
+    u64::from_ne_bytes(bytes)
+}
@@ -7,22 +7,45 @@
 //! zeroes out the bss section and stops all but the first core.
 //!
 //! On arm, we boot into the [`_start`] function. On x86, we rely on the `bootloader` crate.
+//!
+//! On arm, whoever loaded the kernel may also pass the address of a flattened device tree in x0.
+//! That address is kept around and can be found with [`device_tree`]. Cores that are started later,
+//! through PSCI, enter at [`_start_secondary`] instead.
+//!
+//! On x86, the bootloader maps all of physical memory and passes where in its boot information.
+//! That offset is kept around and can be found with [`physical_memory_offset`].
 
 cfg_if::cfg_if! {
     if #[cfg(target_arch = "aarch64")] {
         use core::cell::UnsafeCell;
         use core::ops::RangeInclusive;
-        use crate::arch::asm;
+        use core::sync::atomic::{AtomicUsize, Ordering};
+        use crate::arch::{asm, exception};
         use crate::memory;
+        use cortex_a::registers::*;
+        use tock_registers::interfaces::{Readable, Writeable};
+
+        /// Address of the device tree that was passed in at boot, or 0 if there wasn't one.
+        static DEVICE_TREE: AtomicUsize = AtomicUsize::new(0);
+
+        /// How many of the other cores have reached [`secondary_init`].
+        static SECONDARY_CORES_ONLINE: AtomicUsize = AtomicUsize::new(0);
+
+        /// Where `ID_AA64PFR0_EL1` says whether the core has the GICv3 system registers.
+        const ID_AA64PFR0_GIC_SHIFT: u64 = 24;
+        /// `ICC_SRE_EL2.Enable`, which lets EL1 use `ICC_SRE_EL1`
+        const ICC_SRE_EL2_ENABLE: u64 = 1 << 3;
+        /// `ICC_SRE_EL2.SRE`, which switches EL2 over to the system register interface
+        const ICC_SRE_EL2_SRE: u64 = 1 << 0;
 
         #[naked]
         #[no_mangle]
         pub unsafe extern fn _start() -> ! {
             asm!(
-                // set the stack pointer
-                "adrp x0, __boot_core_stack_end_exclusive",
-                "add x0, x0, #:lo12:__boot_core_stack_end_exclusive",
-                "mov sp, x0",
+                // set the stack pointer, leaving x0 (the device tree) alone
+                "adrp x1, __boot_core_stack_end_exclusive",
+                "add x1, x1, #:lo12:__boot_core_stack_end_exclusive",
+                "mov sp, x1",
 
                 // call into rust code
                 "b _start_rust",
@@ -31,30 +54,120 @@ cfg_if::cfg_if! {
         }
 
         #[no_mangle]
-        pub unsafe extern "C" fn _start_rust() -> ! {
+        pub unsafe extern "C" fn _start_rust(device_tree: usize) -> ! {
             // only continue with running the kernel if we're core 0,
             // otherwise wait_forever (i.e. stop the core)
-            if crate::arch::cpu::core_id() == 0 {
+            if crate::arch::cpu::core_id() != 0 {
+                asm::wait_forever()
+            }
+
+            // the firmware starts us in EL2, but the kernel is meant to run in EL1
+            if CurrentEL.matches_all(CurrentEL::EL::EL2) {
+                prepare_el2_to_el1_transition();
+                // "return" into runtime_init at EL1, which takes the device tree as its argument
+                asm!("eret", in("x0") device_tree, options(noreturn))
+            }
+
+            // already in EL1, on the stack that _start set up
+            runtime_init(device_tree)
+        }
+
+        /// Entry point of the other cores when they're started through PSCI, which passes the top
+        /// of the core's stack as the context in x0. PSCI starts them in the kernel's exception
+        /// level, so unlike the boot core, they don't have to leave EL2.
+        #[naked]
+        #[no_mangle]
+        pub unsafe extern fn _start_secondary() -> ! {
+            asm!(
+                "mov sp, x0",
+                "b secondary_init",
+                options(noreturn)
+            )
+        }
+
+        /// Set up a core that was started after the boot core, and park it, since there's nothing
+        /// for it to do yet.
+        #[no_mangle]
+        pub unsafe extern "C" fn secondary_init() -> ! {
+            exception::init();
+            SECONDARY_CORES_ONLINE.fetch_add(1, Ordering::Release);
+            asm::wait_forever()
+        }
+
+        /// Set up the system registers so that an `eret` from EL2 continues in [`runtime_init`]
+        /// at EL1, using the boot core's stack.
+        #[inline(always)]
+        unsafe fn prepare_el2_to_el1_transition() {
+            // let EL1 access the physical timer and counter
+            CNTHCTL_EL2.write(CNTHCTL_EL2::EL1PCEN::SET + CNTHCTL_EL2::EL1PCTEN::SET);
+            CNTVOFF_EL2.set(0);
+
+            // Let EL1 use the system registers of a GICv3 CPU interface, if the core has one. They
+            // stay out of reach of EL1 unless EL2 uses them too.
+            let pfr0: u64;
+            asm!(
+                "mrs {}, id_aa64pfr0_el1",
+                out(reg) pfr0,
+                options(nomem, nostack, preserves_flags),
+            );
+            if pfr0 >> ID_AA64PFR0_GIC_SHIFT & 0xf != 0 {
                 asm!(
-                    "ldr x1, =_start",
-                    "mov sp, x1",
+                    "msr icc_sre_el2, {}",
+                    "isb",
+                    in(reg) ICC_SRE_EL2_ENABLE | ICC_SRE_EL2_SRE,
+                    options(nostack, preserves_flags),
                 );
-                runtime_init()
-            } else {
-                asm::wait_forever()
             }
+
+            // EL1 runs in aarch64 mode
+            HCR_EL2.write(HCR_EL2::RW::EL1IsAarch64);
+
+            // start EL1 with all interrupts masked and using its own stack pointer
+            SPSR_EL2.write(
+                SPSR_EL2::D::Masked
+                    + SPSR_EL2::A::Masked
+                    + SPSR_EL2::I::Masked
+                    + SPSR_EL2::F::Masked
+                    + SPSR_EL2::M::EL1h,
+            );
+            ELR_EL2.set(runtime_init as *const () as u64);
+            SP_EL1.set(__boot_core_stack_end_exclusive.get() as u64);
         }
 
         /// Zero the bss section before calling into main.
         /// In the future, this function should include any setup code that isn't
         /// architecture specific and required for a normal rust runtime.
         #[no_mangle]
-        pub unsafe fn runtime_init() -> ! {
+        pub unsafe extern "C" fn runtime_init(device_tree: usize) -> ! {
             memory::set_volatile(bss_range(), 0);
+            DEVICE_TREE.store(device_tree, Ordering::Relaxed);
+            exception::init();
             crate::main()
         }
 
+        /// The physical address of the flattened device tree the kernel was booted with, if any.
+        pub fn device_tree() -> Option<usize> {
+            match DEVICE_TREE.load(Ordering::Relaxed) {
+                0 => None,
+                address => Some(address),
+            }
+        }
+
+        /// The physical address that cores started through PSCI should begin executing at. The MMU
+        /// is off, so that's the address of [`_start_secondary`].
+        pub fn secondary_entry_point() -> usize {
+            _start_secondary as *const () as usize
+        }
+
+        /// How many of the cores started after the boot core are up.
+        pub fn secondary_cores_online() -> usize {
+            SECONDARY_CORES_ONLINE.load(Ordering::Acquire)
+        }
+
         extern "Rust" {
+            #[allow(non_upper_case_globals)]
+            static __boot_core_stack_end_exclusive: UnsafeCell<u64>;
+
             // these are named to match the linker script, and screaming snake case is usually
             // reserved for linker commands in those scripts
             #[allow(non_upper_case_globals)]
@@ -76,9 +189,23 @@ cfg_if::cfg_if! {
             bss_range
         }
     } else if #[cfg(any(target_arch = "x86", target_arch = "x86_64"))] {
+        use bootloader::BootInfo;
+        use core::sync::atomic::{AtomicU64, Ordering};
+
+        /// Where the bootloader mapped physical memory.
+        static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+
         #[no_mangle]
-        pub unsafe extern "C" fn _start() -> ! {
+        pub unsafe extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
+            PHYSICAL_MEMORY_OFFSET.store(boot_info.physical_memory_offset, Ordering::Relaxed);
+            crate::arch::exception::init();
             crate::main()
         }
+
+        /// The virtual address that the physical address 0 is mapped at. Everything above it is
+        /// mapped too, including the devices' registers.
+        pub fn physical_memory_offset() -> usize {
+            PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed) as usize
+        }
     }
 }
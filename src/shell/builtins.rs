@@ -0,0 +1,400 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! The commands every kernel has.
+//!
+//! `mem`, `peek` and `poke` take addresses as they are, which are physical addresses as long as
+//! memory is identity mapped. Reading or writing somewhere that isn't mapped takes the kernel down,
+//! and writing to a device register does whatever the device does, so use them with care.
+
+use crate::driver::registry::State;
+use crate::fmt::Hex;
+use crate::log::{self, LevelFilter};
+use crate::power::{self, PanicAction};
+use crate::shell::{Args, Command, Error, Output};
+use crate::shell_command;
+use crate::time::{DurationExt, SimpleTimer};
+use core::convert::TryFrom;
+use core::ptr;
+use core::time::Duration;
+use ufmt::{uWrite, uwrite, uwriteln};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Every builtin command, in the order `help` lists them.
+pub static BUILTINS: [&Command; 14] = [
+    &HELP, &UPTIME, &CLOCKS, &DRIVERS, &LOG, &MEM, &PEEK, &POKE, &REBOOT, &POWEROFF, &HALT,
+    &ON_PANIC, &WATCHDOG, &RANDOM,
+];
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+shell_command! {
+    /// List the commands
+    static HELP: "help" "" => help;
+}
+
+shell_command! {
+    /// Show how long the kernel has been running
+    static UPTIME: "uptime" "" => uptime;
+}
+
+shell_command! {
+    /// List the clock sources and what each reads, and the device used for timer events
+    static CLOCKS: "clocks" "" => clocks;
+}
+
+shell_command! {
+    /// List the drivers and their states
+    static DRIVERS: "drivers" "" => drivers;
+}
+
+shell_command! {
+    /// Show or change the log levels
+    static LOG: "log" "[level [module] <level> | clear <module>]" => log_levels;
+}
+
+shell_command! {
+    /// Dump memory as hex
+    static MEM: "mem" "<address> [length]" => mem;
+}
+
+shell_command! {
+    /// Read a register, 32 bits wide unless given a width in bits
+    static PEEK: "peek" "<address> [8|16|32|64]" => peek;
+}
+
+shell_command! {
+    /// Write a register, 32 bits wide unless given a width in bits
+    static POKE: "poke" "<address> <value> [8|16|32|64]" => poke;
+}
+
+shell_command! {
+    /// Restart the machine
+    static REBOOT: "reboot" "" => reboot;
+}
+
+shell_command! {
+    /// Turn the machine off
+    static POWEROFF: "poweroff" "" => poweroff;
+}
+
+shell_command! {
+    /// Shut down the drivers and stop the CPU
+    static HALT: "halt" "" => halt;
+}
+
+shell_command! {
+    /// Show or change what happens after a panic
+    static ON_PANIC: "on-panic" "[halt | poweroff | reboot <seconds>]" => on_panic;
+}
+
+shell_command! {
+    /// Show whether the watchdog is running, and whether it caused the last reset
+    static WATCHDOG: "watchdog" "" => watchdog;
+}
+
+shell_command! {
+    /// Print random 64-bit numbers
+    static RANDOM: "random" "[count]" => random;
+}
+
+/// How much `mem` dumps if it isn't told.
+const MEM_DEFAULT_LENGTH: u64 = 256;
+
+const BYTES_PER_LINE: usize = 16;
+
+/// The size of an access made by `peek` or `poke`.
+#[derive(Copy, Clone)]
+enum Width {
+    Byte,
+    Half,
+    Word,
+    Double,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn write_spaces(out: &mut Output, count: usize) {
+    for _ in 0..count {
+        let _ = out.write_char(' ');
+    }
+}
+
+/// Write a byte as exactly two hex digits, without a prefix.
+fn write_hex_byte(out: &mut Output, byte: u8) {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    let _ = out.write_char(DIGITS[usize::from(byte >> 4)] as char);
+    let _ = out.write_char(DIGITS[usize::from(byte & 0xf)] as char);
+}
+
+fn address(number: u64) -> Result<usize, Error> {
+    usize::try_from(number).map_err(|_| Error::BadNumber)
+}
+
+impl Width {
+    fn from_bits(bits: u64) -> Result<Self, Error> {
+        match bits {
+            8 => Ok(Width::Byte),
+            16 => Ok(Width::Half),
+            32 => Ok(Width::Word),
+            64 => Ok(Width::Double),
+            _ => Err(Error::Usage),
+        }
+    }
+
+    fn bytes(self) -> usize {
+        match self {
+            Width::Byte => 1,
+            Width::Half => 2,
+            Width::Word => 4,
+            Width::Double => 8,
+        }
+    }
+
+    /// Check that an access of this width at `address` is aligned, which device registers need.
+    fn check_aligned(self, address: usize) -> Result<(), Error> {
+        if address % self.bytes() != 0 {
+            return Err(Error::Failed("the address isn't aligned to the width"))
+        }
+        Ok(())
+    }
+
+    /// Check that `value` fits in this width.
+    fn check_fits(self, value: u64) -> Result<(), Error> {
+        if self.bytes() < 8 && value >> (8 * self.bytes()) != 0 {
+            return Err(Error::BadNumber)
+        }
+        Ok(())
+    }
+}
+
+fn help(args: &mut Args<'_>, out: &mut Output) -> Result<(), Error> {
+    args.finish()?;
+
+    let mut width = 0;
+    crate::shell::for_each_command(|command| {
+        width = width.max(command.name.len() + 1 + command.usage.len());
+    });
+    crate::shell::for_each_command(|command| {
+        let _ = uwrite!(out, "{} {}", command.name, command.usage);
+        write_spaces(out, width - command.name.len() - 1 - command.usage.len() + 2);
+        let _ = uwriteln!(out, "{}", command.help.trim());
+    });
+    Ok(())
+}
+
+fn uptime(args: &mut Args<'_>, out: &mut Output) -> Result<(), Error> {
+    args.finish()?;
+    let uptime = crate::time::uptime();
+    let _ = uwriteln!(out, "up {}", uptime.display_human());
+    Ok(())
+}
+
+fn clocks(args: &mut Args<'_>, out: &mut Output) -> Result<(), Error> {
+    args.finish()?;
+    crate::time::for_each_clock_source(|source, in_use| {
+        let reading = source.uptime();
+        let _ = uwrite!(out, "{}: rated {}, ", source.name(), source.rating());
+        let _ = uwrite!(out, "at {}", reading.display_timestamp());
+        let _ = uwriteln!(out, "{}", if in_use { " (in use)" } else { "" });
+    });
+    let _ = match crate::time::clock_event_name() {
+        Some(name) => uwriteln!(out, "timer events: {}", name),
+        None => uwriteln!(out, "timer events: none"),
+    };
+    Ok(())
+}
+
+fn drivers(args: &mut Args<'_>, out: &mut Output) -> Result<(), Error> {
+    args.finish()?;
+    for (compatible, state) in crate::DRIVERS.get().states() {
+        let _ = match state {
+            State::Registered => uwriteln!(out, "{}: registered", compatible),
+            State::Probed => uwriteln!(out, "{}: waiting for its dependencies", compatible),
+            State::Initialized => uwriteln!(out, "{}: initialized", compatible),
+            State::Failed(e) => uwriteln!(out, "{}: failed, {}", compatible, e),
+        };
+    }
+    Ok(())
+}
+
+fn log_levels(args: &mut Args<'_>, out: &mut Output) -> Result<(), Error> {
+    match args.next() {
+        None => {
+            let _ = uwriteln!(out, "level {}", log::max_level());
+            log::for_each_module_level(|module, level| {
+                let _ = uwriteln!(out, "level {} {}", module, level);
+            });
+        }
+        Some("level") => {
+            let first = args.required()?;
+            match args.next() {
+                None => {
+                    let level = LevelFilter::from_name(first).ok_or(Error::Usage)?;
+                    log::set_max_level(level);
+                }
+                Some(level) => {
+                    let level = LevelFilter::from_name(level).ok_or(Error::Usage)?;
+                    log::set_module_level(first, level)
+                        .map_err(|_| Error::Failed("can't add another module level"))?;
+                }
+            }
+            args.finish()?;
+        }
+        Some("clear") => {
+            let module = args.required()?;
+            args.finish()?;
+            log::clear_module_level(module);
+        }
+        Some(_) => return Err(Error::Usage),
+    }
+    Ok(())
+}
+
+fn mem(args: &mut Args<'_>, out: &mut Output) -> Result<(), Error> {
+    let start = address(args.number()?)?;
+    let length = address(args.number_or(MEM_DEFAULT_LENGTH)?)?;
+    args.finish()?;
+    let end = start.checked_add(length).ok_or(Error::BadNumber)?;
+
+    let mut line_start = start;
+    while line_start < end {
+        // a long dump can take longer than the watchdog's timeout
+        crate::watchdog::heartbeat();
+        let line_end = end.min(line_start.saturating_add(BYTES_PER_LINE));
+        let mut bytes = [0; BYTES_PER_LINE];
+        for (i, byte) in bytes[..line_end - line_start].iter_mut().enumerate() {
+            // SAFETY: none, we read wherever we're told to
+            *byte = unsafe { ptr::read_volatile((line_start + i) as *const u8) };
+        }
+        let bytes = &bytes[..line_end - line_start];
+
+        let _ = uwrite!(out, "{}:", Hex(line_start));
+        for &byte in bytes {
+            let _ = out.write_char(' ');
+            write_hex_byte(out, byte);
+        }
+        write_spaces(out, 3 * (BYTES_PER_LINE - bytes.len()) + 2);
+        for &byte in bytes {
+            let printable = byte.is_ascii_graphic() || byte == b' ';
+            let _ = out.write_char(if printable { byte as char } else { '.' });
+        }
+        let _ = out.write_char('\n');
+
+        line_start = line_end;
+    }
+    Ok(())
+}
+
+fn peek(args: &mut Args<'_>, out: &mut Output) -> Result<(), Error> {
+    let address = address(args.number()?)?;
+    let width = Width::from_bits(args.number_or(32)?)?;
+    args.finish()?;
+    width.check_aligned(address)?;
+
+    // SAFETY: none, we read wherever we're told to
+    let _ = unsafe {
+        match width {
+            Width::Byte => uwriteln!(out, "{}", Hex(ptr::read_volatile(address as *const u8))),
+            Width::Half => uwriteln!(out, "{}", Hex(ptr::read_volatile(address as *const u16))),
+            Width::Word => uwriteln!(out, "{}", Hex(ptr::read_volatile(address as *const u32))),
+            Width::Double => uwriteln!(out, "{}", Hex(ptr::read_volatile(address as *const u64))),
+        }
+    };
+    Ok(())
+}
+
+fn poke(args: &mut Args<'_>, _out: &mut Output) -> Result<(), Error> {
+    let address = address(args.number()?)?;
+    let value = args.number()?;
+    let width = Width::from_bits(args.number_or(32)?)?;
+    args.finish()?;
+    width.check_aligned(address)?;
+    width.check_fits(value)?;
+
+    // SAFETY: none, we write wherever we're told to
+    unsafe {
+        match width {
+            Width::Byte => ptr::write_volatile(address as *mut u8, value as u8),
+            Width::Half => ptr::write_volatile(address as *mut u16, value as u16),
+            Width::Word => ptr::write_volatile(address as *mut u32, value as u32),
+            Width::Double => ptr::write_volatile(address as *mut u64, value),
+        }
+    }
+    Ok(())
+}
+
+fn reboot(args: &mut Args<'_>, out: &mut Output) -> Result<(), Error> {
+    args.finish()?;
+    let _ = uwriteln!(out, "Rebooting");
+    power::reboot()
+}
+
+fn poweroff(args: &mut Args<'_>, out: &mut Output) -> Result<(), Error> {
+    args.finish()?;
+    let _ = uwriteln!(out, "Powering off");
+    power::poweroff()
+}
+
+fn halt(args: &mut Args<'_>, out: &mut Output) -> Result<(), Error> {
+    args.finish()?;
+    let _ = uwriteln!(out, "Halting");
+    crate::DRIVERS.get().shutdown_all();
+    crate::arch::irq::disable();
+    crate::arch::asm::wait_forever()
+}
+
+fn on_panic(args: &mut Args<'_>, out: &mut Output) -> Result<(), Error> {
+    let action = match args.next() {
+        None => {
+            let _ = match power::panic_action() {
+                PanicAction::Halt => uwriteln!(out, "halt"),
+                PanicAction::PowerOff => uwriteln!(out, "poweroff"),
+                PanicAction::Reboot(delay) => uwriteln!(out, "reboot {}", delay.as_secs()),
+            };
+            return Ok(())
+        }
+        Some("halt") => PanicAction::Halt,
+        Some("poweroff") => PanicAction::PowerOff,
+        Some("reboot") => PanicAction::Reboot(Duration::from_secs(args.number()?)),
+        Some(_) => return Err(Error::Usage),
+    };
+    args.finish()?;
+    power::set_panic_action(action);
+    Ok(())
+}
+
+fn watchdog(args: &mut Args<'_>, out: &mut Output) -> Result<(), Error> {
+    args.finish()?;
+    let watchdog = crate::bsp::watchdog(crate::DRIVERS.get())
+        .ok_or(Error::Failed("there's no watchdog"))?;
+    let (timeout, caused_last_reset) =
+        watchdog.with_lock(|watchdog| (watchdog.timeout(), watchdog.caused_last_reset()));
+
+    let _ = match timeout {
+        Some(timeout) => uwriteln!(out, "running, timeout {}", timeout.display_human()),
+        None => uwriteln!(out, "stopped"),
+    };
+    if caused_last_reset {
+        let _ = uwriteln!(out, "the last reset was caused by the watchdog");
+    }
+    Ok(())
+}
+
+fn random(args: &mut Args<'_>, out: &mut Output) -> Result<(), Error> {
+    let count = args.number_or(1)?;
+    args.finish()?;
+    for _ in 0..count {
+        crate::watchdog::heartbeat();
+        let _ = uwriteln!(out, "{}", Hex(crate::rand::next_u64()));
+    }
+    Ok(())
+}
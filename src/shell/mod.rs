@@ -0,0 +1,296 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! A command shell over the console, for poking at the kernel while it runs.
+//!
+//! [run] reads lines from [crate::stdin] and runs the command named by the first word, passing it
+//! the rest of the words as [Args]. The [builtins] cover the kernel in general. Subsystems can add
+//! their own commands by defining them with [`shell_command!`](crate::shell_command) and passing
+//! them to [register]:
+//!
+//! ```ignore
+//! shell_command! {
+//!     /// List the pins that are claimed
+//!     static PINS: "pins" "" => pins;
+//! }
+//!
+//! fn pins(args: &mut Args<'_>, out: &mut Output) -> Result<(), shell::Error> {
+//!     args.finish()?;
+//!     // ...
+//!     Ok(())
+//! }
+//!
+//! shell::register(&PINS)?;
+//! ```
+
+pub mod builtins;
+
+use crate::driver::{self, WriteError};
+use crate::stdin::ReadError;
+use crate::sync::SpinMutex;
+use core::str::SplitWhitespace;
+use ufmt::{uWrite, uwriteln};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A command the shell can run. Define them with [`shell_command!`](crate::shell_command).
+pub struct Command {
+    /// The word that runs the command
+    pub name: &'static str,
+    /// The arguments the command takes, e.g. `<address> [length]`
+    pub usage: &'static str,
+    /// A line about what the command does, for `help`
+    pub help: &'static str,
+    pub run: fn(&mut Args<'_>, &mut Output) -> Result<(), Error>,
+}
+
+/// The words that came after the command's name.
+pub struct Args<'a> {
+    words: SplitWhitespace<'a>,
+}
+
+/// Writes to [crate::stdout], taking the lock for each write, so commands can log as they go.
+///
+/// Anything written while there's no console is dropped.
+pub struct Output {
+    _private: (),
+}
+
+/// Why a command failed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The arguments don't make sense, so the shell prints the command's usage.
+    Usage,
+    /// An argument that should be a number isn't one, or is out of range.
+    BadNumber,
+    /// The command failed, for the given reason.
+    Failed(&'static str),
+    /// A driver the command used failed.
+    Driver(driver::Error),
+}
+
+/// Reasons a command couldn't be registered.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RegisterError {
+    /// All of the command slots are in use.
+    TableFull,
+    /// There already is a command with that name.
+    NameTaken,
+}
+
+/// How many commands can be registered, on top of the builtins.
+pub const MAX_COMMANDS: usize = 16;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The longest command line that can be run.
+const LINE_MAX: usize = 128;
+
+/// Printed before each line that's read.
+const PROMPT: &str = "> ";
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static COMMANDS: SpinMutex<[Option<&'static Command>; MAX_COMMANDS]> =
+    SpinMutex::new_spin([None; MAX_COMMANDS]);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Run a line that was typed, printing what went wrong if anything did.
+fn execute(line: &str, out: &mut Output) {
+    let mut args = Args { words: line.split_whitespace() };
+    let name = match args.next() {
+        Some(name) => name,
+        None => return,
+    };
+    let command = match find(name) {
+        Some(command) => command,
+        None => {
+            let _ = uwriteln!(out, "{}: command not found, try 'help'", name);
+            return
+        }
+    };
+
+    let _ = match (command.run)(&mut args, out) {
+        Ok(()) => Ok(()),
+        Err(Error::Usage) => uwriteln!(out, "usage: {} {}", command.name, command.usage),
+        Err(e) => uwriteln!(out, "{}: {}", command.name, e),
+    };
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Define a [Command] in a `static`, ready to be passed to [register].
+///
+/// The doc comment is the command's help, and is limited to a single line. The function is called
+/// with the command's arguments and somewhere to write its output.
+///
+/// ```ignore
+/// shell_command! {
+///     /// Dump memory as hex
+///     pub static MEM: "mem" "<address> [length]" => mem;
+/// }
+/// ```
+#[macro_export]
+macro_rules! shell_command {
+    (
+        #[doc = $help:literal]
+        $vis:vis static $static:ident: $name:literal $usage:literal => $run:expr;
+    ) => {
+        $vis static $static: $crate::shell::Command = $crate::shell::Command {
+            name: $name,
+            usage: $usage,
+            help: $help,
+            run: $run,
+        };
+    };
+}
+
+/// Make `command` available in the shell.
+pub fn register(command: &'static Command) -> Result<(), RegisterError> {
+    // the name is checked with the lock held, so two commands with the same name can't both get in
+    COMMANDS.with_lock(|commands| {
+        let taken = builtins::BUILTINS.iter().chain(commands.iter().flatten())
+            .any(|existing| existing.name == command.name);
+        if taken {
+            return Err(RegisterError::NameTaken)
+        }
+        let slot = commands.iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(RegisterError::TableFull)?;
+        *slot = Some(command);
+        Ok(())
+    })
+}
+
+/// The command called `name`.
+pub fn find(name: &str) -> Option<&'static Command> {
+    builtins::BUILTINS.iter().copied()
+        .chain(COMMANDS.with_lock(|commands| *commands).iter().flatten().copied())
+        .find(|command| command.name == name)
+}
+
+/// Call `f` with every command, the builtins first.
+pub fn for_each_command<F: FnMut(&'static Command)>(mut f: F) {
+    builtins::BUILTINS.iter().copied().for_each(&mut f);
+    // commands can be registered while this runs, since it's called from a command
+    let commands = COMMANDS.with_lock(|commands| *commands);
+    commands.iter().flatten().copied().for_each(f);
+}
+
+/// Read and run commands for as long as there's input.
+///
+/// Returns [ReadError::NoDevice] if there are no input devices to read commands from.
+pub fn run() -> ReadError {
+    let stdin = crate::stdin();
+    let mut out = Output::new();
+    let mut line = [0; LINE_MAX];
+    loop {
+        let _ = out.write_str(PROMPT);
+        match stdin.read_line(&mut line) {
+            Ok(line) => execute(line, &mut out),
+            Err(ReadError::Interrupted) => {}
+            Err(e) => return e,
+        }
+    }
+}
+
+impl<'a> Args<'a> {
+    /// The next argument, if there is one.
+    #[allow(clippy::should_implement_trait)] // Args aren't meant to be used as an iterator
+    pub fn next(&mut self) -> Option<&'a str> {
+        self.words.next()
+    }
+
+    /// The next argument, which the command can't do without.
+    pub fn required(&mut self) -> Result<&'a str, Error> {
+        self.next().ok_or(Error::Usage)
+    }
+
+    /// The next argument as a number, either decimal or hexadecimal with a `0x` prefix.
+    pub fn number(&mut self) -> Result<u64, Error> {
+        parse_number(self.required()?)
+    }
+
+    /// The next argument as a number, or `default` if there are no more.
+    pub fn number_or(&mut self, default: u64) -> Result<u64, Error> {
+        self.next().map_or(Ok(default), parse_number)
+    }
+
+    /// Check that all of the arguments were used.
+    pub fn finish(&mut self) -> Result<(), Error> {
+        match self.next() {
+            Some(_) => Err(Error::Usage),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Parse a number, either decimal or hexadecimal with a `0x` prefix.
+pub fn parse_number(s: &str) -> Result<u64, Error> {
+    let result = match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    result.map_err(|_| Error::BadNumber)
+}
+
+impl Output {
+    pub const fn new() -> Self {
+        Self { _private: () }
+    }
+}
+
+impl ufmt::uWrite for Output {
+    type Error = WriteError;
+
+    fn write_str(&mut self, s: &str) -> Result<(), Self::Error> {
+        match crate::stdout() {
+            Some(stdout) => stdout.with_lock(|w| w.write_str(s)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl From<driver::Error> for Error {
+    fn from(e: driver::Error) -> Self {
+        Error::Driver(e)
+    }
+}
+
+impl ufmt::uDisplay for RegisterError {
+    fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: ufmt::uWrite + ?Sized
+    {
+        match self {
+            RegisterError::TableFull => f.write_str("there's no room for more commands"),
+            RegisterError::NameTaken => f.write_str("there already is a command with that name"),
+        }
+    }
+}
+
+impl ufmt::uDisplay for Error {
+    fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: ufmt::uWrite + ?Sized
+    {
+        match self {
+            Error::Usage => f.write_str("invalid arguments"),
+            Error::BadNumber => f.write_str("invalid number"),
+            Error::Failed(reason) => f.write_str(reason),
+            Error::Driver(e) => ufmt::uDisplay::fmt(e, f),
+        }
+    }
+}
@@ -0,0 +1,323 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Reading what the user types.
+//!
+//! Input comes from whichever devices the BSP hands out as its input devices, e.g. the UART on the
+//! Pi or the keyboard and the serial port on a PC. They're polled in turn, so all of them can be
+//! typed into at the same time.
+//!
+//! Between the devices and the reader sits a line discipline, like a terminal's. In
+//! [Mode::Line], which is the default, input is collected a line at a time and echoed to
+//! [crate::stdout] as it's typed. Nothing can be read until Enter is pressed, and until then the
+//! line can be edited:
+//!
+//! - Backspace (or Delete, which is what most terminals send for it) erases the last character
+//! - Ctrl-U erases the whole line
+//! - Ctrl-C throws the line away and interrupts the read with [ReadError::Interrupted]
+//!
+//! Other control characters and escape sequences, like the ones the arrow keys send, are dropped.
+//!
+//! In [Mode::Raw], characters are handed to the reader as soon as they arrive, exactly as they
+//! were received and without echo.
+
+use crate::sync::SpinMutex;
+use core::str;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// How input is processed before it's read.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// Collect and edit a line at a time, echoing what's typed
+    Line,
+    /// Pass every character through as soon as it arrives
+    Raw,
+}
+
+/// Why nothing could be read.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReadError {
+    /// The user pressed Ctrl-C.
+    Interrupted,
+    /// There are no input devices, so nothing will ever be typed.
+    NoDevice,
+}
+
+/// Handle to the kernel's input, as returned by [crate::stdin].
+///
+/// The line discipline is shared, so every handle sees the same settings and the same input.
+pub struct Stdin {
+    _private: (),
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The longest line that can be typed. Anything past it is dropped.
+const LINE_MAX: usize = 256;
+
+const CONTROL_C: char = '\x03';
+const BACKSPACE: char = '\x08';
+const CONTROL_U: char = '\x15';
+const ESCAPE: char = '\x1b';
+const DELETE: char = '\x7f';
+
+/// Where we are in an escape sequence that's being dropped.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Escape {
+    None,
+    /// Just after the escape character
+    Start,
+    /// Inside of a CSI (`ESC [`) or SS3 (`ESC O`) sequence, which ends with a character from `@`
+    /// to `~`
+    Sequence,
+}
+
+struct LineDiscipline {
+    mode: Mode,
+    echo: bool,
+    /// The line being typed, as UTF-8
+    line: [u8; LINE_MAX],
+    len: usize,
+    /// Whether Enter was pressed, so the line can be read
+    complete: bool,
+    /// How much of a complete line was already read, one character at a time
+    read: usize,
+    escape: Escape,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static DISCIPLINE: SpinMutex<LineDiscipline> = SpinMutex::new_spin(LineDiscipline::new());
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// The next character from any of the input devices, without waiting.
+fn poll() -> Result<Option<char>, ReadError> {
+    let mut found = false;
+    for device in crate::bsp::stdin(crate::DRIVERS.get()) {
+        found = true;
+        if let Some(c) = device.with_lock(|device| device.read_char()) {
+            return Ok(Some(c))
+        }
+    }
+
+    if !found {
+        return Err(ReadError::NoDevice)
+    }
+    Ok(None)
+}
+
+fn echo(s: &str) {
+    if let Some(stdout) = crate::stdout() {
+        stdout.with_lock(|w| {
+            let _ = w.write_str(s);
+        });
+    }
+}
+
+impl LineDiscipline {
+    const fn new() -> Self {
+        Self {
+            mode: Mode::Line,
+            echo: true,
+            line: [0; LINE_MAX],
+            len: 0,
+            complete: false,
+            read: 0,
+            escape: Escape::None,
+        }
+    }
+
+    /// The line typed so far.
+    fn line(&self) -> &str {
+        // SAFETY: only whole characters are ever added to or removed from the line
+        unsafe { str::from_utf8_unchecked(&self.line[..self.len]) }
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+        self.read = 0;
+        self.complete = false;
+    }
+
+    fn echo(&self, s: &str) {
+        if self.echo {
+            echo(s);
+        }
+    }
+
+    /// Remove the last character of the line, and from the screen.
+    fn erase(&mut self) {
+        if let Some(c) = self.line().chars().next_back() {
+            self.len -= c.len_utf8();
+            self.echo("\x08 \x08");
+        }
+    }
+
+    /// Apply a character that was typed to the line.
+    fn edit(&mut self, c: char) -> Result<(), ReadError> {
+        match (self.escape, c) {
+            (Escape::Start, '[') | (Escape::Start, 'O') => self.escape = Escape::Sequence,
+            (Escape::Start, _) => self.escape = Escape::None,
+            (Escape::Sequence, '@'..='~') => self.escape = Escape::None,
+            (Escape::Sequence, _) => {}
+            (Escape::None, ESCAPE) => self.escape = Escape::Start,
+            (Escape::None, '\n') => {
+                self.complete = true;
+                self.echo("\n");
+            }
+            (Escape::None, BACKSPACE) | (Escape::None, DELETE) => self.erase(),
+            (Escape::None, CONTROL_U) => {
+                while self.len > 0 {
+                    self.erase();
+                }
+            }
+            (Escape::None, CONTROL_C) => {
+                self.clear();
+                self.echo("^C\n");
+                return Err(ReadError::Interrupted)
+            }
+            (Escape::None, c) if c.is_control() && c != '\t' => {}
+            (Escape::None, c) => {
+                let start = self.len;
+                let end = start + c.len_utf8();
+                if end <= LINE_MAX {
+                    c.encode_utf8(&mut self.line[start..end]);
+                    self.len = end;
+                    self.echo(&self.line()[start..]);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Read whatever's waiting from the devices into the line, until the line is complete.
+    ///
+    /// Returns whether it is.
+    fn fill(&mut self) -> Result<bool, ReadError> {
+        while !self.complete {
+            match poll()? {
+                Some(c) => self.edit(c)?,
+                None => return Ok(false),
+            }
+        }
+        Ok(true)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Stdin {
+    pub(crate) const fn new() -> Self {
+        Self { _private: () }
+    }
+
+    pub fn mode(&self) -> Mode {
+        DISCIPLINE.with_lock(|discipline| discipline.mode)
+    }
+
+    /// Switch between line and raw input. Whatever's been typed of the current line is dropped.
+    pub fn set_mode(&self, mode: Mode) {
+        DISCIPLINE.with_lock(|discipline| {
+            discipline.mode = mode;
+            discipline.clear();
+            discipline.escape = Escape::None;
+        })
+    }
+
+    /// Turn the echo of what's typed in line mode on or off, e.g. while a password is entered.
+    pub fn set_echo(&self, echo: bool) {
+        DISCIPLINE.with_lock(|discipline| discipline.echo = echo)
+    }
+
+    /// The next character, without waiting.
+    ///
+    /// In line mode, characters only become available once a whole line was typed, ending with
+    /// `\n`. Returns `Ok(None)` if there's nothing to read yet.
+    pub fn try_read_char(&self) -> Result<Option<char>, ReadError> {
+        DISCIPLINE.with_lock(|discipline| {
+            if discipline.mode == Mode::Raw {
+                return poll()
+            }
+            if !discipline.fill()? {
+                return Ok(None)
+            }
+
+            let c = discipline.line()[discipline.read..].chars().next().unwrap_or('\n');
+            discipline.read += c.len_utf8();
+            if discipline.read > discipline.len {
+                discipline.clear();
+            }
+            Ok(Some(c))
+        })
+    }
+
+    /// Wait for the next character.
+    pub fn read_char(&self) -> Result<char, ReadError> {
+        loop {
+            if let Some(c) = self.try_read_char()? {
+                return Ok(c)
+            }
+            crate::watchdog::heartbeat();
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Wait for a whole line to be typed and copy it into `buffer`, without the `\n`.
+    ///
+    /// This goes through the line discipline even in raw mode. If the line doesn't fit, it's cut
+    /// off at the last whole character that does.
+    pub fn read_line<'b>(&self, buffer: &'b mut [u8]) -> Result<&'b str, ReadError> {
+        let len = loop {
+            let len = DISCIPLINE.with_lock(|discipline| -> Result<_, ReadError> {
+                if !discipline.fill()? {
+                    return Ok(None)
+                }
+
+                // a line that was partly read a character at a time is returned in full
+                let line = discipline.line();
+                let mut len = line.len().min(buffer.len());
+                while !line.is_char_boundary(len) {
+                    len -= 1;
+                }
+                buffer[..len].copy_from_slice(&line.as_bytes()[..len]);
+                discipline.clear();
+                Ok(Some(len))
+            })?;
+            match len {
+                Some(len) => break len,
+                None => {
+                    crate::watchdog::heartbeat();
+                    core::hint::spin_loop()
+                }
+            }
+        };
+
+        // SAFETY: this was cut from a str at a character boundary
+        Ok(unsafe { str::from_utf8_unchecked(&buffer[..len]) })
+    }
+}
+
+impl ufmt::uDisplay for ReadError {
+    fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: ufmt::uWrite + ?Sized
+    {
+        match self {
+            ReadError::Interrupted => f.write_str("interrupted"),
+            ReadError::NoDevice => f.write_str("there's no input device"),
+        }
+    }
+}
@@ -14,6 +14,7 @@
 
 use core::cell::UnsafeCell;
 use core::mem::MaybeUninit;
+use crate::arch::irq;
 use crate::defer::defer;
 
 pub trait RawMutex {
@@ -42,6 +43,9 @@ pub trait RawMutex {
 pub type SpinMutex<T> = Mutex<Spin, T>;
 pub type SpinMutexMut<'a, T> = MutexMut<'a, Spin, T>;
 
+pub type IrqSafeSpinMutex<T> = Mutex<IrqSafeSpin, T>;
+pub type IrqSafeSpinMutexMut<'a, T> = MutexMut<'a, IrqSafeSpin, T>;
+
 pub struct Spin {
     locked: UnsafeCell<bool>,
 }
@@ -91,6 +95,73 @@ impl RawMutex for Spin {
     }
 }
 
+/// Spin lock that also masks interrupts on the current core while it's held.
+///
+/// Use this for data that's shared with interrupt handlers. A plain [Spin] lock would deadlock if
+/// an interrupt handler tried to take it while the interrupted code was holding it.
+pub struct IrqSafeSpin {
+    lock: Spin,
+    /// Whether interrupts were enabled before the lock was acquired
+    irqs_were_enabled: UnsafeCell<bool>,
+}
+
+unsafe impl Send for IrqSafeSpin {}
+unsafe impl Sync for IrqSafeSpin {}
+
+impl IrqSafeSpin {
+    const fn new() -> Self {
+        Self {
+            lock: Spin::new(),
+            irqs_were_enabled: UnsafeCell::new(false),
+        }
+    }
+}
+
+impl Default for IrqSafeSpin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RawMutex for IrqSafeSpin {
+    fn is_locked(&self) -> bool {
+        self.lock.is_locked()
+    }
+
+    fn try_lock(&self) -> bool {
+        let irqs_were_enabled = irq::are_enabled();
+        irq::disable();
+        if self.lock.try_lock() {
+            unsafe { self.irqs_were_enabled.get().write_volatile(irqs_were_enabled) }
+            true
+        } else {
+            if irqs_were_enabled {
+                // SAFETY: we're only restoring the state from before the call
+                unsafe { irq::enable() }
+            }
+            false
+        }
+    }
+
+    fn lock(&self) {
+        let irqs_were_enabled = irq::are_enabled();
+        irq::disable();
+        self.lock.lock();
+        unsafe { self.irqs_were_enabled.get().write_volatile(irqs_were_enabled) }
+    }
+
+    unsafe fn unlock(&self) {
+        if !self.lock.is_locked() {
+            return
+        }
+        let irqs_were_enabled = *self.irqs_were_enabled.get();
+        self.lock.unlock();
+        if irqs_were_enabled {
+            irq::enable()
+        }
+    }
+}
+
 pub struct Mutex<R, T>
 where
     R: RawMutex,
@@ -112,6 +183,26 @@ where
     }
 }
 
+impl<T> Mutex<Spin, T> {
+    /// Create a new spin mutex in a const context, e.g. for use in a `static`.
+    pub const fn new_spin(data: T) -> Self {
+        Self {
+            mutex: Spin::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
+impl<T> Mutex<IrqSafeSpin, T> {
+    /// Create a new IRQ-safe spin mutex in a const context, e.g. for use in a `static`.
+    pub const fn new_irq_safe(data: T) -> Self {
+        Self {
+            mutex: IrqSafeSpin::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
 impl<R, T> Mutex<R, T>
 where
     R: RawMutex,
@@ -143,6 +234,24 @@ where
         })
     }
 
+    /// Run the critical section only if the lock is immediately available.
+    ///
+    /// Returns `None` without blocking if the lock is already held.
+    pub fn try_with_lock<F, V>(&self, critical_section: F) -> Option<V>
+    where
+        F: FnOnce(&mut T) -> V
+    {
+        if !self.mutex.try_lock() {
+            return None
+        }
+        // SAFETY: safe because we just acquired this lock, so we're responsible for releasing it
+        let _d = defer(|| unsafe { self.mutex.unlock() });
+
+        Some(critical_section(unsafe {
+            &mut *self.data.get()
+        }))
+    }
+
     pub fn borrow<T2>(&self) -> MutexMut<'_, R, T2>
     where
         T: AsMut<T2>,
@@ -182,6 +291,26 @@ impl<R: RawMutex, T: ?Sized> MutexMut<'_, R, T> {
             &mut *self.data
         })
     }
+
+    /// Run the critical section only if the lock is immediately available.
+    ///
+    /// Returns `None` without blocking if the lock is already held.
+    pub fn try_with_lock<F, V>(&self, critical_section: F) -> Option<V>
+    where
+        F: FnOnce(&mut T) -> V,
+    {
+        if !self.mutex.try_lock() {
+            return None
+        }
+        let _d = defer({
+            let mutex = &self.mutex; // only capture the mutex field
+            move || unsafe { mutex.unlock() }
+        });
+
+        Some(critical_section(unsafe {
+            &mut *self.data
+        }))
+    }
 }
 
 const ONCE_CELL_FILLED: u32 = 2;
@@ -237,6 +366,12 @@ impl<T> OnceCell<T> {
         match self.get() {
             Some(data) => data,
             None => {
+                // the initializer tried to read the cell it's initializing
+                assert!(
+                    ONCE_CELL_FILLING != unsafe { *self.filled.get() },
+                    "OnceCell initialized recursively",
+                );
+
                 unsafe {
                     self.filled.get().write_volatile(ONCE_CELL_FILLING);
 
@@ -308,6 +443,11 @@ where
         self.get();
     }
 
+    /// Get the value if it has already been initialized, without forcing the initialization.
+    pub fn try_get(&self) -> Option<&T> {
+        self.once.get()
+    }
+
     pub fn get(&self) -> &T {
         self.once.get_or_init(move || {
             // SAFETY: Lazy always starts with the init field being initialized, and this closure
@@ -3,10 +3,18 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
 //! Timer primitives.
+//!
+//! Besides the architecture's timer, boards can have other clocks. They come in two kinds: clock
+//! sources, which are counters that tell the time, and clock event devices, which raise an
+//! interrupt at a given time. Once the drivers are up, [select] picks the best-rated of each, which
+//! are then used by [uptime] and [set_timer].
 
 //--------------------------------------------------------------------------------------------------
 // Architectural Public Reexports
 //--------------------------------------------------------------------------------------------------
+use crate::driver::{self, registry::Registry};
+use crate::info;
+use crate::sync::{OnceCell, SpinMutexMut};
 use core::time::Duration;
 pub use crate::arch::time::simple_timer as arch_timer;
 
@@ -43,15 +51,181 @@ pub trait SimpleTimer {
     fn spin_for(&self, duration: Duration);
 }
 
+/// A counter that can be picked as the kernel's clock.
+///
+/// Its [SimpleTimer::uptime] has to be monotonic, and is read without a lock, so it has to be safe
+/// to call from anywhere, including interrupt handlers.
+pub trait ClockSource: SimpleTimer + Sync {
+    /// What the clock is called in the log.
+    fn name(&self) -> &'static str;
+
+    /// How good the clock is. The scale is the same as Linux's: 100-199 is usable, 200-299 is
+    /// good, 300-399 is desirable and 400-499 is ideal.
+    fn rating(&self) -> u32;
+}
+
+/// Function that's called from an interrupt handler when a timer goes off.
+pub type EventHandler = fn();
+
+/// Object-safe interface to a device that raises an interrupt at a given time.
+pub trait ClockEvent: Send {
+    /// What the device is called in the log.
+    fn name(&self) -> &'static str;
+
+    /// How good the device is, on the same scale as [ClockSource::rating].
+    fn rating(&self) -> u32;
+
+    /// The longest delay the device can be programmed with.
+    fn max_delay(&self) -> Duration;
+
+    /// Call `handler` from the interrupt handler once `delay` has passed. This replaces the timer
+    /// that was set before, if there was one.
+    ///
+    /// Returns [driver::Error::BadConfiguration] if the delay is longer than
+    /// [ClockEvent::max_delay].
+    fn program(&mut self, delay: Duration, handler: EventHandler) -> Result<(), driver::Error>;
+
+    /// Stop the timer, if one is set.
+    fn cancel(&mut self);
+}
+
 /// Extension methods for [`core::time::Duration`]
 pub trait DurationExt {
     fn display_human(&self) -> DisplayDuration<'_>;
+
+    /// Display the duration as seconds with microsecond precision, e.g. `12.000345`.
+    fn display_timestamp(&self) -> DisplayTimestamp<'_>;
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static CLOCK_SOURCE: OnceCell<&'static dyn ClockSource> = OnceCell::new();
+static CLOCK_EVENT: OnceCell<SpinMutexMut<'static, dyn ClockEvent>> = OnceCell::new();
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Every clock source there is, the architecture's first.
+fn clock_sources(drivers: &'static Registry) -> impl Iterator<Item = &'static dyn ClockSource> {
+    core::iter::once(crate::arch::time::clock_source()).chain(crate::bsp::clock_sources(drivers))
+}
+
+/// Whether `a` and `b` are the same clock source.
+fn same_clock(a: &'static dyn ClockSource, b: &'static dyn ClockSource) -> bool {
+    // only compare the data pointers, since the same type can have several vtables
+    let a = a as *const dyn ClockSource as *const ();
+    let b = b as *const dyn ClockSource as *const ();
+    a == b
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Pick the best-rated clock source and clock event device, and log what was picked.
+///
+/// Called once the drivers are initialized. Only the first call has any effect.
+pub fn select(drivers: &'static Registry) {
+    if CLOCK_SOURCE.is_initialized() {
+        return
+    }
+
+    // the first of the best is kept, which prefers the architecture's timer on a tie
+    let source = clock_sources(drivers)
+        .reduce(|best, source| if source.rating() > best.rating() { source } else { best })
+        .unwrap_or_else(crate::arch::time::clock_source);
+    info!("Using the {} as the clock source, rated {}", source.name(), source.rating());
+    CLOCK_SOURCE.get_or_init(|| source);
+
+    let rating = |event: &SpinMutexMut<'static, dyn ClockEvent>| event.with_lock(|e| e.rating());
+    let event = crate::bsp::clock_events(drivers)
+        .reduce(|best, event| if rating(&event) > rating(&best) { event } else { best });
+    match event {
+        Some(event) => {
+            let (name, rating) = event.with_lock(|event| (event.name(), event.rating()));
+            info!("Using the {} for timer events, rated {}", name, rating);
+            CLOCK_EVENT.get_or_init(|| event);
+        }
+        None => info!("There's no device for timer events"),
+    }
+}
+
+/// The clock source that was picked by [select], or the architecture's timer until then.
+pub fn clock() -> &'static dyn ClockSource {
+    CLOCK_SOURCE.get().copied().unwrap_or_else(crate::arch::time::clock_source)
+}
+
+/// The time since the clock started, which is around when the board was powered on.
+pub fn uptime() -> Duration {
+    clock().uptime()
+}
+
+/// Call `f` with every clock source, and whether it's the one in use.
+pub fn for_each_clock_source<F: FnMut(&'static dyn ClockSource, bool)>(mut f: F) {
+    let current = clock();
+    let drivers = match crate::DRIVERS.try_get() {
+        Some(drivers) => drivers,
+        None => return f(current, true),
+    };
+    for source in clock_sources(drivers) {
+        f(source, same_clock(source, current));
+    }
+}
+
+/// The name of the device that was picked for timer events, if there is one.
+pub fn clock_event_name() -> Option<&'static str> {
+    CLOCK_EVENT.get().map(|event| event.with_lock(|event| event.name()))
+}
+
+/// Call `handler` from an interrupt handler once `delay` has passed, using the device that was
+/// picked by [select]. This replaces the timer that was set before, if there was one.
+///
+/// Returns [driver::Error::NotPresent] if there's no device for timer events.
+pub fn set_timer(delay: Duration, handler: EventHandler) -> Result<(), driver::Error> {
+    let event = CLOCK_EVENT.get().ok_or(driver::Error::NotPresent)?;
+    event.with_lock(|event| event.program(delay, handler))
+}
+
+/// Stop the timer that was set with [set_timer], if there is one.
+pub fn cancel_timer() {
+    if let Some(event) = CLOCK_EVENT.get() {
+        event.with_lock(|event| event.cancel())
+    }
 }
 
 impl DurationExt for Duration {
     fn display_human(&self) -> DisplayDuration<'_> {
         DisplayDuration(self)
     }
+
+    fn display_timestamp(&self) -> DisplayTimestamp<'_> {
+        DisplayTimestamp(self)
+    }
+}
+
+pub struct DisplayTimestamp<'d>(&'d Duration);
+
+impl ufmt::uDisplay for DisplayTimestamp<'_> {
+    fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: ufmt::uWrite + ?Sized
+    {
+        use ufmt::uwrite;
+
+        // ufmt doesn't support padding, so zero-fill the fractional part by hand
+        let mut micros = self.0.subsec_micros();
+        let mut digits = [b'0'; 6];
+        for digit in digits.iter_mut().rev() {
+            *digit = b'0' + (micros % 10) as u8;
+            micros /= 10;
+        }
+        let digits = core::str::from_utf8(&digits).unwrap_or("??????");
+
+        uwrite!(f, "{}.{}", self.0.as_secs(), digits)
+    }
 }
 
 pub struct DisplayDuration<'d>(&'d Duration);
@@ -0,0 +1,41 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Architecture-independent watchdog handling.
+//!
+//! Boards with a watchdog arm it as its driver is initialized. From then on, the kernel has to
+//! call [heartbeat] more often than the watchdog's timeout, or the board is reset. The places
+//! that wait, like [crate::stdin] while it waits for input, do so as they spin.
+
+use core::time::Duration;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Object-safe interface to a watchdog.
+pub trait Watchdog {
+    /// Restart the countdown, so the watchdog doesn't reset the machine yet.
+    fn pet(&mut self);
+
+    /// How long the watchdog waits to be petted before it resets the machine, or `None` if it
+    /// isn't running.
+    fn timeout(&self) -> Option<Duration>;
+
+    /// Whether the watchdog reset the machine before this boot.
+    fn caused_last_reset(&self) -> bool;
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Let the watchdog know that the kernel is still running.
+///
+/// Does nothing if the board has no watchdog, or its driver isn't initialized yet.
+pub fn heartbeat() {
+    if let Some(watchdog) = crate::DRIVERS.try_get().and_then(crate::bsp::watchdog) {
+        watchdog.with_lock(|watchdog| watchdog.pet())
+    }
+}